@@ -24,6 +24,8 @@ pub struct RuntimeInfo {
     pub needs_mhe: bool,
     pub can_skip_mhe: bool,
     pub uses_local: bool,
+    /// Sets MHE up through `syqure_mhe`, which reuses cached keys.
+    pub caches_mhe_keys: bool,
 }
 
 #[derive(Debug, Serialize)]
@@ -59,6 +61,7 @@ pub fn analyze_source(path: &Path, source: &str) -> Result<Analysis> {
     let has_mpa = Regex::new(r"\bMPA\b")?.is_match(source);
     let uses_mhe = Regex::new(r"mpc\.mhe\b")?.is_match(source);
     let uses_local = Regex::new(r"@local\b")?.is_match(source);
+    let caches_mhe_keys = Regex::new(r"(?m)^\s*(from|import)\s+syqure_mhe\b")?.is_match(source);
 
     // Operation counting
     let matmul_count = Regex::new(r"@")?.find_iter(source).count();
@@ -113,6 +116,7 @@ pub fn analyze_source(path: &Path, source: &str) -> Result<Analysis> {
             needs_mhe,
             can_skip_mhe,
             uses_local,
+            caches_mhe_keys,
         },
         estimate: Estimate {
             jit_seconds,
@@ -199,6 +203,17 @@ pub fn print_analysis(analysis: &Analysis) {
         }
     );
 
+    if analysis.runtime.needs_mhe {
+        println!(
+            "  Caches MHE keys:     {}",
+            if analysis.runtime.caches_mhe_keys {
+                "Yes (with --mhe-keys or [job.mhe_keys])"
+            } else {
+                "No (use mpc() from syqure_mhe)"
+            }
+        );
+    }
+
     println!("\n--- Estimated Runtime ---");
     println!(
         "  JIT Compilation:     {:>6.1}s",
@@ -206,8 +221,13 @@ pub fn print_analysis(analysis: &Analysis) {
     );
     if analysis.runtime.needs_mhe {
         println!(
-            "  MHE Key Setup:       {:>6.1}s",
-            analysis.estimate.mhe_seconds
+            "  MHE Key Setup:       {:>6.1}s{}",
+            analysis.estimate.mhe_seconds,
+            if analysis.runtime.caches_mhe_keys {
+                " (first run of a job group)"
+            } else {
+                ""
+            }
         );
     }
     println!(
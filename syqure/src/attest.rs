@@ -0,0 +1,306 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::runner::CompileOptions;
+use crate::{bundle, helpers};
+
+/// Bumped whenever the canonical encoding below changes.
+const ATTESTATION_FORMAT: &str = "syqure-attestation-v2";
+
+/// Prefix of the combined hash of a job whose parties run different programs.
+const JOB_ATTESTATION_FORMAT: &str = "syqure-job-attestation-v1";
+
+/// Canonical identity of a program: everything that decides what a party runs.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Attestation {
+    /// SHA-256 over the canonical encoding of every field below.
+    pub hash: String,
+    /// Source file and its local transitive imports, relative to the source's
+    /// directory, with the SHA-256 of each.
+    pub files: BTreeMap<String, String>,
+    /// Signature of the Codon/Sequre bundle (covers the stdlib and plugins).
+    pub bundle_signature: String,
+    /// Compile and runtime options that change program behavior.
+    pub options: BTreeMap<String, String>,
+}
+
+impl Attestation {
+    /// Short form of the hash for logs and tables.
+    pub fn short_hash(&self) -> &str {
+        &self.hash[..self.hash.len().min(12)]
+    }
+}
+
+/// Attestation of a whole job: every party's program and the one hash all
+/// parties compare.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JobAttestation {
+    /// The parties' shared hash when they all run the same program; otherwise a
+    /// SHA-256 over every party's hash.
+    pub hash: String,
+    /// Attestation of each party's program, by pid.
+    pub parties: BTreeMap<usize, Attestation>,
+}
+
+impl JobAttestation {
+    pub fn from_parties(parties: BTreeMap<usize, Attestation>) -> Self {
+        let mut hashes = parties.values().map(|a| a.hash.as_str());
+        let first = hashes.next().unwrap_or_default();
+        let hash = if hashes.all(|h| h == first) {
+            first.to_string()
+        } else {
+            let mut hasher = Sha256::new();
+            hasher.update(JOB_ATTESTATION_FORMAT.as_bytes());
+            hasher.update(b"\n");
+            for (pid, attestation) in &parties {
+                hasher.update(format!("party {} {}\n", pid, attestation.hash).as_bytes());
+            }
+            hex(&hasher.finalize())
+        };
+        Self { hash, parties }
+    }
+
+    /// Whether every party runs the same program with the same options.
+    pub fn is_uniform(&self) -> bool {
+        self.parties.values().all(|a| a.hash == self.hash)
+    }
+
+    /// Short form of the hash for logs and tables.
+    pub fn short_hash(&self) -> &str {
+        &self.hash[..self.hash.len().min(12)]
+    }
+
+    /// Print each party's program hash, one line per party.
+    pub fn print_parties(&self) {
+        for (pid, party) in &self.parties {
+            println!("  CP{}: {}", pid, party.hash);
+        }
+    }
+}
+
+/// Attest each `(pid, source, options)` program of a job.
+pub fn attest_job(
+    programs: impl IntoIterator<Item = (usize, PathBuf, CompileOptions)>,
+) -> Result<JobAttestation> {
+    let bundle_signature = bundle::signature()?;
+    let mut parties = BTreeMap::new();
+    for (pid, source, opts) in programs {
+        helpers::write_helpers(&source)?;
+        parties.insert(pid, attest_with_bundle(&source, &opts, &bundle_signature)?);
+    }
+    Ok(JobAttestation::from_parties(parties))
+}
+
+/// Attest `source` as it would be compiled with `opts`.
+pub fn attest(source: &Path, opts: &CompileOptions) -> Result<Attestation> {
+    helpers::write_helpers(source)?;
+    attest_with_bundle(source, opts, &bundle::signature()?)
+}
+
+/// Like [`attest`], with an explicit bundle signature.
+pub fn attest_with_bundle(
+    source: &Path,
+    opts: &CompileOptions,
+    bundle_signature: &str,
+) -> Result<Attestation> {
+    let sources = collect_sources(source)?;
+    let root = sources[0]
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    let mut files = BTreeMap::new();
+    for path in &sources {
+        let bytes =
+            std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+        files.insert(relative_name(&root, path), hex(&Sha256::digest(&bytes)));
+    }
+
+    let mut options = BTreeMap::new();
+    options.insert("plugin".to_string(), opts.plugin.clone());
+    options.insert("release".to_string(), opts.release.to_string());
+    options.insert("disable_opts".to_string(), encode_list(&opts.disable_opts));
+    options.insert("libs".to_string(), encode_list(&opts.libs));
+    options.insert("linker_flags".to_string(), opts.linker_flags.clone());
+    options.insert("program_args".to_string(), encode_list(&opts.program_args));
+    options.insert(
+        "skip_mhe_setup".to_string(),
+        opts.runtime.skip_mhe_setup.to_string(),
+    );
+
+    let mut hasher = Sha256::new();
+    hasher.update(ATTESTATION_FORMAT.as_bytes());
+    hasher.update(b"\n");
+    for (name, digest) in &files {
+        hasher.update(format!("file {} {}\n", name, digest).as_bytes());
+    }
+    hasher.update(format!("bundle {}\n", bundle_signature).as_bytes());
+    for (key, value) in &options {
+        hasher.update(format!("option {}={}\n", key, value).as_bytes());
+    }
+
+    Ok(Attestation {
+        hash: hex(&hasher.finalize()),
+        files,
+        bundle_signature: bundle_signature.to_string(),
+        options,
+    })
+}
+
+/// JSON array of `values`, so lists that only differ in where the separators
+/// fall (`["a b"]` and `["a", "b"]`) hash differently.
+fn encode_list(values: &[String]) -> String {
+    serde_json::to_string(values).unwrap_or_default()
+}
+
+/// The source file (first) plus every import that resolves to a local file,
+/// transitively. Imports that don't resolve locally come from the bundled stdlib.
+pub fn collect_sources(source: &Path) -> Result<Vec<PathBuf>> {
+    let from_re = Regex::new(r"^\s*from\s+(\.*)([\w.]*)\s+import\s+(.+)$")?;
+    let import_re = Regex::new(r"^\s*import\s+(.+)$")?;
+
+    let root = source
+        .canonicalize()
+        .with_context(|| format!("source file not found: {}", source.display()))?;
+    let root_dir = root.parent().map(Path::to_path_buf).unwrap_or_default();
+
+    let mut seen = vec![root.clone()];
+    let mut queue = vec![root];
+    while let Some(file) = queue.pop() {
+        let text = std::fs::read_to_string(&file)
+            .with_context(|| format!("failed to read {}", file.display()))?;
+        let dir = file.parent().map(Path::to_path_buf).unwrap_or_default();
+
+        let mut modules = Vec::new();
+        for line in text.lines() {
+            if let Some(caps) = from_re.captures(line) {
+                let dots = caps[1].len();
+                let mut base = dir.clone();
+                for _ in 1..dots {
+                    base.pop();
+                }
+                let bases = if dots > 0 {
+                    vec![base]
+                } else {
+                    vec![dir.clone(), root_dir.clone()]
+                };
+                let module = caps[2].to_string();
+                // `from pkg import name` may name a submodule as well as a symbol.
+                for name in imported_names(&caps[3]) {
+                    let submodule = match module.as_str() {
+                        "" => name.to_string(),
+                        m => format!("{}.{}", m, name),
+                    };
+                    modules.push((bases.clone(), submodule));
+                }
+                modules.push((bases, module));
+            } else if let Some(caps) = import_re.captures(line) {
+                for name in imported_names(&caps[1]) {
+                    modules.push((vec![dir.clone(), root_dir.clone()], name.to_string()));
+                }
+            }
+        }
+
+        for (bases, module) in modules {
+            let Some(path) = bases.iter().find_map(|b| resolve_module(b, &module)) else {
+                continue;
+            };
+            if !seen.contains(&path) {
+                seen.push(path.clone());
+                queue.push(path);
+            }
+        }
+    }
+
+    seen[1..].sort();
+    Ok(seen)
+}
+
+/// Names in an import list, without `as` aliases or parentheses.
+fn imported_names(list: &str) -> impl Iterator<Item = &str> {
+    list.split(',')
+        .filter_map(|item| {
+            item.trim_matches(|c: char| c == '(' || c == ')' || c.is_whitespace())
+                .split_whitespace()
+                .next()
+        })
+        .filter(|name| *name != "*")
+}
+
+/// Path of `path` relative to `root`, so parties with different checkout
+/// locations still agree on file names.
+pub(crate) fn relative_name(root: &Path, path: &Path) -> String {
+    let root: Vec<_> = root.components().collect();
+    let target: Vec<_> = path.components().collect();
+    let common = root.iter().zip(&target).take_while(|(a, b)| a == b).count();
+    let mut rel = PathBuf::new();
+    for _ in common..root.len() {
+        rel.push("..");
+    }
+    for part in &target[common..] {
+        rel.push(part);
+    }
+    rel.to_string_lossy().into_owned()
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Inverse of [`hex`]; None unless `text` is an even number of hex digits.
+pub(crate) fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok())
+        .collect()
+}
+
+fn resolve_module(base: &Path, module: &str) -> Option<PathBuf> {
+    let mut path = base.to_path_buf();
+    for part in module.split('.').filter(|p| !p.is_empty()) {
+        path.push(part);
+    }
+    [path.with_extension("codon"), path.join("__init__.codon")]
+        .into_iter()
+        .find(|p| p.is_file())
+        .and_then(|p| p.canonicalize().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attest_args(source: &Path, program_args: &[&str]) -> Attestation {
+        let opts = CompileOptions {
+            program_args: program_args.iter().map(|s| s.to_string()).collect(),
+            ..CompileOptions::default()
+        };
+        attest_with_bundle(source, &opts, "bundle").unwrap()
+    }
+
+    #[test]
+    fn program_args_are_unambiguous() {
+        let dir = std::env::temp_dir().join(format!("syqure-attest-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("main.codon");
+        std::fs::write(&source, "print(1)\n").unwrap();
+
+        let joined = attest_args(&source, &["a b"]);
+        let split = attest_args(&source, &["a", "b"]);
+        assert_ne!(joined.hash, split.hash);
+        assert_eq!(joined.hash, attest_args(&source, &["a b"]).hash);
+        assert_ne!(
+            attest_args(&source, &[]).hash,
+            attest_args(&source, &[""]).hash
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
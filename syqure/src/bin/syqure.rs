@@ -1,8 +1,33 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
 
-use anyhow::Result;
-use clap::{Parser, Subcommand};
-use syqure::{analyze, analyze_file, bundle, CompileOptions, Syqure};
+use anyhow::{anyhow, Context, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use syqure::cluster::DEFAULT_CLUSTER_FILE;
+use syqure::daemon::{
+    self, ApiRequest, Daemon, DaemonOptions, JobRecord, JobRequest, JobStatus, LogStream,
+};
+use syqure::inputs::InputArg;
+use syqure::keys::{Keystore, PublicIdentity, DEFAULT_IDENTITY};
+use syqure::mhe::{self, KeyProtection, MheKeyConfig};
+use syqure::package::{self, SiteOptions};
+use syqure::party::{self, PartyOptions, DEFAULT_PREFLIGHT_TIMEOUT};
+use syqure::ports::PortAllocator;
+use syqure::signals::{self, Interrupted, INTERRUPTED_EXIT_CODE};
+use syqure::transport::bench::{bench_file_transport, BenchOptions};
+use syqure::transport::codec::{DEFAULT_CHUNK_SIZE, DEFAULT_COMPRESS_THRESHOLD};
+use syqure::transport::envelope::MessageKey;
+use syqure::transport::file::{run_proxy, FileProxyOptions};
+use syqure::transport::netem::NetProfile;
+use syqure::transport::state::{gc_job, Retention};
+use syqure::transport::tls;
+use syqure::transport::transcript::{self, ReplayOptions};
+use syqure::transport::ChannelStats;
+use syqure::{
+    analyze, analyze_file, attest, bundle, launch_cluster, launch_local, ClusterConfig,
+    CompileOptions, LaunchOptions, MultiPartyResult, RunResult, SequreRuntimeConfig, Syqure,
+};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
@@ -28,6 +53,34 @@ struct Args {
     #[arg(long, global = true)]
     skip_mhe_setup: bool,
 
+    /// Cache MHE keys across runs, under DIR or <config dir>/mhe-keys (programs
+    /// use `mpc()` from syqure_mhe)
+    #[arg(long, global = true, value_name = "DIR", num_args = 0..=1, require_equals = true)]
+    mhe_keys: Option<Option<PathBuf>>,
+
+    /// Seal cached MHE keys at rest with this message key file (implies --mhe-keys)
+    #[arg(long, global = true, value_name = "KEY_FILE")]
+    mhe_key_seal: Option<PathBuf>,
+
+    /// Print per-channel traffic after a run (file and tls transports)
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        num_args = 0..=1,
+        default_missing_value = "table",
+        require_equals = true
+    )]
+    comm_stats: Option<StatsFormat>,
+
+    /// Record every inter-party message under this directory (file transport only)
+    #[arg(long, global = true)]
+    record_transcript: Option<PathBuf>,
+
+    /// Emulate latency, bandwidth and loss from this TOML profile (file transport only)
+    #[arg(long, global = true)]
+    net_profile: Option<PathBuf>,
+
     /// Show compiler warnings (hidden by default)
     #[arg(long, global = true)]
     show_warnings: bool,
@@ -41,6 +94,12 @@ struct Args {
     program_args: Vec<String>,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum StatsFormat {
+    Table,
+    Json,
+}
+
 #[derive(Subcommand, Debug)]
 enum Command {
     /// Compile and run a .codon source file
@@ -56,13 +115,480 @@ enum Command {
         #[arg(long)]
         json: bool,
     },
+    /// Print the attestation hash parties compare before running
+    Attest {
+        /// Path to the .codon source file (taken from --config when omitted)
+        #[arg(required_unless_present = "config")]
+        source: Option<PathBuf>,
+        /// Cluster description file; attests every party's program as one job
+        #[arg(long, conflicts_with = "source")]
+        config: Option<PathBuf>,
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Build once and run every compute party as a local process
+    Local {
+        /// Path to the .codon source file (taken from --config when omitted)
+        #[arg(required_unless_present = "config")]
+        source: Option<PathBuf>,
+        /// Cluster description file (syqure.toml)
+        #[arg(long, conflicts_with_all = ["parties", "port_base"])]
+        config: Option<PathBuf>,
+        /// Number of compute parties (including the trusted dealer CP0)
+        #[arg(long, default_value_t = 3)]
+        parties: usize,
+        /// Base port exported to every party as SEQURE_PORT_BASE
+        #[arg(long)]
+        port_base: Option<u16>,
+        /// Reserve a free port range for this run (lockfiles in $SYQURE_PORT_LOCK_DIR)
+        #[arg(long, conflicts_with = "port_base")]
+        auto_ports: bool,
+        /// Write every party's named outputs to this file as JSON
+        #[arg(long)]
+        outputs: Option<PathBuf>,
+        /// Fail when parties report the same output with different values
+        #[arg(long)]
+        check_outputs: bool,
+    },
+    /// Run one compute party of a multi-machine job
+    Party {
+        /// Path to the .codon source file (taken from --config when omitted)
+        #[arg(required_unless_present = "config")]
+        source: Option<PathBuf>,
+        /// Cluster description file (syqure.toml)
+        #[arg(long)]
+        config: Option<PathBuf>,
+        /// This party's id (0 is the trusted dealer)
+        #[arg(long)]
+        pid: usize,
+        /// Comma-separated IPs of every party, indexed by pid
+        #[arg(long, env = "SEQURE_CP_IPS", value_delimiter = ',')]
+        peers: Vec<String>,
+        /// Base port for party-to-party channels
+        #[arg(long, env = "SEQURE_PORT_BASE")]
+        port_base: Option<u16>,
+        /// Path to libgmp for Sequre to dlopen
+        #[arg(long, env = "SEQURE_GMP_PATH")]
+        gmp_path: Option<PathBuf>,
+        /// Private input file for this party, as NAME=PATH (repeatable; overrides
+        /// the party's `inputs` in --config)
+        #[arg(long = "input", value_name = "NAME=PATH")]
+        inputs: Vec<InputArg>,
+        #[command(flatten)]
+        preflight: PreflightArgs,
+    },
+    /// Proxy one party's Sequre TCP channels through a shared message directory
+    Proxy {
+        /// Party this proxy serves
+        #[arg(long)]
+        pid: usize,
+        /// Number of parties in the job
+        #[arg(long, default_value_t = 3)]
+        parties: usize,
+        /// Shared message directory (e.g. a SyftBox-synced folder)
+        #[arg(long)]
+        data_dir: PathBuf,
+        /// Port base of this party's Sequre process
+        #[arg(long, alias = "sequre-base-port", default_value_t = 9000)]
+        base_port: u16,
+        /// Poll interval for inbound message files, in milliseconds
+        #[arg(long, default_value_t = 10)]
+        poll_ms: u64,
+        /// Attestation hash to compare with every peer before relaying
+        #[arg(long)]
+        program_hash: Option<String>,
+        /// Job key file; seal outbound messages and refuse inbound ones that fail to open
+        #[arg(long, requires = "job_id")]
+        message_key: Option<PathBuf>,
+        /// Compress frames of at least this many bytes (0 disables)
+        #[arg(long, default_value_t = DEFAULT_COMPRESS_THRESHOLD)]
+        compress_threshold: usize,
+        /// Split messages larger than this many bytes into chunk files (0 disables)
+        #[arg(long, default_value_t = DEFAULT_CHUNK_SIZE)]
+        chunk_size: usize,
+        /// Poll for message files even where inotify is available
+        #[arg(long)]
+        no_inotify: bool,
+        /// Keep this job's files in DATA_DIR/JOB_ID, next to other jobs
+        #[arg(long)]
+        job_id: Option<String>,
+        /// What to do with delivered messages: delete or archive
+        #[arg(long, default_value_t = Retention::Delete)]
+        retention: Retention,
+    },
+    /// Remove a finished job's files from a shared message directory
+    Gc {
+        /// Shared message directory
+        #[arg(long)]
+        data_dir: PathBuf,
+        /// Job to remove
+        #[arg(long)]
+        job_id: String,
+        /// Only remove the archived messages
+        #[arg(long)]
+        archive_only: bool,
+    },
+    /// Compare file-transport round trips per second with inotify and with polling
+    BenchTransport {
+        /// Request/response rounds per mode
+        #[arg(long, default_value_t = 1000)]
+        rounds: usize,
+        /// Payload bytes per message
+        #[arg(long, default_value_t = 64)]
+        size: usize,
+        /// Poll interval of the polling mode, in milliseconds
+        #[arg(long, default_value_t = 10)]
+        poll_ms: u64,
+        /// First local port to use
+        #[arg(long, default_value_t = 23000)]
+        port_base: u16,
+        /// Print results as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Write a random key for sealing file-transport messages (share it with every party)
+    MessageKey {
+        /// Where to write the key
+        path: PathBuf,
+    },
+    /// Re-run one party against the messages recorded with --record-transcript
+    Replay {
+        /// Path to the .codon source file that was recorded
+        source: PathBuf,
+        /// Party to re-run
+        #[arg(long)]
+        pid: usize,
+        /// Directory passed to --record-transcript
+        #[arg(long)]
+        transcript: PathBuf,
+        /// Port base for the replayed party's channels
+        #[arg(long, default_value_t = 9000)]
+        port_base: u16,
+    },
+    /// Work with cluster description files
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommand,
+    },
+    /// Manage party certificates for the tls transport
+    Tls {
+        #[command(subcommand)]
+        action: TlsCommand,
+    },
+    /// Manage long-term party identities and trusted peers
+    Keys {
+        /// Keystore directory (default: <config dir>/keys)
+        #[arg(long, env = "SYQURE_KEYSTORE", global = true)]
+        keystore: Option<PathBuf>,
+        #[command(subcommand)]
+        action: KeysCommand,
+    },
+    /// Pack, sign, check and run job packages, or submit jobs to a daemon
+    Job {
+        /// Keystore directory (default: <config dir>/keys)
+        #[arg(long, env = "SYQURE_KEYSTORE", global = true)]
+        keystore: Option<PathBuf>,
+        #[command(subcommand)]
+        action: JobCommand,
+    },
+    /// Stay up as one party of this site and run submitted jobs one at a time
+    Daemon {
+        /// This site's party id
+        #[arg(long)]
+        pid: usize,
+        /// Socket and job history (default: $SYQURE_DAEMON_DIR or <config dir>/daemon/CP<pid>)
+        #[arg(long)]
+        dir: Option<PathBuf>,
+        /// Serve the job API on this socket instead of <dir>/daemon.sock
+        #[arg(long)]
+        socket: Option<PathBuf>,
+        /// Also run job requests dropped into this directory as *.json files
+        #[arg(long)]
+        watch: Option<PathBuf>,
+        /// Keystore for verifying job packages (default: <config dir>/keys)
+        #[arg(long, env = "SYQURE_KEYSTORE")]
+        keystore: Option<PathBuf>,
+    },
+    /// List or discard cached MHE keys
+    MheKeys {
+        /// Store root (default: <config dir>/mhe-keys)
+        #[arg(long, global = true)]
+        dir: Option<PathBuf>,
+        #[command(subcommand)]
+        action: MheKeysCommand,
+    },
     /// Show build and system information for debugging
     Info,
 }
 
+#[derive(clap::Args, Debug)]
+struct PreflightArgs {
+    /// Only check that every peer is reachable and runs the same program, then exit
+    #[arg(long)]
+    check: bool,
+    /// Skip the automatic peer check before compiling
+    #[arg(long, conflicts_with = "check")]
+    no_preflight: bool,
+    /// Seconds to wait for peers during the check
+    #[arg(long)]
+    preflight_timeout: Option<u64>,
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Check a cluster file for consistency
+    Validate {
+        /// Path to the cluster file
+        #[arg(default_value = DEFAULT_CLUSTER_FILE)]
+        path: PathBuf,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum TlsCommand {
+    /// Write a self-signed certificate and key per party for the tls transport
+    Generate {
+        /// Number of parties
+        #[arg(long, default_value_t = 3)]
+        parties: usize,
+        /// Output directory for cp<n>.pem and cp<n>.key
+        #[arg(long, default_value = "certs")]
+        out: PathBuf,
+    },
+    /// Print the SHA-256 fingerprint to pin as cert_sha256
+    Fingerprint {
+        /// PEM certificate
+        cert: PathBuf,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum KeysCommand {
+    /// Create a signing and encryption keypair for this party
+    Generate {
+        /// Identity name
+        #[arg(long, default_value = DEFAULT_IDENTITY)]
+        name: String,
+        /// Replace an existing identity of the same name
+        #[arg(long)]
+        force: bool,
+    },
+    /// Print an identity's public keys for peers to import
+    ExportPublic {
+        /// Identity name
+        #[arg(long, default_value = DEFAULT_IDENTITY)]
+        name: String,
+        /// Write to a file instead of stdout
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    /// Trust a peer's exported public identity
+    ImportPeer {
+        /// File written by `keys export-public` on the peer's machine
+        file: PathBuf,
+        /// Store under this name instead of the one in the file
+        #[arg(long)]
+        name: Option<String>,
+        /// Replace a different key already stored under the same name
+        #[arg(long)]
+        force: bool,
+    },
+    /// List identities and trusted peers with their fingerprints
+    List {
+        /// Print as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum MheKeysCommand {
+    /// Show each cached key set by group and party
+    List {
+        /// Print as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Remove cached keys so the next run generates new ones
+    #[command(group(clap::ArgGroup::new("scope").required(true).args(["group", "all"])))]
+    Clear {
+        /// Job group to clear
+        #[arg(long)]
+        group: Option<String>,
+        /// Clear every group
+        #[arg(long)]
+        all: bool,
+        /// Only this party's keys
+        #[arg(long)]
+        pid: Option<usize>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum JobCommand {
+    /// Pack a cluster job's sources, config and expected attestation into a
+    /// signed tar.zst
+    Pack {
+        /// Cluster description file
+        #[arg(long, default_value = DEFAULT_CLUSTER_FILE)]
+        config: PathBuf,
+        /// Where to write the package
+        #[arg(long)]
+        out: PathBuf,
+        /// Identity that signs the package
+        #[arg(long, default_value = DEFAULT_IDENTITY)]
+        identity: String,
+    },
+    /// Add a signature to an existing package
+    Sign {
+        /// Job package (.tar.zst)
+        package: PathBuf,
+        /// Identity that signs the package
+        #[arg(long, default_value = DEFAULT_IDENTITY)]
+        identity: String,
+    },
+    /// Check a package's files, signatures and attestation
+    Verify {
+        /// Job package (.tar.zst)
+        package: PathBuf,
+        /// Keep the unpacked job in this (new or empty) directory
+        #[arg(long)]
+        into: Option<PathBuf>,
+        /// Print the manifest as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Verify a package, then run this site's party of it
+    Run {
+        /// Job package (.tar.zst)
+        package: PathBuf,
+        /// This site's party id
+        #[arg(long)]
+        pid: usize,
+        /// Keep the unpacked job in this (new or empty) directory
+        #[arg(long)]
+        into: Option<PathBuf>,
+        /// Shared message directory (file transport)
+        #[arg(long)]
+        message_dir: Option<PathBuf>,
+        /// Job key file (file transport)
+        #[arg(long)]
+        message_key: Option<PathBuf>,
+        /// This party's certificate; must match the one pinned in the package (tls transport)
+        #[arg(long, requires = "key")]
+        cert: Option<PathBuf>,
+        /// Private key for --cert
+        #[arg(long, requires = "cert")]
+        key: Option<PathBuf>,
+        /// Private input file for this party, as NAME=PATH (repeatable)
+        #[arg(long = "input", value_name = "NAME=PATH")]
+        inputs: Vec<InputArg>,
+        #[command(flatten)]
+        preflight: PreflightArgs,
+    },
+    /// Queue a job on this site's daemon
+    #[command(group(clap::ArgGroup::new("job").required(true).args(["config", "package"])))]
+    Submit {
+        /// Cluster description file
+        #[arg(long)]
+        config: Option<PathBuf>,
+        /// Job package (.tar.zst), verified by the daemon
+        #[arg(long)]
+        package: Option<PathBuf>,
+        /// Job id (default: generated)
+        #[arg(long)]
+        id: Option<String>,
+        /// Shared message directory (file transport)
+        #[arg(long)]
+        message_dir: Option<PathBuf>,
+        /// Job key file (file transport)
+        #[arg(long)]
+        message_key: Option<PathBuf>,
+        /// This party's certificate for a packaged tls job
+        #[arg(long, requires = "key")]
+        cert: Option<PathBuf>,
+        /// Private key for --cert
+        #[arg(long, requires = "cert")]
+        key: Option<PathBuf>,
+        /// Private input file for this party, as NAME=PATH (repeatable)
+        #[arg(long = "input", value_name = "NAME=PATH")]
+        inputs: Vec<InputArg>,
+        /// Wait for the job to finish and print its status
+        #[arg(long)]
+        wait: bool,
+        #[command(flatten)]
+        daemon: DaemonArgs,
+    },
+    /// Show a daemon job's status
+    Status {
+        /// Job id
+        id: String,
+        /// Print the job record as JSON
+        #[arg(long)]
+        json: bool,
+        #[command(flatten)]
+        daemon: DaemonArgs,
+    },
+    /// List the daemon's jobs, oldest first
+    List {
+        /// Print the job records as JSON
+        #[arg(long)]
+        json: bool,
+        #[command(flatten)]
+        daemon: DaemonArgs,
+    },
+    /// Print a finished daemon job's captured output
+    Logs {
+        /// Job id
+        id: String,
+        /// Which stream: stdout or stderr
+        #[arg(long, default_value = "stdout")]
+        stream: LogStream,
+        #[command(flatten)]
+        daemon: DaemonArgs,
+    },
+    /// Print a daemon job's named outputs as JSON
+    Results {
+        /// Job id
+        id: String,
+        #[command(flatten)]
+        daemon: DaemonArgs,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+struct DaemonArgs {
+    /// Daemon socket
+    #[arg(long, env = "SYQURE_DAEMON_SOCKET")]
+    socket: Option<PathBuf>,
+    /// Talk to the daemon of this party at its default socket
+    #[arg(long, required_unless_present = "socket")]
+    pid: Option<usize>,
+}
+
+impl DaemonArgs {
+    fn socket(&self) -> Result<PathBuf> {
+        match (&self.socket, self.pid) {
+            (Some(socket), _) => Ok(socket.clone()),
+            (None, Some(pid)) => Ok(daemon::socket_path(&daemon::default_daemon_dir(pid)?)),
+            (None, None) => unreachable!("clap requires --socket or --pid"),
+        }
+    }
+}
+
 fn main() -> Result<()> {
-    let args = Args::parse();
+    let result = run(Args::parse());
+    if let Err(ref e) = result {
+        if e.downcast_ref::<Interrupted>().is_some() {
+            eprintln!("Error: {:#}", e);
+            std::process::exit(INTERRUPTED_EXIT_CODE);
+        }
+    }
+    result
+}
 
+fn run(args: Args) -> Result<()> {
     match &args.command {
         Some(Command::Analyze { source, json }) => {
             let analysis = analyze_file(source)?;
@@ -75,6 +601,288 @@ fn main() -> Result<()> {
         Some(Command::Run { source }) => {
             run_source(&args, source)?;
         }
+        Some(Command::Local {
+            source,
+            config,
+            parties,
+            port_base,
+            auto_ports,
+            outputs,
+            check_outputs,
+        }) => {
+            signals::install()?;
+            let opts = compile_options(&args);
+            let launch = LaunchOptions {
+                transcript_dir: args.record_transcript.clone(),
+                net_profile: net_profile(&args)?,
+                auto_ports: auto_ports.then(PortAllocator::default),
+                mhe_keys: mhe_keys(&args),
+                ..LaunchOptions::default()
+            };
+            let result = match config {
+                Some(path) => launch_cluster(&opts, &launch, &ClusterConfig::load(path)?)?,
+                None => {
+                    let launch = LaunchOptions {
+                        parties: *parties,
+                        port_base: *port_base,
+                        ..launch
+                    };
+                    let source = source.as_ref().expect("clap requires source or --config");
+                    launch_local(&opts, &launch, source)?
+                }
+            };
+            if let Some(format) = args.comm_stats {
+                print_comm_stats(&result.comm_stats, format)?;
+            }
+            if let Some(path) = outputs {
+                std::fs::write(path, serde_json::to_string_pretty(&result.outputs)?)
+                    .with_context(|| format!("failed to write {}", path.display()))?;
+            }
+            report_parties(&result)?;
+            if *check_outputs {
+                check_party_outputs(&result)?;
+            }
+        }
+        Some(Command::Party {
+            source,
+            config,
+            pid,
+            peers,
+            port_base,
+            gmp_path,
+            inputs,
+            preflight,
+        }) => {
+            let cluster = config.as_ref().map(ClusterConfig::load).transpose()?;
+            let mut runtime = match cluster {
+                Some(ref cluster) => cluster.runtime_config(*pid),
+                None => SequreRuntimeConfig {
+                    pid: Some(*pid),
+                    ..SequreRuntimeConfig::default()
+                },
+            };
+            if !peers.is_empty() {
+                runtime.cp_ips = peers.clone();
+                runtime.parties = None;
+            }
+            if port_base.is_some() {
+                runtime.port_base = *port_base;
+            }
+            if gmp_path.is_some() {
+                runtime.gmp_path = gmp_path.clone();
+            }
+            run_party(
+                &args,
+                compile_options(&args),
+                source.as_ref(),
+                cluster.as_ref(),
+                runtime,
+                inputs,
+                preflight,
+            )?;
+        }
+        Some(Command::Attest {
+            source,
+            config,
+            json,
+        }) => {
+            let opts = compile_options(&args);
+            let attestation = match (source, config) {
+                (Some(source), _) => attest(source, &opts)?,
+                (None, Some(config)) => {
+                    let cluster = ClusterConfig::load(config)?;
+                    cluster.validate()?;
+                    let job = cluster.attest(&opts)?;
+                    if *json {
+                        println!("{}", serde_json::to_string_pretty(&job)?);
+                        return Ok(());
+                    }
+                    if !job.is_uniform() {
+                        println!("{}", job.hash);
+                        job.print_parties();
+                        return Ok(());
+                    }
+                    job.parties[&0].clone()
+                }
+                (None, None) => unreachable!("clap requires source or --config"),
+            };
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&attestation)?);
+            } else {
+                println!("{}", attestation.hash);
+                for (name, digest) in &attestation.files {
+                    println!("  {}  {}", &digest[..12], name);
+                }
+            }
+        }
+        Some(Command::Config {
+            action: ConfigCommand::Validate { path },
+        }) => {
+            let cluster = ClusterConfig::load(path)?;
+            cluster.validate()?;
+            println!(
+                "{}: OK ({} parties, {} transport)",
+                path.display(),
+                cluster.party_count(),
+                cluster.job.transport
+            );
+        }
+        Some(Command::Tls {
+            action: TlsCommand::Generate { parties, out },
+        }) => {
+            for pid in 0..*parties {
+                let (cert, fingerprint) = tls::generate_identity(out, pid)?;
+                println!("CP{}: {}  {}", pid, cert.display(), fingerprint);
+            }
+        }
+        Some(Command::Tls {
+            action: TlsCommand::Fingerprint { cert },
+        }) => {
+            println!("{}", tls::cert_fingerprint(cert)?);
+        }
+        Some(Command::Keys { keystore, action }) => {
+            let store = match keystore {
+                Some(dir) => Keystore::open(dir),
+                None => Keystore::open_default()?,
+            };
+            run_keys(&store, action)?;
+        }
+        Some(Command::Job { keystore, action }) => {
+            let store = match keystore {
+                Some(dir) => Keystore::open(dir),
+                None => Keystore::open_default()?,
+            };
+            run_job(&args, &store, action)?;
+        }
+        Some(Command::Daemon {
+            pid,
+            dir,
+            socket,
+            watch,
+            keystore,
+        }) => {
+            let keystore = match keystore {
+                Some(dir) => Some(Keystore::open(dir)),
+                None => Keystore::open_default().ok(),
+            };
+            if !args.program_args.is_empty() {
+                return Err(anyhow!(
+                    "the daemon takes program arguments with each job (`syqure job submit ... -- ARGS`)"
+                ));
+            }
+            let mut compile = compile_options(&args);
+            compile.runtime.pid = Some(*pid);
+            let daemon = Daemon::new(DaemonOptions {
+                pid: *pid,
+                dir: match dir {
+                    Some(dir) => dir.clone(),
+                    None => daemon::default_daemon_dir(*pid)?,
+                },
+                socket: socket.clone(),
+                watch_dir: watch.clone(),
+                keystore,
+                compile,
+                mhe_keys: mhe_keys(&args),
+                ..DaemonOptions::default()
+            })?;
+            daemon.run()?;
+        }
+        Some(Command::BenchTransport {
+            rounds,
+            size,
+            poll_ms,
+            port_base,
+            json,
+        }) => {
+            let results = bench_file_transport(&BenchOptions {
+                rounds: *rounds,
+                message_size: *size,
+                poll_interval: Duration::from_millis(*poll_ms),
+                port_base: *port_base,
+                ..BenchOptions::default()
+            })?;
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&results)?);
+            } else {
+                println!(
+                    "{:<8} {:>8} {:>9} {:>10} {:>9}",
+                    "MODE", "ROUNDS", "SECS", "ROUNDS/S", "CPU SECS"
+                );
+                for r in &results {
+                    let cpu = r.cpu_secs.map_or("-".to_string(), |c| format!("{:.3}", c));
+                    println!(
+                        "{:<8} {:>8} {:>9.3} {:>10.1} {:>9}",
+                        r.mode, r.rounds, r.secs, r.rounds_per_sec, cpu
+                    );
+                }
+                if results.len() == 1 {
+                    println!("(inotify is not available here)");
+                }
+            }
+        }
+        Some(Command::MessageKey { path }) => {
+            MessageKey::generate(path)?;
+            println!("Wrote {}", path.display());
+        }
+        Some(Command::Proxy {
+            pid,
+            parties,
+            data_dir,
+            base_port,
+            poll_ms,
+            program_hash,
+            message_key,
+            compress_threshold,
+            chunk_size,
+            no_inotify,
+            job_id,
+            retention,
+        }) => {
+            let stats = run_proxy(&FileProxyOptions {
+                pid: *pid,
+                parties: *parties,
+                data_dir: data_dir.clone(),
+                job_id: job_id.clone(),
+                retention: *retention,
+                base_port: *base_port,
+                poll_interval: Duration::from_millis(*poll_ms),
+                use_inotify: !*no_inotify,
+                program_hash: program_hash.clone(),
+                message_key: message_key.clone(),
+                compress_threshold: (*compress_threshold > 0).then_some(*compress_threshold),
+                chunk_size: (*chunk_size > 0).then_some(*chunk_size),
+                transcript_dir: args.record_transcript.clone(),
+                net_profile: net_profile(&args)?,
+                ..FileProxyOptions::default()
+            })?;
+            if let Some(format) = args.comm_stats {
+                print_comm_stats(&stats, format)?;
+            }
+        }
+        Some(Command::Gc {
+            data_dir,
+            job_id,
+            archive_only,
+        }) => {
+            let removed = gc_job(data_dir, job_id, *archive_only)?;
+            println!("Removed {}", removed.display());
+        }
+        Some(Command::Replay {
+            source,
+            pid,
+            transcript,
+            port_base,
+        }) => {
+            replay_party(&args, source, *pid, transcript, *port_base)?;
+        }
+        Some(Command::MheKeys { dir, action }) => {
+            let root = MheKeyConfig {
+                dir: dir.clone(),
+                ..MheKeyConfig::default()
+            }
+            .root()?;
+            run_mhe_keys(&root, action)?;
+        }
         Some(Command::Info) => {
             print_info();
         }
@@ -93,7 +901,7 @@ fn main() -> Result<()> {
     Ok(())
 }
 
-fn run_source(args: &Args, source: &PathBuf) -> Result<()> {
+fn compile_options(args: &Args) -> CompileOptions {
     let mut opts = CompileOptions::default();
     if let Some(ref path) = args.codon_path {
         opts.codon_path = path.clone();
@@ -102,17 +910,442 @@ fn run_source(args: &Args, source: &PathBuf) -> Result<()> {
     opts.run_after_build = !args.build_only;
     opts.quiet = !args.show_warnings;
 
-    // Build program args, prepending --skip-mhe-setup if requested
-    let mut program_args = Vec::new();
-    if args.skip_mhe_setup {
-        program_args.push("--skip-mhe-setup".to_string());
+    opts.program_args = args.program_args.clone();
+    opts.runtime.skip_mhe_setup = args.skip_mhe_setup;
+    opts
+}
+
+/// Key caching requested on the command line, if any.
+fn mhe_keys(args: &Args) -> Option<MheKeyConfig> {
+    if args.mhe_keys.is_none() && args.mhe_key_seal.is_none() {
+        return None;
+    }
+    Some(MheKeyConfig {
+        dir: args.mhe_keys.clone().flatten(),
+        protection: match args.mhe_key_seal {
+            Some(_) => KeyProtection::Sealed,
+            None => KeyProtection::OwnerOnly,
+        },
+        seal_key: args.mhe_key_seal.clone(),
+        ..MheKeyConfig::default()
+    })
+}
+
+fn run_mhe_keys(root: &Path, action: &MheKeysCommand) -> Result<()> {
+    match action {
+        MheKeysCommand::List { json } => {
+            let stored = mhe::list(root)?;
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&stored)?);
+            } else if stored.is_empty() {
+                println!("No cached MHE keys in {}", root.display());
+            } else {
+                println!(
+                    "{:<20} {:<5} {:<22} {:<11} CREATED (ms)",
+                    "GROUP", "PARTY", "KEY SET", "PROTECTION"
+                );
+                for slot in stored {
+                    match slot.meta {
+                        Some(meta) => println!(
+                            "{:<20} CP{:<3} {:<22} {:<11} {}",
+                            slot.group,
+                            slot.pid,
+                            meta.key_set,
+                            meta.protection.to_string(),
+                            meta.created_at_ms
+                        ),
+                        None => println!("{:<20} CP{:<3} (none)", slot.group, slot.pid),
+                    }
+                }
+            }
+        }
+        MheKeysCommand::Clear { group, all: _, pid } => {
+            let removed = mhe::clear(root, group.as_deref(), *pid)?;
+            for dir in &removed {
+                println!("Removed {}", dir.display());
+            }
+            if removed.is_empty() {
+                println!("No cached MHE keys matched");
+            }
+        }
     }
-    program_args.extend(args.program_args.clone());
-    opts.program_args = program_args;
+    Ok(())
+}
 
+fn run_keys(store: &Keystore, action: &KeysCommand) -> Result<()> {
+    match action {
+        KeysCommand::Generate { name, force } => {
+            let public = store.generate(name, *force)?;
+            println!(
+                "Generated identity '{}' in {}",
+                name,
+                store.root().display()
+            );
+            println!("Fingerprint: {}", public.fingerprint());
+        }
+        KeysCommand::ExportPublic { name, out } => {
+            let json = serde_json::to_string_pretty(&store.public(name)?)?;
+            match out {
+                Some(path) => {
+                    std::fs::write(path, format!("{}\n", json))
+                        .with_context(|| format!("failed to write {}", path.display()))?;
+                    eprintln!("Wrote {}", path.display());
+                }
+                None => println!("{}", json),
+            }
+        }
+        KeysCommand::ImportPeer { file, name, force } => {
+            let bytes = std::fs::read(file)
+                .with_context(|| format!("failed to read {}", file.display()))?;
+            let identity: PublicIdentity = serde_json::from_slice(&bytes)
+                .with_context(|| format!("failed to parse {}", file.display()))?;
+            let stored = store.import_peer(&identity, name.as_deref(), *force)?;
+            println!("Trusted peer '{}'", stored.name);
+            println!(
+                "Fingerprint: {} (confirm it with the peer out of band)",
+                stored.fingerprint()
+            );
+        }
+        KeysCommand::List { json } => {
+            let entries = store.list()?;
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&entries)?);
+            } else if entries.is_empty() {
+                println!("No keys in {}", store.root().display());
+            } else {
+                println!("{:<6} {:<20} FINGERPRINT", "KIND", "NAME");
+                for entry in entries {
+                    println!("{:<6} {:<20} {}", entry.kind, entry.name, entry.fingerprint);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn run_job(args: &Args, store: &Keystore, action: &JobCommand) -> Result<()> {
+    match action {
+        JobCommand::Pack {
+            config,
+            out,
+            identity,
+        } => {
+            let cluster = ClusterConfig::load(config)?;
+            let manifest = package::pack(
+                &cluster,
+                &compile_options(args),
+                &store.identity(identity)?,
+                out,
+            )?;
+            println!("Wrote {}", out.display());
+            println!("Program attestation: {}", manifest.expected_hash);
+            for party in &manifest.parties {
+                println!("  CP{}: {} ({})", party.pid, party.host, party.role);
+            }
+        }
+        JobCommand::Sign { package, identity } => {
+            let count = package::countersign(package, &store.identity(identity)?)?;
+            println!(
+                "Signed {} as '{}' ({} signature(s))",
+                package.display(),
+                identity,
+                count
+            );
+        }
+        JobCommand::Verify {
+            package,
+            into,
+            json,
+        } => {
+            let job = package::verify(package, into.as_deref(), store)?;
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&job.manifest)?);
+            } else {
+                println!("{}: OK", package.display());
+                println!("Program attestation: {}", job.manifest.expected_hash);
+                println!("Signed by: {}", job.trusted_signers.join(", "));
+                for party in &job.manifest.parties {
+                    println!("  CP{}: {} ({})", party.pid, party.host, party.role);
+                }
+                if into.is_some() {
+                    println!("Unpacked into {}", job.dir.display());
+                }
+            }
+        }
+        JobCommand::Run {
+            package,
+            pid,
+            into,
+            message_dir,
+            message_key,
+            cert,
+            key,
+            inputs,
+            preflight,
+        } => {
+            if !args.program_args.is_empty() {
+                return Err(anyhow!(
+                    "packaged jobs take their program arguments from the package"
+                ));
+            }
+            let job = package::verify(package, into.as_deref(), store)?;
+            println!(
+                "Verified {} (signed by {})",
+                package.display(),
+                job.trusted_signers.join(", ")
+            );
+            let cluster = job.cluster(
+                *pid,
+                &SiteOptions {
+                    message_dir: message_dir.clone(),
+                    message_key: message_key.clone(),
+                    cert: cert.clone(),
+                    key: key.clone(),
+                },
+            )?;
+            let mut opts = compile_options(args);
+            opts.release = job.manifest.release();
+            let runtime = cluster.runtime_config(*pid);
+            run_party(args, opts, None, Some(&cluster), runtime, inputs, preflight)?;
+        }
+        JobCommand::Submit {
+            config,
+            package,
+            id,
+            message_dir,
+            message_key,
+            cert,
+            key,
+            inputs,
+            wait,
+            daemon,
+        } => {
+            let socket = daemon.socket()?;
+            let mut job = JobRequest {
+                id: id.clone(),
+                config: config.clone(),
+                package: package.clone(),
+                inputs: inputs
+                    .iter()
+                    .map(|input| (input.name.clone(), input.path.clone()))
+                    .collect(),
+                program_args: args.program_args.clone(),
+                message_dir: message_dir.clone(),
+                message_key: message_key.clone(),
+                cert: cert.clone(),
+                key: key.clone(),
+            };
+            // The daemon runs elsewhere, so send paths it can resolve.
+            job.resolve_paths(&std::env::current_dir()?);
+            job.validate()?;
+            let record = daemon::request(&socket, &ApiRequest::Submit { job })?
+                .job
+                .ok_or_else(|| anyhow!("the daemon did not return the queued job"))?;
+            println!("Queued job {}", record.id);
+            if *wait {
+                signals::install()?;
+                let record = wait_for_job(&socket, &record.id)?;
+                print_job(&record);
+                if record.status != JobStatus::Succeeded {
+                    return Err(anyhow!("job {} {}", record.id, record.status));
+                }
+            }
+        }
+        JobCommand::Status { id, json, daemon } => {
+            let record =
+                daemon::request(&daemon.socket()?, &ApiRequest::Status { id: id.clone() })?
+                    .job
+                    .ok_or_else(|| anyhow!("the daemon did not return job '{}'", id))?;
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&record)?);
+            } else {
+                print_job(&record);
+            }
+        }
+        JobCommand::List { json, daemon } => {
+            let jobs = daemon::request(&daemon.socket()?, &ApiRequest::List)?.jobs;
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&jobs)?);
+            } else if jobs.is_empty() {
+                println!("No jobs");
+            } else {
+                println!("{:<32} {:<12} ATTESTATION", "ID", "STATUS");
+                for job in jobs {
+                    println!(
+                        "{:<32} {:<12} {}",
+                        job.id,
+                        job.status.to_string(),
+                        job.attestation.as_deref().unwrap_or("-")
+                    );
+                }
+            }
+        }
+        JobCommand::Logs { id, stream, daemon } => {
+            let request = ApiRequest::Logs {
+                id: id.clone(),
+                stream: *stream,
+            };
+            let log = daemon::request(&daemon.socket()?, &request)?.log;
+            print!("{}", log.unwrap_or_default());
+        }
+        JobCommand::Results { id, daemon } => {
+            let outputs =
+                daemon::request(&daemon.socket()?, &ApiRequest::Results { id: id.clone() })?
+                    .outputs
+                    .unwrap_or_default();
+            println!("{}", serde_json::to_string_pretty(&outputs)?);
+        }
+    }
+    Ok(())
+}
+
+/// Poll the daemon until job `id` has finished.
+fn wait_for_job(socket: &Path, id: &str) -> Result<JobRecord> {
+    loop {
+        if let Some(sig) = signals::received() {
+            return Err(Interrupted(sig).into());
+        }
+        let record = daemon::request(socket, &ApiRequest::Status { id: id.to_string() })?
+            .job
+            .ok_or_else(|| anyhow!("the daemon did not return job '{}'", id))?;
+        if !matches!(record.status, JobStatus::Queued | JobStatus::Running) {
+            return Ok(record);
+        }
+        std::thread::sleep(Duration::from_secs(1));
+    }
+}
+
+fn print_job(record: &JobRecord) {
+    println!("Job {}: {}", record.id, record.status);
+    if let Some(ref hash) = record.attestation {
+        println!("Program attestation: {}", hash);
+    }
+    if let Some(ref error) = record.error {
+        println!("Error: {}", error);
+    }
+    for (name, value) in &record.outputs {
+        println!("  {} = {}", name, value);
+    }
+}
+
+fn net_profile(args: &Args) -> Result<Option<NetProfile>> {
+    args.net_profile.as_ref().map(NetProfile::load).transpose()
+}
+
+fn run_source(args: &Args, source: &PathBuf) -> Result<()> {
+    run_with(compile_options(args), source)
+}
+
+fn run_party(
+    args: &Args,
+    mut opts: CompileOptions,
+    source: Option<&PathBuf>,
+    cluster: Option<&ClusterConfig>,
+    runtime: SequreRuntimeConfig,
+    inputs: &[InputArg],
+    preflight: &PreflightArgs,
+) -> Result<()> {
+    opts.runtime = SequreRuntimeConfig {
+        skip_mhe_setup: opts.runtime.skip_mhe_setup,
+        ..runtime
+    };
+    let outcome = party::run_party(&PartyOptions {
+        compile: opts,
+        source: source.cloned(),
+        cluster: cluster.cloned(),
+        inputs: inputs
+            .iter()
+            .map(|input| (input.name.clone(), input.path.clone()))
+            .collect(),
+        preflight: !preflight.no_preflight,
+        check_only: preflight.check,
+        preflight_timeout: preflight.preflight_timeout.map(Duration::from_secs),
+        transcript_dir: args.record_transcript.clone(),
+        net_profile: net_profile(args)?,
+        comm_stats: args.comm_stats.is_some(),
+        mhe_keys: mhe_keys(args),
+    })?;
+    if let Some(ref run) = outcome.run {
+        print_run(run);
+        if let Some(format) = args.comm_stats {
+            print_comm_stats(&outcome.comm_stats, format)?;
+        }
+    }
+    for warning in &outcome.warnings {
+        eprintln!("warning: {}", warning);
+    }
+    Ok(())
+}
+
+fn replay_party(
+    args: &Args,
+    source: &PathBuf,
+    pid: usize,
+    transcript_dir: &Path,
+    port_base: u16,
+) -> Result<()> {
+    let meta = transcript::read_meta(transcript_dir, pid)?;
+    let replay = ReplayOptions {
+        transcript_dir: transcript_dir.to_path_buf(),
+        pid,
+        base_port: port_base,
+        connect_timeout: DEFAULT_PREFLIGHT_TIMEOUT,
+    };
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let _ = tx.send(transcript::replay_peers(&replay));
+    });
+
+    let mut opts = compile_options(args);
+    opts.runtime = SequreRuntimeConfig {
+        port_base: Some(port_base),
+        skip_mhe_setup: opts.runtime.skip_mhe_setup,
+        ..SequreRuntimeConfig::localhost(pid, meta.parties)
+    };
+    run_with(opts, source)?;
+
+    let channels = rx
+        .recv_timeout(Duration::from_secs(10))
+        .map_err(|_| anyhow!("replay peers did not finish"))??;
+    println!();
+    println!(
+        "{:<10} {:>10} {:>10} {:>10}  STATUS",
+        "CHANNEL", "DELIVERED", "RECORDED", "REPLAYED"
+    );
+    for c in &channels {
+        let status = match c.first_divergence {
+            _ if c.matches() => "match".to_string(),
+            Some(seq) => format!("differs from frame {}", seq),
+            None => "frame count differs".to_string(),
+        };
+        println!(
+            "{:<10} {:>10} {:>10} {:>10}  {}",
+            format!("{}_to_{}", pid, c.peer),
+            c.delivered,
+            c.recorded,
+            c.replayed,
+            status
+        );
+    }
+    println!(
+        "Replayed outbound messages: {}",
+        transcript::party_dir(transcript_dir, pid)
+            .join("replay")
+            .display()
+    );
+    if !channels.iter().all(|c| c.matches()) {
+        return Err(anyhow!("CP{} diverged from the recording", pid));
+    }
+    Ok(())
+}
+
+fn run_with(opts: CompileOptions, source: &PathBuf) -> Result<()> {
     let syqure = Syqure::new(opts);
-    let result = syqure.compile_and_maybe_run(source)?;
+    print_run(&syqure.compile_and_maybe_run(source)?);
+    Ok(())
+}
 
+fn print_run(result: &RunResult) {
     // Print captured output
     if !result.stdout.is_empty() {
         print!("{}", result.stdout);
@@ -121,13 +1354,95 @@ fn run_source(args: &Args, source: &PathBuf) -> Result<()> {
         eprint!("{}", result.stderr);
     }
 
-    if let Some(output) = result.output_path {
+    if let Some(ref output) = result.output_path {
         println!("Built executable at {}", output.display());
     }
+}
 
+fn print_comm_stats(stats: &[ChannelStats], format: StatsFormat) -> Result<()> {
+    if format == StatsFormat::Json {
+        println!("{}", serde_json::to_string_pretty(stats)?);
+        return Ok(());
+    }
+    println!();
+    if stats.is_empty() {
+        println!("No communication statistics (only collected with the file and tls transports)");
+        return Ok(());
+    }
+    println!(
+        "{:<10} {:>10} {:>14} {:>12} {:>10}",
+        "CHANNEL", "MESSAGES", "BYTES", "ROUND TRIPS", "WAIT"
+    );
+    for c in stats {
+        println!(
+            "{:<10} {:>10} {:>14} {:>12} {:>9.2}s",
+            format!("{}_to_{}", c.from, c.to),
+            c.messages,
+            c.bytes,
+            c.round_trips,
+            c.wait.as_secs_f64()
+        );
+    }
     Ok(())
 }
 
+fn report_parties(result: &MultiPartyResult) -> Result<()> {
+    println!();
+    if let Some(ref attestation) = result.attestation {
+        println!("Program attestation: {}", attestation.hash);
+        if !attestation.is_uniform() {
+            attestation.print_parties();
+        }
+    }
+    for party in &result.parties {
+        match (party.exit_code, &result.first_failure) {
+            (_, Some(failure)) if party.stopped => {
+                println!("CP{}: stopped after CP{} failed", party.pid, failure.pid)
+            }
+            (Some(code), _) => println!("CP{}: exit code {}", party.pid, code),
+            (None, _) => println!("CP{}: terminated by signal", party.pid),
+        }
+    }
+    for (pid, outputs) in &result.outputs {
+        for (name, value) in outputs {
+            println!("CP{}: {} = {}", pid, name, value);
+        }
+    }
+    for warning in &result.warnings {
+        eprintln!("warning: {}", warning);
+    }
+    if let Some(ref failure) = result.first_failure {
+        return Err(anyhow!("CP{} failed first: {}", failure.pid, failure.error));
+    }
+    if !result.success() {
+        return Err(anyhow!("one or more parties failed"));
+    }
+
+    Ok(())
+}
+
+fn check_party_outputs(result: &MultiPartyResult) -> Result<()> {
+    let mismatches = result.output_mismatches();
+    if mismatches.is_empty() {
+        if !result.outputs.is_empty() {
+            println!("Outputs agree across parties");
+        }
+        return Ok(());
+    }
+    for mismatch in &mismatches {
+        let values: Vec<String> = mismatch
+            .values
+            .iter()
+            .map(|(pid, value)| format!("CP{} = {}", pid, value))
+            .collect();
+        eprintln!("output {} differs: {}", mismatch.name, values.join(", "));
+    }
+    Err(anyhow!(
+        "{} output(s) differ between parties",
+        mismatches.len()
+    ))
+}
+
 fn print_info() {
     println!("syqure {}", VERSION);
     println!();
@@ -2,11 +2,15 @@ use std::collections::hash_map::DefaultHasher;
 use std::fs;
 use std::hash::{Hash, Hasher};
 use std::path::PathBuf;
+use std::sync::OnceLock;
 
 use anyhow::{anyhow, Result};
+use sha2::{Digest, Sha256};
 use tar::Archive;
 use zstd::stream::read::Decoder;
 
+use crate::attest::hex;
+
 #[cfg(not(feature = "runtime-bundle"))]
 const BUNDLE_BYTES: &[u8] = include_bytes!(env!("SYQURE_BUNDLE_FILE"));
 
@@ -15,7 +19,7 @@ const BUNDLE_BYTES: &[u8] = include_bytes!(env!("SYQURE_BUNDLE_FILE"));
 /// The hash-based subdirectory allows multiple versions to coexist.
 pub fn ensure_bundle() -> Result<PathBuf> {
     let bundle_bytes = load_bundle_bytes()?;
-    let sig = bundle_signature(&bundle_bytes);
+    let sig = cache_key(&bundle_bytes);
 
     let cache_dir =
         versioned_cache_dir(&sig).ok_or_else(|| anyhow!("cannot determine cache directory"))?;
@@ -108,8 +112,35 @@ fn load_bundle_bytes() -> Result<Vec<u8>> {
     }
 }
 
-fn bundle_signature(bytes: &[u8]) -> String {
+/// SHA-256 of the bundle this build uses, as attested and compared between
+/// parties. Computed once per process.
+pub fn signature() -> Result<String> {
+    static SIGNATURE: OnceLock<String> = OnceLock::new();
+    if let Some(signature) = SIGNATURE.get() {
+        return Ok(signature.clone());
+    }
+    let signature = hex(&Sha256::digest(load_bundle_bytes()?));
+    Ok(SIGNATURE.get_or_init(|| signature).clone())
+}
+
+/// Names the bundle's cache dir. Not stable across Rust releases and not
+/// collision resistant, so it is never used to compare bundles.
+fn cache_key(bytes: &[u8]) -> String {
     let mut hasher = DefaultHasher::new();
     bytes.hash(&mut hasher);
     format!("{:x}-{}", hasher.finish(), bytes.len())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signature_is_the_sha256_of_the_bundle() {
+        let bytes = load_bundle_bytes().unwrap();
+        let first = signature().unwrap();
+        assert_eq!(first.len(), 64);
+        assert_eq!(first, hex(&Sha256::digest(&bytes)));
+        assert_eq!(signature().unwrap(), first);
+    }
+}
@@ -0,0 +1,856 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::attest::{attest_job, JobAttestation};
+use crate::config::{
+    channel_port, channel_span, SequreRuntimeConfig, DEFAULT_PORT_BASE, MIN_PARTIES,
+};
+use crate::inputs::check_input_name;
+use crate::mhe::{KeyProtection, MheKeyConfig};
+use crate::runner::CompileOptions;
+use crate::transport::codec::{DEFAULT_CHUNK_SIZE, DEFAULT_COMPRESS_THRESHOLD};
+use crate::transport::envelope::MessageKey;
+use crate::transport::file::FileProxyOptions;
+use crate::transport::state::{check_job_id, Retention};
+use crate::transport::tls::{cert_fingerprint, TlsPeer, TlsRelayOptions};
+
+/// Default name of the cluster description file.
+pub const DEFAULT_CLUSTER_FILE: &str = "syqure.toml";
+
+/// Default port of each party's TLS relay.
+pub const DEFAULT_RELAY_PORT: u16 = 9443;
+
+/// How parties exchange messages.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    /// Sequre's own TCP sockets between party hosts.
+    #[default]
+    Tcp,
+    /// TCP to a local `syqure proxy`, which relays through a shared message directory.
+    File,
+    /// TCP to a local relay, which carries traffic between parties over mutually
+    /// authenticated TLS.
+    Tls,
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Transport::Tcp => write!(f, "tcp"),
+            Transport::File => write!(f, "file"),
+            Transport::Tls => write!(f, "tls"),
+        }
+    }
+}
+
+/// Declarative description of a multi-party job (`syqure.toml`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClusterConfig {
+    pub job: JobConfig,
+    #[serde(rename = "party", default)]
+    pub parties: Vec<PartyConfig>,
+}
+
+/// The `[job]` table: settings shared by every party.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JobConfig {
+    /// Optional job name, used in logs.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Path to the .codon source file (parties may override it).
+    pub source: PathBuf,
+    /// Program arguments passed to every party.
+    #[serde(default)]
+    pub program_args: Vec<String>,
+    /// Skip MHE (homomorphic encryption) setup for MPC-only programs.
+    #[serde(default)]
+    pub skip_mhe_setup: bool,
+    /// Cache MHE keys across runs (programs opt in through `syqure_mhe`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mhe_keys: Option<MheKeyConfig>,
+    #[serde(default)]
+    pub transport: Transport,
+    /// Port base shared by all parties (TCP) or used by parties without their own.
+    #[serde(default = "default_port_base")]
+    pub port_base: u16,
+    /// Shared message directory for the file transport.
+    #[serde(default)]
+    pub message_dir: Option<PathBuf>,
+    /// Keeps this job's files in `message_dir/{id}` so several jobs can share
+    /// one synced folder.
+    #[serde(default)]
+    pub id: Option<String>,
+    /// What to do with delivered message files: `delete` or `archive`.
+    #[serde(default)]
+    pub retention: Retention,
+    /// Key file shared by every party; seals file-transport messages per channel.
+    #[serde(default)]
+    pub message_key: Option<PathBuf>,
+    /// Compress file-transport frames of at least this many bytes (0 disables).
+    #[serde(default = "default_compress_threshold")]
+    pub compress_threshold: usize,
+    /// Split file-transport messages larger than this many bytes into chunk
+    /// files (0 disables).
+    #[serde(default = "default_chunk_size")]
+    pub chunk_size: usize,
+    /// Poll interval for inbound message files, in milliseconds.
+    #[serde(default = "default_poll_ms")]
+    pub poll_ms: u64,
+    /// Pick up message files through inotify on Linux; polling otherwise.
+    #[serde(default = "default_true")]
+    pub inotify: bool,
+    /// How long to wait for peers to come up, in seconds.
+    #[serde(default = "default_connect_timeout_secs")]
+    pub connect_timeout_secs: u64,
+    /// Upper bound on the whole run, in seconds (unbounded when unset).
+    #[serde(default)]
+    pub run_timeout_secs: Option<u64>,
+    /// TLS relay port for parties without their own.
+    #[serde(default = "default_relay_port")]
+    pub relay_port: u16,
+}
+
+/// One `[[party]]` entry.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PartyConfig {
+    pub pid: usize,
+    /// Address other parties use to reach this one.
+    #[serde(default = "default_host")]
+    pub host: String,
+    /// Program this party runs instead of `[job].source`.
+    #[serde(default)]
+    pub source: Option<PathBuf>,
+    /// Program arguments for this party only, after `[job].program_args`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub program_args: Vec<String>,
+    /// Port base for this party's Sequre process (file and tls transports).
+    #[serde(default)]
+    pub port_base: Option<u16>,
+    /// Port this party's TLS relay listens on.
+    #[serde(default)]
+    pub relay_port: Option<u16>,
+    /// PEM certificate identifying this party on TLS links.
+    #[serde(default)]
+    pub cert: Option<PathBuf>,
+    /// PEM private key for `cert`; only needed on the party's own machine.
+    #[serde(default)]
+    pub key: Option<PathBuf>,
+    /// SHA-256 of the DER certificate, pinned instead of (or as well as) `cert`.
+    #[serde(default)]
+    pub cert_sha256: Option<String>,
+    /// Working directory for this party's process.
+    #[serde(default)]
+    pub data_dir: Option<PathBuf>,
+    /// Private input files, by name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub inputs: BTreeMap<String, PathBuf>,
+}
+
+fn default_port_base() -> u16 {
+    DEFAULT_PORT_BASE
+}
+
+fn default_compress_threshold() -> usize {
+    DEFAULT_COMPRESS_THRESHOLD
+}
+
+fn default_chunk_size() -> usize {
+    DEFAULT_CHUNK_SIZE
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_poll_ms() -> u64 {
+    10
+}
+
+fn default_connect_timeout_secs() -> u64 {
+    120
+}
+
+fn default_relay_port() -> u16 {
+    DEFAULT_RELAY_PORT
+}
+
+fn default_host() -> String {
+    "127.0.0.1".to_string()
+}
+
+/// Every consistency problem found in a cluster config.
+#[derive(Debug, thiserror::Error)]
+#[error("invalid cluster config:\n  - {}", .0.join("\n  - "))]
+pub struct ClusterError(pub Vec<String>);
+
+impl ClusterConfig {
+    /// Parse a cluster file; relative paths are resolved against its directory.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let mut config: ClusterConfig =
+            toml::from_str(&text).with_context(|| format!("failed to parse {}", path.display()))?;
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+        config.resolve_paths(&dir);
+        config.parties.sort_by_key(|p| p.pid);
+        Ok(config)
+    }
+
+    fn resolve_paths(&mut self, base: &Path) {
+        let resolve = |p: &mut PathBuf| {
+            if p.is_relative() {
+                *p = base.join(&*p);
+            }
+        };
+        resolve(&mut self.job.source);
+        for path in [&mut self.job.message_dir, &mut self.job.message_key] {
+            if let Some(path) = path.as_mut() {
+                resolve(path);
+            }
+        }
+        if let Some(keys) = self.job.mhe_keys.as_mut() {
+            for path in [&mut keys.dir, &mut keys.seal_key] {
+                if let Some(path) = path.as_mut() {
+                    resolve(path);
+                }
+            }
+        }
+        for party in &mut self.parties {
+            for path in [
+                &mut party.source,
+                &mut party.data_dir,
+                &mut party.cert,
+                &mut party.key,
+            ] {
+                if let Some(path) = path.as_mut() {
+                    resolve(path);
+                }
+            }
+            party.inputs.values_mut().for_each(resolve);
+        }
+    }
+
+    pub fn party_count(&self) -> usize {
+        self.parties.len()
+    }
+
+    pub fn party(&self, pid: usize) -> Option<&PartyConfig> {
+        self.parties.iter().find(|p| p.pid == pid)
+    }
+
+    /// Program party `pid` runs.
+    pub fn source(&self, pid: usize) -> &Path {
+        self.party(pid)
+            .and_then(|p| p.source.as_deref())
+            .unwrap_or(&self.job.source)
+    }
+
+    /// `base` with party `pid`'s program arguments (job, then party, then
+    /// `base`'s own) and the job's `skip_mhe_setup` applied.
+    pub fn compile_options(&self, pid: usize, base: &CompileOptions) -> CompileOptions {
+        let mut opts = base.clone();
+        opts.program_args = self.job.program_args.clone();
+        if let Some(party) = self.party(pid) {
+            opts.program_args.extend(party.program_args.iter().cloned());
+        }
+        opts.program_args.extend(base.program_args.iter().cloned());
+        opts.runtime.skip_mhe_setup |= self.job.skip_mhe_setup;
+        opts
+    }
+
+    /// Attest every party's program as compiled from `base`; see [`attest_job`].
+    pub fn attest(&self, base: &CompileOptions) -> Result<JobAttestation> {
+        attest_job(self.parties.iter().map(|p| {
+            (
+                p.pid,
+                self.source(p.pid).to_path_buf(),
+                self.compile_options(p.pid, base),
+            )
+        }))
+    }
+
+    /// MHE key caching for this job: `[job.mhe_keys]` with `site`'s settings
+    /// on top, grouped by the job name unless a group is set. None when neither
+    /// asks for it.
+    pub fn mhe_keys(&self, site: Option<&MheKeyConfig>) -> Option<MheKeyConfig> {
+        let mut keys = match (&self.job.mhe_keys, site) {
+            (None, None) => return None,
+            (Some(job), None) => job.clone(),
+            (None, Some(site)) => site.clone(),
+            (Some(job), Some(site)) => MheKeyConfig {
+                dir: site.dir.clone().or_else(|| job.dir.clone()),
+                group: site.group.clone().or_else(|| job.group.clone()),
+                protection: match site.seal_key {
+                    Some(_) => KeyProtection::Sealed,
+                    None => job.protection,
+                },
+                seal_key: site.seal_key.clone().or_else(|| job.seal_key.clone()),
+                max_age_days: site.max_age_days.or(job.max_age_days),
+            },
+        };
+        if keys.group.is_none() {
+            keys.group = self.job.name.clone();
+        }
+        Some(keys)
+    }
+
+    /// Port base of party `pid`'s Sequre process.
+    pub fn port_base(&self, pid: usize) -> u16 {
+        self.party(pid)
+            .and_then(|p| p.port_base)
+            .unwrap_or(self.job.port_base)
+    }
+
+    /// Ports the job takes when every party runs on one host: the channel ports,
+    /// once per party unless Sequre connects directly, plus the TLS relays.
+    pub fn local_port_span(&self) -> usize {
+        let n = self.party_count();
+        match self.job.transport {
+            Transport::Tcp => channel_span(n),
+            Transport::File => n * channel_span(n),
+            Transport::Tls => n * channel_span(n) + n,
+        }
+    }
+
+    /// Move the job onto the [`Self::local_port_span`] ports starting at `base`.
+    pub fn rebase_ports(&mut self, base: u16) {
+        let n = self.party_count();
+        let span = channel_span(n);
+        self.job.port_base = base;
+        for party in &mut self.parties {
+            party.port_base = match self.job.transport {
+                Transport::Tcp => None,
+                Transport::File | Transport::Tls => Some(base + (party.pid * span) as u16),
+            };
+            if self.job.transport == Transport::Tls {
+                party.relay_port = Some(base + (n * span + party.pid) as u16);
+            }
+        }
+    }
+
+    pub fn connect_timeout(&self) -> Duration {
+        Duration::from_secs(self.job.connect_timeout_secs)
+    }
+
+    pub fn run_timeout(&self) -> Option<Duration> {
+        self.job.run_timeout_secs.map(Duration::from_secs)
+    }
+
+    /// Runtime settings for party `pid`.
+    pub fn runtime_config(&self, pid: usize) -> SequreRuntimeConfig {
+        let cp_ips = match self.job.transport {
+            Transport::Tcp => self.parties.iter().map(|p| p.host.clone()).collect(),
+            // Sequre only ever talks to its local proxy or relay.
+            Transport::File | Transport::Tls => {
+                vec!["127.0.0.1".to_string(); self.party_count()]
+            }
+        };
+        SequreRuntimeConfig {
+            pid: Some(pid),
+            cp_ips,
+            parties: Some(self.party_count()),
+            port_base: Some(self.port_base(pid)),
+            skip_mhe_setup: self.job.skip_mhe_setup,
+            ..SequreRuntimeConfig::default()
+        }
+    }
+
+    /// Proxy settings for party `pid`, or None unless the job uses the file transport.
+    pub fn proxy_options(&self, pid: usize) -> Option<FileProxyOptions> {
+        if self.job.transport != Transport::File {
+            return None;
+        }
+        Some(FileProxyOptions {
+            pid,
+            parties: self.party_count(),
+            data_dir: self.job.message_dir.clone()?,
+            base_port: self.port_base(pid),
+            poll_interval: Duration::from_millis(self.job.poll_ms),
+            use_inotify: self.job.inotify,
+            connect_timeout: self.connect_timeout(),
+            program_hash: None,
+            transcript_dir: None,
+            net_profile: None,
+            job_id: self.job.id.clone(),
+            retention: self.job.retention,
+            message_key: self.job.message_key.clone(),
+            compress_threshold: (self.job.compress_threshold > 0)
+                .then_some(self.job.compress_threshold),
+            chunk_size: (self.job.chunk_size > 0).then_some(self.job.chunk_size),
+        })
+    }
+
+    /// Port party `pid`'s TLS relay listens on.
+    pub fn relay_port(&self, pid: usize) -> u16 {
+        self.party(pid)
+            .and_then(|p| p.relay_port)
+            .unwrap_or(self.job.relay_port)
+    }
+
+    /// Pinned certificate hash of party `pid`.
+    pub fn fingerprint(&self, pid: usize) -> Result<String> {
+        let party = self
+            .party(pid)
+            .ok_or_else(|| anyhow!("no party with pid {}", pid))?;
+        match (&party.cert_sha256, &party.cert) {
+            (Some(hash), _) => Ok(hash.to_ascii_lowercase()),
+            (None, Some(cert)) => cert_fingerprint(cert),
+            (None, None) => Err(anyhow!("CP{} has neither cert nor cert_sha256", pid)),
+        }
+    }
+
+    /// Relay settings for party `pid`, or None unless the job uses the tls transport.
+    pub fn tls_relay_options(&self, pid: usize) -> Result<Option<TlsRelayOptions>> {
+        if self.job.transport != Transport::Tls {
+            return Ok(None);
+        }
+        let party = self
+            .party(pid)
+            .ok_or_else(|| anyhow!("no party with pid {}", pid))?;
+        let (Some(cert), Some(key)) = (&party.cert, &party.key) else {
+            return Err(anyhow!(
+                "CP{} needs cert and key for the tls transport",
+                pid
+            ));
+        };
+        let mut peers = Vec::new();
+        for peer in self.parties.iter().filter(|p| p.pid != pid) {
+            peers.push(TlsPeer {
+                pid: peer.pid,
+                address: format!("{}:{}", peer.host, self.relay_port(peer.pid)),
+                fingerprint: self.fingerprint(peer.pid)?,
+            });
+        }
+        Ok(Some(TlsRelayOptions {
+            pid,
+            parties: self.party_count(),
+            base_port: self.port_base(pid),
+            listen_port: self.relay_port(pid),
+            cert: cert.clone(),
+            key: key.clone(),
+            peers,
+            connect_timeout: self.connect_timeout(),
+            program_hash: None,
+        }))
+    }
+
+    /// Check the config for consistency, reporting every problem at once.
+    pub fn validate(&self) -> Result<(), ClusterError> {
+        let mut problems = Vec::new();
+        let n = self.party_count();
+
+        if !self.job.source.exists() {
+            problems.push(format!(
+                "source {} does not exist",
+                self.job.source.display()
+            ));
+        }
+        if n < MIN_PARTIES {
+            problems.push(format!(
+                "Sequre requires at least {} parties, {} declared",
+                MIN_PARTIES, n
+            ));
+        }
+        for (expected, party) in self.parties.iter().enumerate() {
+            if party.pid != expected {
+                problems.push(format!(
+                    "party pids must be 0..{} without gaps or duplicates (found pid {})",
+                    n, party.pid
+                ));
+                break;
+            }
+        }
+        if self.job.poll_ms == 0 {
+            problems.push("poll_ms must be positive".to_string());
+        }
+
+        if let Some(ref id) = self.job.id {
+            if let Err(e) = check_job_id(id) {
+                problems.push(e.to_string());
+            }
+        }
+        if let Some(keys) = self.mhe_keys(None) {
+            if let Err(e) = keys.validate() {
+                problems.push(format!("{:#}", e));
+            }
+        }
+        if self.job.message_key.is_some() && self.job.transport != Transport::File {
+            problems.push("message_key is only used by the file transport".to_string());
+        }
+        match self.job.transport {
+            Transport::Tcp => {
+                if self.parties.iter().any(|p| p.port_base.is_some()) {
+                    problems.push(
+                        "per-party port_base is only supported with the file and tls transports; \
+                         TCP parties must share [job].port_base"
+                            .to_string(),
+                    );
+                }
+            }
+            Transport::File => {
+                if self.job.message_dir.is_none() {
+                    problems.push("file transport requires [job].message_dir".to_string());
+                }
+                if let Some(ref key) = self.job.message_key {
+                    if let Err(e) = MessageKey::load(key) {
+                        problems.push(format!("{:#}", e));
+                    }
+                    if self.job.id.is_none() {
+                        problems.push(
+                            "message_key requires [job].id, so runs sealed with the same key \
+                             cannot replay each other's messages"
+                                .to_string(),
+                        );
+                    }
+                }
+                self.check_port_overlap(&mut problems);
+            }
+            Transport::Tls => {
+                self.check_port_overlap(&mut problems);
+                self.check_tls(&mut problems);
+            }
+        }
+
+        for party in &self.parties {
+            if let Some(ref source) = party.source {
+                if !source.exists() {
+                    problems.push(format!(
+                        "CP{}: source {} does not exist",
+                        party.pid,
+                        source.display()
+                    ));
+                }
+            }
+            if party.host.trim().is_empty() {
+                problems.push(format!("CP{}: host is empty", party.pid));
+            }
+            if n >= MIN_PARTIES && party.pid < n {
+                if let Err(e) = self.runtime_config(party.pid).validate() {
+                    problems.push(format!("CP{}: {}", party.pid, e));
+                }
+            }
+            // Input files only exist on their owner's host; they are checked
+            // when that party stages them.
+            for name in party.inputs.keys() {
+                if let Err(e) = check_input_name(name) {
+                    problems.push(format!("CP{}: {}", party.pid, e));
+                }
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ClusterError(problems))
+        }
+    }
+
+    fn check_tls(&self, problems: &mut Vec<String>) {
+        let n = self.party_count();
+        for party in &self.parties {
+            match (&party.cert_sha256, &party.cert) {
+                (Some(hash), _) => {
+                    if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                        problems.push(format!(
+                            "CP{}: cert_sha256 is not a SHA-256 hex digest",
+                            party.pid
+                        ));
+                    }
+                }
+                (None, Some(cert)) => {
+                    if let Err(e) = cert_fingerprint(cert) {
+                        problems.push(format!("CP{}: {:#}", party.pid, e));
+                    }
+                }
+                (None, None) => problems.push(format!(
+                    "CP{}: tls transport requires cert or cert_sha256",
+                    party.pid
+                )),
+            }
+            // The relay and its own Sequre process share a host and must not collide.
+            let relay = self.relay_port(party.pid);
+            for other in self.parties.iter().filter(|o| o.host == party.host) {
+                let base = self.port_base(other.pid);
+                let last = channel_port(base, n, n.saturating_sub(2), n.saturating_sub(1))
+                    .unwrap_or(u16::MAX);
+                if (base..=last).contains(&relay) {
+                    problems.push(format!(
+                        "CP{}: relay port {} falls inside CP{}'s Sequre ports {}-{}",
+                        party.pid, relay, other.pid, base, last
+                    ));
+                }
+                if other.pid > party.pid && self.relay_port(other.pid) == relay {
+                    problems.push(format!(
+                        "CP{} and CP{} share host {} and relay port {}",
+                        party.pid, other.pid, party.host, relay
+                    ));
+                }
+            }
+        }
+    }
+
+    /// With the file and tls transports, parties sharing a host need disjoint port ranges.
+    fn check_port_overlap(&self, problems: &mut Vec<String>) {
+        let n = self.party_count();
+        if n < 2 {
+            return;
+        }
+        let range = |pid: usize| {
+            let base = self.port_base(pid);
+            let last = channel_port(base, n, n - 2, n - 1).unwrap_or(u16::MAX);
+            (base, last)
+        };
+        for a in &self.parties {
+            for b in self.parties.iter().filter(|b| b.pid > a.pid) {
+                if a.host != b.host {
+                    continue;
+                }
+                let ((a_lo, a_hi), (b_lo, b_hi)) = (range(a.pid), range(b.pid));
+                if a_lo <= b_hi && b_lo <= a_hi {
+                    problems.push(format!(
+                        "CP{} and CP{} share host {} with overlapping ports {}-{} and {}-{}",
+                        a.pid, b.pid, a.host, a_lo, a_hi, b_lo, b_hi
+                    ));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PIN: &str = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "syqure-cluster-test-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("prog.codon"), "").unwrap();
+        dir
+    }
+
+    fn load(dir: &Path, text: &str) -> ClusterConfig {
+        let path = dir.join(DEFAULT_CLUSTER_FILE);
+        std::fs::write(&path, text).unwrap();
+        ClusterConfig::load(&path).unwrap()
+    }
+
+    fn parties(entries: &[&str]) -> String {
+        entries
+            .iter()
+            .map(|entry| format!("[[party]]\n{}\n", entry))
+            .collect()
+    }
+
+    #[test]
+    fn validate_reports_each_problem() {
+        let dir = scratch("validate");
+        let file = "[job]\nsource = \"prog.codon\"\ntransport = \"file\"\nmessage_dir = \"m\"\n";
+        let tls = "[job]\nsource = \"prog.codon\"\ntransport = \"tls\"\n";
+        let cases = [
+            (
+                "duplicate pids",
+                format!(
+                    "[job]\nsource = \"prog.codon\"\n{}",
+                    parties(&["pid = 0", "pid = 1", "pid = 1"])
+                ),
+                "without gaps or duplicates (found pid 1)",
+            ),
+            (
+                "pid gap",
+                format!(
+                    "[job]\nsource = \"prog.codon\"\n{}",
+                    parties(&["pid = 0", "pid = 1", "pid = 3"])
+                ),
+                "without gaps or duplicates (found pid 3)",
+            ),
+            (
+                "tls party without a pin",
+                format!(
+                    "{}{}",
+                    tls,
+                    parties(&[
+                        &format!("pid = 0\nport_base = 10000\nrelay_port = 9443\ncert_sha256 = \"{}\"", PIN),
+                        &format!("pid = 1\nport_base = 11000\nrelay_port = 9444\ncert_sha256 = \"{}\"", PIN),
+                        "pid = 2\nport_base = 12000\nrelay_port = 9445",
+                    ])
+                ),
+                "CP2: tls transport requires cert or cert_sha256",
+            ),
+            (
+                "file transport without message_dir",
+                format!(
+                    "[job]\nsource = \"prog.codon\"\ntransport = \"file\"\n{}",
+                    parties(&[
+                        "pid = 0\nport_base = 10000",
+                        "pid = 1\nport_base = 11000",
+                        "pid = 2\nport_base = 12000",
+                    ])
+                ),
+                "file transport requires [job].message_dir",
+            ),
+            (
+                "overlapping ports",
+                format!(
+                    "{}{}",
+                    file,
+                    parties(&[
+                        "pid = 0\nport_base = 10000",
+                        "pid = 1\nport_base = 10002",
+                        "pid = 2\nport_base = 12000",
+                    ])
+                ),
+                "CP0 and CP1 share host 127.0.0.1 with overlapping ports 10000-10003 and 10002-10005",
+            ),
+            (
+                "relay inside Sequre ports",
+                format!(
+                    "{}{}",
+                    tls,
+                    parties(&[
+                        &format!("pid = 0\nport_base = 10000\nrelay_port = 11001\ncert_sha256 = \"{}\"", PIN),
+                        &format!("pid = 1\nport_base = 11000\nrelay_port = 9444\ncert_sha256 = \"{}\"", PIN),
+                        &format!("pid = 2\nport_base = 12000\nrelay_port = 9445\ncert_sha256 = \"{}\"", PIN),
+                    ])
+                ),
+                "CP0: relay port 11001 falls inside CP1's Sequre ports 11000-11003",
+            ),
+        ];
+        for (name, text, expected) in cases {
+            let problems = load(&dir, &text).validate().unwrap_err().0;
+            assert!(
+                problems.iter().any(|p| p.contains(expected)),
+                "{}: {:?}",
+                name,
+                problems
+            );
+        }
+
+        let valid = format!(
+            "{}{}",
+            file,
+            parties(&[
+                "pid = 0\nport_base = 10000",
+                "pid = 1\nport_base = 10004",
+                "pid = 2\nport_base = 10008",
+            ])
+        );
+        load(&dir, &valid).validate().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn relative_paths_resolve_against_the_config_dir() {
+        let dir = scratch("paths");
+        let config = load(
+            &dir,
+            &format!(
+                "[job]\nsource = \"prog.codon\"\nmessage_dir = \"shared/messages\"\n\
+                 message_key = \"/etc/syqure/message.key\"\n\n\
+                 [job.mhe_keys]\ndir = \"keys\"\n{}",
+                parties(&[
+                    "pid = 2\ndata_dir = \"cp2\"\ninputs = { value = \"inputs/cp2.csv\" }",
+                    "pid = 0\nsource = \"../dealer.codon\"\ncert = \"certs/cp0.pem\"",
+                    "pid = 1\ndata_dir = \"/srv/cp1\"",
+                ])
+            ),
+        );
+        let base = dir.canonicalize().unwrap();
+        assert_eq!(config.job.source, base.join("prog.codon"));
+        assert_eq!(config.job.message_dir, Some(base.join("shared/messages")));
+        assert_eq!(
+            config.job.message_key,
+            Some(PathBuf::from("/etc/syqure/message.key"))
+        );
+        assert_eq!(
+            config.job.mhe_keys.as_ref().unwrap().dir,
+            Some(base.join("keys"))
+        );
+        let pids: Vec<usize> = config.parties.iter().map(|p| p.pid).collect();
+        assert_eq!(pids, [0, 1, 2]);
+        assert_eq!(config.source(0), base.join("../dealer.codon"));
+        assert_eq!(config.source(1), base.join("prog.codon"));
+        assert_eq!(config.parties[0].cert, Some(base.join("certs/cp0.pem")));
+        assert_eq!(config.parties[1].data_dir, Some(PathBuf::from("/srv/cp1")));
+        assert_eq!(config.parties[2].data_dir, Some(base.join("cp2")));
+        assert_eq!(
+            config.parties[2].inputs["value"],
+            base.join("inputs/cp2.csv")
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rebase_ports_packs_the_job_after_base() {
+        let dir = scratch("rebase");
+        let entries = [
+            format!(
+                "pid = 0\nport_base = 10000\nrelay_port = 9443\ncert_sha256 = \"{}\"",
+                PIN
+            ),
+            format!(
+                "pid = 1\nport_base = 10000\nrelay_port = 9443\ncert_sha256 = \"{}\"",
+                PIN
+            ),
+            format!(
+                "pid = 2\nport_base = 10000\nrelay_port = 9443\ncert_sha256 = \"{}\"",
+                PIN
+            ),
+        ];
+        let entries: Vec<&str> = entries.iter().map(String::as_str).collect();
+        let mut config = load(
+            &dir,
+            &format!(
+                "[job]\nsource = \"prog.codon\"\ntransport = \"tls\"\n{}",
+                parties(&entries)
+            ),
+        );
+        assert!(config.validate().is_err());
+
+        config.rebase_ports(20000);
+        assert_eq!(config.local_port_span(), 3 * 4 + 3);
+        let bases: Vec<u16> = (0..3).map(|pid| config.port_base(pid)).collect();
+        let relays: Vec<u16> = (0..3).map(|pid| config.relay_port(pid)).collect();
+        assert_eq!(bases, [20000, 20004, 20008]);
+        assert_eq!(relays, [20012, 20013, 20014]);
+        config.validate().unwrap();
+
+        // The file transport needs no relays; TCP parties share the job's base.
+        config.job.transport = Transport::File;
+        config.job.message_dir = Some(dir.join("messages"));
+        config.parties.iter_mut().for_each(|p| p.relay_port = None);
+        config.rebase_ports(30000);
+        assert_eq!(config.local_port_span(), 12);
+        assert_eq!(config.port_base(2), 30008);
+        assert_eq!(config.parties[2].relay_port, None);
+        config.validate().unwrap();
+
+        config.job.transport = Transport::Tcp;
+        config.rebase_ports(40000);
+        assert_eq!(config.local_port_span(), 4);
+        assert!(config.parties.iter().all(|p| p.port_base.is_none()));
+        assert_eq!(config.port_base(1), 40000);
+        config.validate().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
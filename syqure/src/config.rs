@@ -0,0 +1,269 @@
+use std::path::PathBuf;
+use std::process::Command;
+
+use thiserror::Error;
+
+use crate::inputs::ENV_INPUT_DIR;
+use crate::mhe::ENV_MHE_KEY_DIR;
+use crate::results::ENV_RESULTS_FILE;
+
+/// Environment variables read by Sequre's `constants.codon`.
+pub const ENV_CP_IPS: &str = "SEQURE_CP_IPS";
+pub const ENV_CP_COUNT: &str = "SEQURE_CP_COUNT";
+pub const ENV_PORT_BASE: &str = "SEQURE_PORT_BASE";
+pub const ENV_GMP_PATH: &str = "SEQURE_GMP_PATH";
+
+/// Sequre refuses to run with fewer parties (trusted dealer plus two compute parties).
+pub const MIN_PARTIES: usize = 3;
+/// Sequre's default COMMUNICATION_PORT.
+pub const DEFAULT_PORT_BASE: u16 = 9000;
+/// Party count Sequre assumes when neither SEQURE_CP_COUNT nor SEQURE_CP_IPS is set.
+pub const DEFAULT_PARTIES: usize = 3;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ConfigError {
+    #[error("Sequre requires at least {MIN_PARTIES} parties, got {0}")]
+    TooFewParties(usize),
+    #[error("{ips} peer IPs given for {parties} parties")]
+    IpCountMismatch { ips: usize, parties: usize },
+    #[error("peer IP for CP{0} is empty")]
+    EmptyIp(usize),
+    #[error("pid {pid} is out of range for {parties} parties")]
+    PidOutOfRange { pid: usize, parties: usize },
+    #[error("CP{0} needs peer IPs (SEQURE_CP_IPS) to run outside @local")]
+    MissingPeers(usize),
+    #[error("port base {base} leaves no room for {parties} parties")]
+    PortRangeOverflow { base: u16, parties: usize },
+}
+
+/// Per-party runtime settings Sequre otherwise picks up from ad hoc env vars.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SequreRuntimeConfig {
+    /// This party's id. None for `@local` programs that fork every party themselves.
+    pub pid: Option<usize>,
+    /// IP of every party, indexed by pid (SEQURE_CP_IPS).
+    pub cp_ips: Vec<String>,
+    /// Explicit party count (SEQURE_CP_COUNT); implied by `cp_ips` when empty.
+    pub parties: Option<usize>,
+    /// First port used for party-to-party channels (SEQURE_PORT_BASE).
+    pub port_base: Option<u16>,
+    /// Path Sequre dlopens for GMP (SEQURE_GMP_PATH).
+    pub gmp_path: Option<PathBuf>,
+    /// Skip MHE (homomorphic encryption) setup for MPC-only programs.
+    pub skip_mhe_setup: bool,
+    /// This party's staged private inputs (SYQURE_INPUT_DIR).
+    pub input_dir: Option<PathBuf>,
+    /// File this party's named outputs go to (SYQURE_RESULTS_FILE).
+    pub results_file: Option<PathBuf>,
+    /// Where this party's MHE keys are cached (SYQURE_MHE_KEY_DIR).
+    pub mhe_key_dir: Option<PathBuf>,
+}
+
+impl SequreRuntimeConfig {
+    /// Config for party `pid` of a job whose parties all run on this host.
+    pub fn localhost(pid: usize, parties: usize) -> Self {
+        Self {
+            pid: Some(pid),
+            cp_ips: vec!["127.0.0.1".to_string(); parties],
+            parties: Some(parties),
+            ..Self::default()
+        }
+    }
+
+    /// Number of parties Sequre will see once this config is applied.
+    pub fn party_count(&self) -> usize {
+        if !self.cp_ips.is_empty() {
+            return self.cp_ips.len();
+        }
+        self.parties.unwrap_or(DEFAULT_PARTIES)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let parties = self.party_count();
+        if let Some(explicit) = self.parties {
+            if !self.cp_ips.is_empty() && self.cp_ips.len() != explicit {
+                return Err(ConfigError::IpCountMismatch {
+                    ips: self.cp_ips.len(),
+                    parties: explicit,
+                });
+            }
+        }
+        if parties < MIN_PARTIES {
+            return Err(ConfigError::TooFewParties(parties));
+        }
+        if let Some(pid) = self.cp_ips.iter().position(|ip| ip.trim().is_empty()) {
+            return Err(ConfigError::EmptyIp(pid));
+        }
+        if let Some(pid) = self.pid {
+            if pid >= parties {
+                return Err(ConfigError::PidOutOfRange { pid, parties });
+            }
+            if self.cp_ips.is_empty() {
+                return Err(ConfigError::MissingPeers(pid));
+            }
+        }
+        if let Some(base) = self.port_base {
+            let last = channel_port(base, parties, parties - 2, parties - 1);
+            if last.is_none() {
+                return Err(ConfigError::PortRangeOverflow { base, parties });
+            }
+        }
+        Ok(())
+    }
+
+    /// Environment variables to export for the Sequre program. Unset fields are
+    /// left out so values already present in the environment still apply.
+    pub fn env_vars(&self) -> Vec<(&'static str, String)> {
+        let mut vars = Vec::new();
+        if !self.cp_ips.is_empty() {
+            vars.push((ENV_CP_IPS, self.cp_ips.join(",")));
+        }
+        if self.parties.is_some() || !self.cp_ips.is_empty() {
+            vars.push((ENV_CP_COUNT, self.party_count().to_string()));
+        }
+        if let Some(base) = self.port_base {
+            vars.push((ENV_PORT_BASE, base.to_string()));
+        }
+        if let Some(ref gmp) = self.gmp_path {
+            vars.push((ENV_GMP_PATH, gmp.to_string_lossy().into_owned()));
+        }
+        if let Some(ref dir) = self.input_dir {
+            vars.push((ENV_INPUT_DIR, dir.to_string_lossy().into_owned()));
+        }
+        if let Some(ref file) = self.results_file {
+            vars.push((ENV_RESULTS_FILE, file.to_string_lossy().into_owned()));
+        }
+        if let Some(ref dir) = self.mhe_key_dir {
+            vars.push((ENV_MHE_KEY_DIR, dir.to_string_lossy().into_owned()));
+        }
+        vars
+    }
+
+    /// Export the config into this process (for in-process runs via FFI).
+    pub fn export(&self) {
+        for (key, value) in self.env_vars() {
+            std::env::set_var(key, value);
+        }
+    }
+
+    /// Export the config into a child process.
+    pub fn apply(&self, cmd: &mut Command) {
+        cmd.envs(self.env_vars());
+    }
+
+    /// Full program arguments: runtime flags, then the pid Sequre expects as its
+    /// first positional argument, then the user's arguments in their own order.
+    pub fn program_args(&self, extra: &[String]) -> Vec<String> {
+        let mut args = Vec::new();
+        if self.skip_mhe_setup && !extra.iter().any(|a| a == "--skip-mhe-setup") {
+            args.push("--skip-mhe-setup".to_string());
+        }
+        if let Some(pid) = self.pid {
+            args.push(pid.to_string());
+        }
+        args.extend(extra.iter().cloned());
+        args
+    }
+}
+
+/// Port of the channel between parties `a` and `b`, mirroring Sequre's layout:
+/// `base + (min * n - min * (min + 1) / 2) + (max - min)`.
+pub fn channel_port(base: u16, parties: usize, a: usize, b: usize) -> Option<u16> {
+    let (min, max) = (a.min(b), a.max(b));
+    let offset = min * parties - min * (min + 1) / 2 + (max - min);
+    u16::try_from(base as usize + offset).ok()
+}
+
+/// Ports a job's channels span above its base, including the unused base port.
+pub fn channel_span(parties: usize) -> usize {
+    let (min, max) = (parties.saturating_sub(2), parties.saturating_sub(1));
+    min * parties - min * (min + 1) / 2 + (max - min) + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn program_args_keep_user_order() {
+        let mut config = SequreRuntimeConfig::localhost(1, 3);
+        assert_eq!(
+            config.program_args(&args(&["--rows", "100"])),
+            args(&["1", "--rows", "100"])
+        );
+        config.skip_mhe_setup = true;
+        assert_eq!(
+            config.program_args(&args(&["a", "--use-ring"])),
+            args(&["--skip-mhe-setup", "1", "a", "--use-ring"])
+        );
+        assert_eq!(
+            config.program_args(&args(&["--skip-mhe-setup"])),
+            args(&["1", "--skip-mhe-setup"])
+        );
+    }
+
+    #[test]
+    fn program_args_pass_through_without_pid() {
+        let config = SequreRuntimeConfig::default();
+        let extra = args(&["--rows", "100", "x"]);
+        assert_eq!(config.program_args(&extra), extra);
+    }
+
+    #[test]
+    fn validate_accepts_localhost() {
+        assert_eq!(SequreRuntimeConfig::localhost(2, 3).validate(), Ok(()));
+        assert_eq!(SequreRuntimeConfig::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn validate_rejects_bad_layouts() {
+        let mut config = SequreRuntimeConfig::localhost(0, 2);
+        assert_eq!(config.validate(), Err(ConfigError::TooFewParties(2)));
+
+        config = SequreRuntimeConfig::localhost(3, 3);
+        assert_eq!(
+            config.validate(),
+            Err(ConfigError::PidOutOfRange { pid: 3, parties: 3 })
+        );
+
+        config = SequreRuntimeConfig::localhost(0, 3);
+        config.parties = Some(4);
+        assert_eq!(
+            config.validate(),
+            Err(ConfigError::IpCountMismatch { ips: 3, parties: 4 })
+        );
+
+        config = SequreRuntimeConfig::localhost(0, 3);
+        config.cp_ips[1] = " ".to_string();
+        assert_eq!(config.validate(), Err(ConfigError::EmptyIp(1)));
+
+        config = SequreRuntimeConfig {
+            pid: Some(0),
+            parties: Some(3),
+            ..SequreRuntimeConfig::default()
+        };
+        assert_eq!(config.validate(), Err(ConfigError::MissingPeers(0)));
+
+        config = SequreRuntimeConfig::localhost(0, 3);
+        config.port_base = Some(u16::MAX - 1);
+        assert_eq!(
+            config.validate(),
+            Err(ConfigError::PortRangeOverflow {
+                base: u16::MAX - 1,
+                parties: 3
+            })
+        );
+    }
+
+    #[test]
+    fn channel_ports_follow_sequre_layout() {
+        assert_eq!(channel_port(9000, 3, 0, 1), Some(9001));
+        assert_eq!(channel_port(9000, 3, 0, 2), Some(9002));
+        assert_eq!(channel_port(9000, 3, 2, 1), Some(9003));
+        assert_eq!(channel_span(3), 4);
+    }
+}
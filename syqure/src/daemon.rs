@@ -0,0 +1,953 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::cluster::{ClusterConfig, DEFAULT_CLUSTER_FILE};
+use crate::config::{ENV_CP_COUNT, ENV_CP_IPS, ENV_PORT_BASE};
+use crate::inputs::{check_input_name, ENV_INPUT_DIR};
+use crate::keys::{config_dir, create_private_dir, create_private_file, Keystore};
+use crate::launcher::send_signal;
+use crate::mhe::{MheKeyConfig, ENV_MHE_KEY_DIR};
+use crate::package::{self, SiteOptions};
+use crate::results::{collect_outputs, Outputs, ENV_RESULTS_FILE};
+use crate::runner::CompileOptions;
+use crate::signals::{self, Interrupted};
+use crate::transport::file::write_message;
+use crate::transport::state::check_job_id;
+
+/// Overrides where the daemon keeps its socket and job history.
+pub const ENV_DAEMON_DIR: &str = "SYQURE_DAEMON_DIR";
+
+const SOCKET_FILE: &str = "daemon.sock";
+const RECORD_FILE: &str = "job.json";
+const RESULTS_FILE: &str = "results.jsonl";
+const PACKAGE_DIR: &str = "package";
+
+/// How often the watched directory is scanned for new requests.
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often an idle daemon checks for signals.
+const IDLE_POLL: Duration = Duration::from_millis(200);
+
+/// How long a job's party gets to clean up after the daemon is stopped.
+const JOB_STOP_GRACE: Duration = Duration::from_secs(10);
+
+/// `$SYQURE_DAEMON_DIR`, else `daemon/CP{pid}` under the syqure config dir.
+pub fn default_daemon_dir(pid: usize) -> Result<PathBuf> {
+    if let Some(dir) = std::env::var_os(ENV_DAEMON_DIR) {
+        return Ok(PathBuf::from(dir));
+    }
+    let dir = config_dir().ok_or_else(|| anyhow!("cannot determine syqure config directory"))?;
+    Ok(dir.join("daemon").join(format!("CP{}", pid)))
+}
+
+/// The API socket of the daemon keeping its state in `dir`.
+pub fn socket_path(dir: &Path) -> PathBuf {
+    dir.join(SOCKET_FILE)
+}
+
+/// Settings of a `syqure daemon` serving one party.
+#[derive(Debug, Clone)]
+pub struct DaemonOptions {
+    /// Party this site runs in every job.
+    pub pid: usize,
+    /// Socket and job history (`jobs/{id}/`), readable only by this user.
+    pub dir: PathBuf,
+    /// Serve the JSON API on this socket; `dir/daemon.sock` when unset.
+    pub socket: Option<PathBuf>,
+    /// Also pick up job requests dropped here as `*.json` files.
+    pub watch_dir: Option<PathBuf>,
+    /// Trusted keys for verifying job packages.
+    pub keystore: Option<Keystore>,
+    /// Compile settings shared by every job.
+    pub compile: CompileOptions,
+    /// Where and how this site caches MHE keys between jobs.
+    pub mhe_keys: Option<MheKeyConfig>,
+    /// syqure binary that runs each job as `syqure party`.
+    pub syqure_exe: PathBuf,
+}
+
+impl Default for DaemonOptions {
+    fn default() -> Self {
+        Self {
+            pid: 0,
+            dir: std::env::temp_dir().join("syqure-daemon"),
+            socket: None,
+            watch_dir: None,
+            keystore: None,
+            compile: CompileOptions::default(),
+            mhe_keys: None,
+            syqure_exe: std::env::current_exe().unwrap_or_else(|_| PathBuf::from("syqure")),
+        }
+    }
+}
+
+/// A job submitted to the daemon: a cluster config or a signed job package,
+/// plus this site's private inputs.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JobRequest {
+    /// Job id; generated when unset.
+    #[serde(default)]
+    pub id: Option<String>,
+    /// Cluster description file (syqure.toml).
+    #[serde(default)]
+    pub config: Option<PathBuf>,
+    /// Job package from `syqure job pack`, verified against the daemon's keystore.
+    #[serde(default)]
+    pub package: Option<PathBuf>,
+    /// Private input files, by name.
+    #[serde(default)]
+    pub inputs: BTreeMap<String, PathBuf>,
+    /// Extra program arguments (cluster configs only; packages carry their own).
+    #[serde(default)]
+    pub program_args: Vec<String>,
+    /// Shared message directory (file transport).
+    #[serde(default)]
+    pub message_dir: Option<PathBuf>,
+    /// Job key file (file transport).
+    #[serde(default)]
+    pub message_key: Option<PathBuf>,
+    /// This party's certificate and key for packaged tls jobs.
+    #[serde(default)]
+    pub cert: Option<PathBuf>,
+    #[serde(default)]
+    pub key: Option<PathBuf>,
+}
+
+impl JobRequest {
+    pub fn validate(&self) -> Result<()> {
+        if let Some(ref id) = self.id {
+            check_job_id(id)?;
+        }
+        match (&self.config, &self.package) {
+            (Some(_), None) | (None, Some(_)) => {}
+            _ => return Err(anyhow!("a job needs exactly one of config and package")),
+        }
+        if self.package.is_some() && !self.program_args.is_empty() {
+            return Err(anyhow!(
+                "packaged jobs take their program arguments from the package"
+            ));
+        }
+        if self.config.is_some() && (self.cert.is_some() || self.key.is_some()) {
+            return Err(anyhow!(
+                "cert and key only apply to packages; a cluster config names its own"
+            ));
+        }
+        for name in self.inputs.keys() {
+            check_input_name(name)?;
+        }
+        Ok(())
+    }
+
+    /// Resolve relative paths against `base`, since the daemon runs elsewhere.
+    pub fn resolve_paths(&mut self, base: &Path) {
+        let resolve = |p: &mut PathBuf| {
+            if p.is_relative() {
+                *p = base.join(&*p);
+            }
+        };
+        for path in [
+            &mut self.config,
+            &mut self.package,
+            &mut self.message_dir,
+            &mut self.message_key,
+            &mut self.cert,
+            &mut self.key,
+        ] {
+            if let Some(path) = path.as_mut() {
+                resolve(path);
+            }
+        }
+        self.inputs.values_mut().for_each(resolve);
+    }
+
+    /// Refuse relative paths: the daemon cannot know what the sender meant
+    /// them relative to.
+    pub fn ensure_absolute(&self) -> Result<()> {
+        let relative = [
+            &self.config,
+            &self.package,
+            &self.message_dir,
+            &self.message_key,
+            &self.cert,
+            &self.key,
+        ]
+        .into_iter()
+        .flatten()
+        .chain(self.inputs.values())
+        .find(|p| p.is_relative());
+        match relative {
+            Some(path) => Err(anyhow!(
+                "{} is relative; send absolute paths over the socket",
+                path.display()
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    /// The daemon stopped while the job was running.
+    Interrupted,
+}
+
+impl fmt::Display for JobStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobStatus::Queued => write!(f, "queued"),
+            JobStatus::Running => write!(f, "running"),
+            JobStatus::Succeeded => write!(f, "succeeded"),
+            JobStatus::Failed => write!(f, "failed"),
+            JobStatus::Interrupted => write!(f, "interrupted"),
+        }
+    }
+}
+
+/// History entry of one job (`jobs/{id}/job.json`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobRecord {
+    pub id: String,
+    pub status: JobStatus,
+    pub request: JobRequest,
+    pub submitted_at_ms: u64,
+    #[serde(default)]
+    pub started_at_ms: Option<u64>,
+    #[serde(default)]
+    pub finished_at_ms: Option<u64>,
+    /// Job attestation hash the parties agreed on.
+    #[serde(default)]
+    pub attestation: Option<String>,
+    #[serde(default)]
+    pub error: Option<String>,
+    /// Named outputs this party's program emitted.
+    #[serde(default)]
+    pub outputs: Outputs,
+}
+
+/// Which captured stream of a job to read.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogStream {
+    #[default]
+    Stdout,
+    Stderr,
+}
+
+impl LogStream {
+    fn file_name(self) -> &'static str {
+        match self {
+            LogStream::Stdout => "stdout.log",
+            LogStream::Stderr => "stderr.log",
+        }
+    }
+}
+
+impl fmt::Display for LogStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogStream::Stdout => write!(f, "stdout"),
+            LogStream::Stderr => write!(f, "stderr"),
+        }
+    }
+}
+
+impl FromStr for LogStream {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stdout" => Ok(LogStream::Stdout),
+            "stderr" => Ok(LogStream::Stderr),
+            other => Err(format!("unknown log stream '{}' (stdout or stderr)", other)),
+        }
+    }
+}
+
+/// One request on the daemon socket: a JSON object on a single line.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum ApiRequest {
+    Submit {
+        job: JobRequest,
+    },
+    Status {
+        id: String,
+    },
+    List,
+    Logs {
+        id: String,
+        #[serde(default)]
+        stream: LogStream,
+    },
+    Results {
+        id: String,
+    },
+}
+
+/// The daemon's one-line JSON reply; only the fields the request asked for are set.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ApiResponse {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub job: Option<JobRecord>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub jobs: Vec<JobRecord>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outputs: Option<Outputs>,
+}
+
+/// Send one request to the daemon listening on `socket`. A reply with
+/// `ok: false` becomes an error.
+pub fn request(socket: &Path, request: &ApiRequest) -> Result<ApiResponse> {
+    let mut stream = UnixStream::connect(socket)
+        .with_context(|| format!("no syqure daemon listening on {}", socket.display()))?;
+    let mut line = serde_json::to_string(request)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply)?;
+    let response: ApiResponse =
+        serde_json::from_str(&reply).context("malformed reply from the daemon")?;
+    if !response.ok {
+        return Err(anyhow!(
+            "{}",
+            response
+                .error
+                .unwrap_or_else(|| "request failed".to_string())
+        ));
+    }
+    Ok(response)
+}
+
+/// Job history on disk: `jobs/{id}/{job.json,stdout.log,stderr.log,results.jsonl}`.
+#[derive(Debug, Clone)]
+pub struct JobStore {
+    root: PathBuf,
+}
+
+impl JobStore {
+    pub fn open(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            root: dir.into().join("jobs"),
+        }
+    }
+
+    pub fn job_dir(&self, id: &str) -> PathBuf {
+        self.root.join(id)
+    }
+
+    pub fn load(&self, id: &str) -> Result<JobRecord> {
+        check_job_id(id)?;
+        let path = self.job_dir(id).join(RECORD_FILE);
+        let bytes = std::fs::read(&path).map_err(|_| anyhow!("no job '{}'", id))?;
+        serde_json::from_slice(&bytes).with_context(|| format!("corrupt {}", path.display()))
+    }
+
+    pub fn save(&self, record: &JobRecord) -> Result<()> {
+        let path = self.job_dir(&record.id).join(RECORD_FILE);
+        write_message(&path, &serde_json::to_vec_pretty(record)?)
+    }
+
+    /// Every job, oldest first.
+    pub fn list(&self) -> Result<Vec<JobRecord>> {
+        let entries = match std::fs::read_dir(&self.root) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to read {}", self.root.display()))
+            }
+        };
+        let mut records = Vec::new();
+        for entry in entries {
+            let id = entry?.file_name().to_string_lossy().into_owned();
+            if let Ok(record) = self.load(&id) {
+                records.push(record);
+            }
+        }
+        records.sort_by(|a, b| (a.submitted_at_ms, &a.id).cmp(&(b.submitted_at_ms, &b.id)));
+        Ok(records)
+    }
+
+    /// Captured output of a finished job (empty while it is queued or running).
+    pub fn log(&self, id: &str, stream: LogStream) -> Result<String> {
+        self.load(id)?;
+        match std::fs::read_to_string(self.job_dir(id).join(stream.file_name())) {
+            Ok(text) => Ok(text),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Record a new queued job, refusing an id already in the history.
+    fn create(&self, id: String, request: JobRequest) -> Result<JobRecord> {
+        create_private_dir(&self.root)?;
+        let dir = self.job_dir(&id);
+        std::fs::create_dir(&dir).map_err(|e| match e.kind() {
+            std::io::ErrorKind::AlreadyExists => anyhow!("job id '{}' is already taken", id),
+            _ => anyhow!("failed to create {}: {}", dir.display(), e),
+        })?;
+        let record = JobRecord {
+            id,
+            status: JobStatus::Queued,
+            request,
+            submitted_at_ms: now_ms(),
+            started_at_ms: None,
+            finished_at_ms: None,
+            attestation: None,
+            error: None,
+            outputs: Outputs::new(),
+        };
+        self.save(&record)?;
+        Ok(record)
+    }
+}
+
+/// A long-lived party that runs submitted jobs one at a time, each in its own
+/// `syqure party` process so no job's environment or directory leaks into
+/// the daemon or the next job.
+#[derive(Debug)]
+pub struct Daemon {
+    opts: DaemonOptions,
+    store: JobStore,
+    queue: Mutex<VecDeque<String>>,
+    ready: Condvar,
+    next_id: AtomicU64,
+}
+
+impl Daemon {
+    /// Open the daemon's history. Jobs left running by a previous daemon are
+    /// marked interrupted; jobs still queued are queued again.
+    pub fn new(opts: DaemonOptions) -> Result<Arc<Self>> {
+        create_private_dir(&opts.dir)?;
+        let store = JobStore::open(&opts.dir);
+        let mut queue = VecDeque::new();
+        for mut record in store.list()? {
+            match record.status {
+                JobStatus::Queued => queue.push_back(record.id),
+                JobStatus::Running => {
+                    record.status = JobStatus::Interrupted;
+                    record.finished_at_ms = Some(now_ms());
+                    record.error = Some("the daemon stopped while the job was running".into());
+                    store.save(&record)?;
+                }
+                _ => {}
+            }
+        }
+        Ok(Arc::new(Self {
+            opts,
+            store,
+            queue: Mutex::new(queue),
+            ready: Condvar::new(),
+            next_id: AtomicU64::new(0),
+        }))
+    }
+
+    pub fn store(&self) -> &JobStore {
+        &self.store
+    }
+
+    pub fn socket(&self) -> PathBuf {
+        self.opts
+            .socket
+            .clone()
+            .unwrap_or_else(|| socket_path(&self.opts.dir))
+    }
+
+    /// Queue a job. Relative paths in `request` must already be resolved.
+    pub fn submit(&self, request: JobRequest) -> Result<JobRecord> {
+        request.validate()?;
+        let id = match request.id.clone() {
+            Some(id) => id,
+            None => format!(
+                "job-{}-{}",
+                now_ms(),
+                self.next_id.fetch_add(1, Ordering::SeqCst)
+            ),
+        };
+        let record = self.store.create(id, request)?;
+        println!("CP{}: queued job {}", self.opts.pid, record.id);
+        self.queue
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push_back(record.id.clone());
+        self.ready.notify_one();
+        Ok(record)
+    }
+
+    /// Serve the socket (and the watched directory), running queued jobs until
+    /// SIGINT or SIGTERM.
+    pub fn run(self: &Arc<Self>) -> Result<()> {
+        signals::install()?;
+        let socket = self.socket();
+        let listener = bind_socket(&socket)?;
+        println!(
+            "CP{}: syqure daemon listening on {}",
+            self.opts.pid,
+            socket.display()
+        );
+        let daemon = self.clone();
+        std::thread::spawn(move || daemon.serve(listener));
+        if let Some(dir) = self.opts.watch_dir.clone() {
+            prepare_watch_dir(&dir)?;
+            println!("CP{}: watching {} for jobs", self.opts.pid, dir.display());
+            let daemon = self.clone();
+            std::thread::spawn(move || daemon.watch(&dir));
+        }
+
+        let result = loop {
+            if let Some(sig) = signals::received() {
+                break Err(Interrupted(sig).into());
+            }
+            let next = {
+                let queue = self.queue.lock().unwrap_or_else(|e| e.into_inner());
+                let (mut queue, _) = self
+                    .ready
+                    .wait_timeout_while(queue, IDLE_POLL, |q| q.is_empty())
+                    .unwrap_or_else(|e| e.into_inner());
+                queue.pop_front()
+            };
+            if let Some(id) = next {
+                if let Err(e) = self.run_job(&id) {
+                    eprintln!("CP{}: job {}: {:#}", self.opts.pid, id, e);
+                }
+            }
+        };
+        let _ = std::fs::remove_file(&socket);
+        result
+    }
+
+    fn run_job(&self, id: &str) -> Result<()> {
+        let mut record = self.store.load(id)?;
+        record.status = JobStatus::Running;
+        record.started_at_ms = Some(now_ms());
+        self.store.save(&record)?;
+        println!("CP{}: running job {}", self.opts.pid, id);
+
+        match self.execute(&record) {
+            Ok(outcome) => {
+                record.status = JobStatus::Succeeded;
+                record.attestation = Some(outcome.attestation);
+                record.outputs = outcome.outputs;
+            }
+            Err(e) if e.is::<Interrupted>() => {
+                record.status = JobStatus::Interrupted;
+                record.error = Some("the daemon stopped while the job was running".into());
+            }
+            Err(e) => {
+                record.status = JobStatus::Failed;
+                record.error = Some(format!("{:#}", e));
+            }
+        }
+        record.finished_at_ms = Some(now_ms());
+        self.store.save(&record)?;
+        println!("CP{}: job {} {}", self.opts.pid, id, record.status);
+        Ok(())
+    }
+
+    fn execute(&self, record: &JobRecord) -> Result<JobOutcome> {
+        let request = &record.request;
+        let pid = self.opts.pid;
+        // The party runs from the job directory, so every path it gets is absolute.
+        let cwd = std::env::current_dir()?;
+        let dir = cwd.join(self.store.job_dir(&record.id));
+        let mut compile = self.opts.compile.clone();
+        // A package stays unpacked in the job's directory, next to its logs.
+        let (mut cluster, attestation) = match (&request.config, &request.package) {
+            (Some(config), _) => {
+                let mut cluster = ClusterConfig::load(config)?;
+                if request.message_dir.is_some() {
+                    cluster.job.message_dir = request.message_dir.clone();
+                }
+                if request.message_key.is_some() {
+                    cluster.job.message_key = request.message_key.clone();
+                }
+                compile.program_args = request.program_args.clone();
+                let attestation = cluster.attest(&compile)?.hash;
+                (cluster, attestation)
+            }
+            (None, Some(package)) => {
+                let keystore = self
+                    .opts
+                    .keystore
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("this daemon has no keystore to verify packages"))?;
+                let unpacked = package::verify(package, Some(&dir.join(PACKAGE_DIR)), keystore)?;
+                let site = SiteOptions {
+                    message_dir: request.message_dir.clone(),
+                    message_key: request.message_key.clone(),
+                    cert: request.cert.clone(),
+                    key: request.key.clone(),
+                };
+                compile.release = unpacked.manifest.release();
+                let cluster = unpacked.cluster(pid, &site)?;
+                (cluster, unpacked.attestation.hash.clone())
+            }
+            (None, None) => return Err(anyhow!("job has neither config nor package")),
+        };
+
+        // The party reads the job as a cluster file, with the site's key cache
+        // folded in.
+        cluster.job.mhe_keys = cluster
+            .mhe_keys(self.opts.mhe_keys.as_ref())
+            .map(|mut keys| {
+                keys.dir = keys.dir.map(|d| cwd.join(d));
+                keys.seal_key = keys.seal_key.map(|k| cwd.join(k));
+                keys
+            });
+        let cluster_file = dir.join(DEFAULT_CLUSTER_FILE);
+        create_private_file(&cluster_file)?
+            .write_all(toml::to_string(&cluster)?.as_bytes())
+            .with_context(|| format!("failed to write {}", cluster_file.display()))?;
+
+        let results_file = dir.join(RESULTS_FILE);
+        let stdout_log = dir.join(LogStream::Stdout.file_name());
+        let stderr_log = dir.join(LogStream::Stderr.file_name());
+        let mut cmd = self.party_command(&compile, &cwd, &cluster_file, request);
+        cmd.current_dir(&dir)
+            .env(ENV_RESULTS_FILE, &results_file)
+            .stdin(Stdio::null())
+            .stdout(File::create(&stdout_log)?)
+            .stderr(File::create(&stderr_log)?);
+        // Settings the daemon was started with must not override the job's.
+        for var in [
+            ENV_INPUT_DIR,
+            ENV_MHE_KEY_DIR,
+            ENV_CP_IPS,
+            ENV_CP_COUNT,
+            ENV_PORT_BASE,
+        ] {
+            cmd.env_remove(var);
+        }
+        let child = cmd
+            .spawn()
+            .with_context(|| format!("failed to start {}", self.opts.syqure_exe.display()))?;
+        let status = wait_for_party(child)?;
+
+        let stdout = std::fs::read_to_string(&stdout_log).unwrap_or_default();
+        if !status.success() {
+            let stderr = std::fs::read_to_string(&stderr_log).unwrap_or_default();
+            let error = stderr
+                .lines()
+                .rev()
+                .find(|l| !l.trim().is_empty())
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("party exited with {}", status));
+            return Err(anyhow!("{}", error));
+        }
+        Ok(JobOutcome {
+            attestation,
+            outputs: collect_outputs(Some(&results_file), &stdout)?,
+        })
+    }
+
+    /// `syqure party` for this daemon's pid, with the daemon's compile settings
+    /// as flags (paths made absolute against `cwd`).
+    fn party_command(
+        &self,
+        compile: &CompileOptions,
+        cwd: &Path,
+        cluster_file: &Path,
+        request: &JobRequest,
+    ) -> Command {
+        let mut cmd = Command::new(&self.opts.syqure_exe);
+        cmd.arg("party")
+            .arg("--config")
+            .arg(cluster_file)
+            .arg("--pid")
+            .arg(self.opts.pid.to_string())
+            .arg("--codon-path")
+            .arg(cwd.join(&compile.codon_path));
+        if compile.release {
+            cmd.arg("--release");
+        }
+        if compile.runtime.skip_mhe_setup {
+            cmd.arg("--skip-mhe-setup");
+        }
+        if !compile.quiet {
+            cmd.arg("--show-warnings");
+        }
+        if let Some(ref gmp) = compile.runtime.gmp_path {
+            cmd.arg("--gmp-path").arg(cwd.join(gmp));
+        }
+        for (name, path) in &request.inputs {
+            let mut arg = OsString::from(format!("{}=", name));
+            arg.push(path);
+            cmd.arg("--input").arg(arg);
+        }
+        if !compile.program_args.is_empty() {
+            cmd.arg("--").args(&compile.program_args);
+        }
+        cmd
+    }
+
+    fn serve(self: Arc<Self>, listener: UnixListener) {
+        for stream in listener.incoming().map_while(Result::ok) {
+            let daemon = self.clone();
+            std::thread::spawn(move || daemon.handle(stream));
+        }
+    }
+
+    fn handle(&self, stream: UnixStream) {
+        let Ok(mut writer) = stream.try_clone() else {
+            return;
+        };
+        for line in BufReader::new(stream).lines().map_while(Result::ok) {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let response = serde_json::from_str::<ApiRequest>(&line)
+                .map_err(anyhow::Error::from)
+                .and_then(|request| self.answer(request))
+                .unwrap_or_else(|e| ApiResponse {
+                    error: Some(format!("{:#}", e)),
+                    ..ApiResponse::default()
+                });
+            let Ok(mut reply) = serde_json::to_string(&response) else {
+                return;
+            };
+            reply.push('\n');
+            if writer.write_all(reply.as_bytes()).is_err() {
+                return;
+            }
+        }
+    }
+
+    fn answer(&self, request: ApiRequest) -> Result<ApiResponse> {
+        let mut response = ApiResponse {
+            ok: true,
+            ..ApiResponse::default()
+        };
+        match request {
+            ApiRequest::Submit { job } => {
+                job.ensure_absolute()?;
+                response.job = Some(self.submit(job)?);
+            }
+            ApiRequest::Status { id } => response.job = Some(self.store.load(&id)?),
+            ApiRequest::List => response.jobs = self.store.list()?,
+            ApiRequest::Logs { id, stream } => response.log = Some(self.store.log(&id, stream)?),
+            ApiRequest::Results { id } => response.outputs = Some(self.store.load(&id)?.outputs),
+        }
+        Ok(response)
+    }
+
+    /// Submit every `*.json` request that appears in `dir`. Accepted files are
+    /// removed; rejected ones are renamed to `*.rejected` next to an `.error` note.
+    fn watch(self: Arc<Self>, dir: &Path) {
+        loop {
+            let mut files: Vec<PathBuf> = std::fs::read_dir(dir)
+                .into_iter()
+                .flatten()
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+                .collect();
+            files.sort();
+            for path in files {
+                if let Err(e) = self.submit_file(dir, &path) {
+                    eprintln!("CP{}: rejected {}: {:#}", self.opts.pid, path.display(), e);
+                    let _ = std::fs::write(path.with_extension("error"), format!("{:#}\n", e));
+                    let _ = std::fs::rename(&path, path.with_extension("rejected"));
+                }
+            }
+            std::thread::sleep(WATCH_INTERVAL);
+        }
+    }
+
+    fn submit_file(&self, dir: &Path, path: &Path) -> Result<()> {
+        let bytes = std::fs::read(path)?;
+        let mut request: JobRequest =
+            serde_json::from_slice(&bytes).context("not a job request")?;
+        // Requests are named after their job unless they say otherwise.
+        if request.id.is_none() {
+            request.id = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned());
+        }
+        request.resolve_paths(dir);
+        self.submit(request)?;
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+}
+
+/// What a job that ran to completion left behind.
+struct JobOutcome {
+    attestation: String,
+    outputs: Outputs,
+}
+
+/// Wait for a job's party to exit. If the daemon is told to stop meanwhile,
+/// pass the signal on and give the party a grace period to clean up.
+fn wait_for_party(mut child: Child) -> Result<ExitStatus> {
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        if let Some(sig) = signals::received() {
+            send_signal(&mut child, sig);
+            let deadline = Instant::now() + JOB_STOP_GRACE;
+            while child.try_wait()?.is_none() {
+                if Instant::now() >= deadline {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    break;
+                }
+                std::thread::sleep(IDLE_POLL);
+            }
+            return Err(Interrupted(sig).into());
+        }
+        std::thread::sleep(IDLE_POLL);
+    }
+}
+
+/// Create the watched directory owner-only, or check that an existing one is
+/// ours and that nobody else can drop requests into it: every request found
+/// there runs with the daemon's privileges.
+fn prepare_watch_dir(dir: &Path) -> Result<()> {
+    if !dir.exists() {
+        return create_private_dir(dir);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+        let meta =
+            std::fs::metadata(dir).with_context(|| format!("failed to read {}", dir.display()))?;
+        // SAFETY: geteuid has no preconditions.
+        if meta.uid() != unsafe { libc::geteuid() } {
+            return Err(anyhow!(
+                "{} belongs to another user; watch a directory of your own",
+                dir.display()
+            ));
+        }
+        let mode = meta.permissions().mode();
+        if mode & 0o022 != 0 {
+            return Err(anyhow!(
+                "other users can write to {} (mode {:o}); run chmod go-w on it",
+                dir.display(),
+                mode & 0o777
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Listen on `path`, replacing a socket left behind by a daemon that is gone.
+/// Only this user may connect, since jobs run with the daemon's privileges.
+fn bind_socket(path: &Path) -> Result<UnixListener> {
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(anyhow!(
+                "another syqure daemon is listening on {}",
+                path.display()
+            ));
+        }
+        std::fs::remove_file(path)
+            .with_context(|| format!("failed to remove stale socket {}", path.display()))?;
+    }
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let listener =
+        UnixListener::bind(path).with_context(|| format!("failed to bind {}", path.display()))?;
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "syqure-daemon-test-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn socket_requests_need_absolute_paths() {
+        let mut job = JobRequest {
+            package: Some("/srv/jobs/sum.tar.zst".into()),
+            message_dir: Some("/shared/mpc_messages".into()),
+            ..JobRequest::default()
+        };
+        job.ensure_absolute().unwrap();
+        job.inputs.insert("value".into(), "cp1.csv".into());
+        assert!(job
+            .ensure_absolute()
+            .unwrap_err()
+            .to_string()
+            .contains("cp1.csv"));
+        job.resolve_paths(Path::new("/home/site"));
+        job.ensure_absolute().unwrap();
+        job.key = Some("../cp1.key".into());
+        assert!(job.ensure_absolute().is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn watch_dirs_are_created_private_and_shared_ones_refused() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = temp_dir("watch");
+        prepare_watch_dir(&dir).unwrap();
+        let mode = std::fs::metadata(&dir).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+        prepare_watch_dir(&dir).unwrap();
+
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o775)).unwrap();
+        let err = prepare_watch_dir(&dir).unwrap_err().to_string();
+        assert!(err.contains("other users can write"), "{}", err);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn job_clusters_survive_the_trip_through_a_file() {
+        let dir = temp_dir("cluster");
+        create_private_dir(&dir).unwrap();
+        let example = dir.join("example.toml");
+        std::fs::write(&example, include_str!("../../example/syqure-file.toml")).unwrap();
+        let cluster = ClusterConfig::load(&example).unwrap();
+        let path = dir.join(DEFAULT_CLUSTER_FILE);
+        std::fs::write(&path, toml::to_string(&cluster).unwrap()).unwrap();
+        let reloaded = ClusterConfig::load(&path).unwrap();
+        assert_eq!(reloaded.job.source, cluster.job.source);
+        assert_eq!(reloaded.job.message_dir, cluster.job.message_dir);
+        assert_eq!(reloaded.parties.len(), cluster.parties.len());
+        assert_eq!(reloaded.runtime_config(1), cluster.runtime_config(1));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,44 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use regex::Regex;
+
+use crate::attest::collect_sources;
+use crate::{inputs, mhe, results};
+
+/// Codon modules syqure generates for programs, by import name.
+const HELPERS: &[(&str, &str)] = &[
+    (inputs::HELPER_MODULE, inputs::HELPER_SOURCE),
+    (mhe::HELPER_MODULE, mhe::HELPER_SOURCE),
+    (results::HELPER_MODULE, results::HELPER_SOURCE),
+];
+
+/// Write each generated module the program imports next to `source`, so every
+/// party compiles (and attests) the same files.
+pub fn write_helpers(source: &Path) -> Result<Vec<PathBuf>> {
+    let mut texts = Vec::new();
+    for file in collect_sources(source)? {
+        texts.push(
+            std::fs::read_to_string(&file)
+                .with_context(|| format!("failed to read {}", file.display()))?,
+        );
+    }
+    let dir = source.parent().unwrap_or(Path::new("."));
+    let mut written = Vec::new();
+    for &(module, text) in HELPERS {
+        let import_re = Regex::new(&format!(
+            r"(?m)^\s*(from\s+{0}\s+import|import\s+{0}\b)",
+            module
+        ))?;
+        if !texts.iter().any(|t| import_re.is_match(t)) {
+            continue;
+        }
+        let path = dir.join(format!("{}.codon", module));
+        if std::fs::read_to_string(&path).ok().as_deref() != Some(text) {
+            std::fs::write(&path, text)
+                .with_context(|| format!("failed to write {}", path.display()))?;
+        }
+        written.push(path);
+    }
+    Ok(written)
+}
@@ -0,0 +1,178 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::keys::create_unique_private_dir;
+
+/// Directory holding this party's private inputs, exported only to its own process.
+pub const ENV_INPUT_DIR: &str = "SYQURE_INPUT_DIR";
+
+/// Module programs import to load their inputs; syqure writes it next to the source.
+pub const HELPER_MODULE: &str = "syqure_inputs";
+
+pub(crate) const HELPER_SOURCE: &str = r##"# Generated by syqure; rewritten on every run, do not edit.
+# Loads this party's private inputs (`syqure party --input name=path.csv` or
+# `inputs` under [[party]] in syqure.toml) from $SYQURE_INPUT_DIR.
+import os
+
+INPUT_DIR_ENV = "SYQURE_INPUT_DIR"
+
+
+def input_dir() -> str:
+    return os.getenv(INPUT_DIR_ENV, default="")
+
+
+def input_path(name: str) -> str:
+    root = input_dir()
+    if root == "":
+        raise ValueError(f"this party has no private inputs ({INPUT_DIR_ENV} is unset)")
+    return f"{root}/{name}.csv"
+
+
+def load_matrix(name: str, T: type = float) -> List[List[T]]:
+    """Rows of a numeric CSV file; blank lines and lines starting with # are skipped."""
+    rows = List[List[T]]()
+    with open(input_path(name)) as f:
+        for line in f:
+            line = line.strip()
+            if line == "" or line.startswith("#"):
+                continue
+            rows.append([T(cell.strip()) for cell in line.split(",")])
+    return rows
+
+
+def load_vector(name: str, T: type = float) -> List[T]:
+    """Every value of a numeric CSV file, row by row."""
+    values = List[T]()
+    for row in load_matrix(name, T):
+        values.extend(row)
+    return values
+
+
+def load_scalar(name: str, T: type = float) -> T:
+    values = load_vector(name, T)
+    if len(values) != 1:
+        raise ValueError(f"input {name} holds {len(values)} values, expected one")
+    return values[0]
+"##;
+
+/// One `--input name=path` argument.
+#[derive(Debug, Clone)]
+pub struct InputArg {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+impl FromStr for InputArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, path) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected name=path, got '{}'", s))?;
+        check_input_name(name).map_err(|e| e.to_string())?;
+        if path.is_empty() {
+            return Err(format!("input '{}' has an empty path", name));
+        }
+        Ok(InputArg {
+            name: name.to_string(),
+            path: PathBuf::from(path),
+        })
+    }
+}
+
+/// Input names become file names and Codon identifiers, so keep them simple.
+pub fn check_input_name(name: &str) -> Result<()> {
+    let mut chars = name.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "invalid input name '{}': use letters, digits and '_', not starting with a digit",
+            name
+        ))
+    }
+}
+
+/// A party's private inputs copied into their own directory. The copies are
+/// removed when this is dropped.
+#[derive(Debug)]
+pub struct StagedInputs {
+    pub dir: PathBuf,
+}
+
+impl StagedInputs {
+    /// Copy `inputs` for party `pid` to `{dir}/{name}.csv` in a fresh directory
+    /// readable only by this user.
+    pub fn stage(pid: usize, inputs: &BTreeMap<String, PathBuf>) -> Result<Self> {
+        let staged = StagedInputs {
+            dir: input_dir(pid)?,
+        };
+        for (name, path) in inputs {
+            check_input_name(name)?;
+            let target = staged.dir.join(format!("{}.csv", name));
+            std::fs::copy(path, &target).with_context(|| {
+                format!("failed to copy input '{}' from {}", name, path.display())
+            })?;
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                std::fs::set_permissions(&target, std::fs::Permissions::from_mode(0o600))?;
+            }
+        }
+        Ok(staged)
+    }
+}
+
+impl Drop for StagedInputs {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// New staging directory for party `pid`'s inputs. Never a predictable path,
+/// which another user could have prepared.
+fn input_dir(pid: usize) -> Result<PathBuf> {
+    create_unique_private_dir(&std::env::temp_dir(), &format!("syqure-inputs-CP{}-", pid))
+        .context("failed to create an input directory")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inputs_are_staged_privately_in_fresh_dirs() {
+        let source =
+            std::env::temp_dir().join(format!("syqure-input-src-{}.csv", std::process::id()));
+        std::fs::write(&source, "1,2,3\n").unwrap();
+        let inputs = BTreeMap::from([("value".to_string(), source.clone())]);
+
+        let first = StagedInputs::stage(1, &inputs).unwrap();
+        let second = StagedInputs::stage(1, &inputs).unwrap();
+        assert_ne!(first.dir, second.dir);
+        let staged = first.dir.join("value.csv");
+        assert_eq!(std::fs::read_to_string(&staged).unwrap(), "1,2,3\n");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode =
+                |p: &std::path::Path| std::fs::metadata(p).unwrap().permissions().mode() & 0o777;
+            assert_eq!(mode(&first.dir), 0o700);
+            assert_eq!(mode(&staged), 0o600);
+        }
+        let dir = first.dir.clone();
+        drop(first);
+        assert!(!dir.exists());
+
+        let bad = BTreeMap::from([("../x".to_string(), source.clone())]);
+        assert!(StagedInputs::stage(1, &bad).is_err());
+        std::fs::remove_file(&source).unwrap();
+    }
+}
@@ -0,0 +1,451 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::signature::{Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::attest::{hex, unhex};
+
+/// Identity used when no name is given.
+pub const DEFAULT_IDENTITY: &str = "default";
+
+/// Format tag of exported public identities; bump on incompatible changes.
+const PUBLIC_FORMAT: &str = "syqure-identity-v1";
+
+const SIGNING_KEY_FILE: &str = "signing.key";
+const ENCRYPTION_KEY_FILE: &str = "encryption.key";
+const PUBLIC_FILE: &str = "public.json";
+
+/// Root of syqure's configuration: `SYQURE_CONFIG_DIR`, else
+/// `$XDG_CONFIG_HOME/syqure`, else `~/.config/syqure`.
+pub fn config_dir() -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os("SYQURE_CONFIG_DIR") {
+        return Some(PathBuf::from(dir));
+    }
+    if let Some(dir) = std::env::var_os("XDG_CONFIG_HOME").filter(|d| !d.is_empty()) {
+        return Some(PathBuf::from(dir).join("syqure"));
+    }
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config").join("syqure"))
+}
+
+/// Public half of a party identity: what peers import and pin.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PublicIdentity {
+    pub format: String,
+    pub name: String,
+    /// Ed25519 verifying key, hex.
+    pub signing_key: String,
+    /// X25519 public key, hex.
+    pub encryption_key: String,
+}
+
+impl PublicIdentity {
+    /// SHA-256 over both public keys; compare out of band before trusting a peer.
+    pub fn fingerprint(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.signing_key.as_bytes());
+        hasher.update(b":");
+        hasher.update(self.encryption_key.as_bytes());
+        hex(&hasher.finalize())
+    }
+
+    /// Check the format tag and that both keys decode.
+    pub fn validate(&self) -> Result<()> {
+        if self.format != PUBLIC_FORMAT {
+            return Err(anyhow!(
+                "unsupported identity format '{}' (expected {})",
+                self.format,
+                PUBLIC_FORMAT
+            ));
+        }
+        check_name(&self.name)?;
+        decode_key(&self.signing_key).context("invalid signing_key")?;
+        decode_key(&self.encryption_key).context("invalid encryption_key")?;
+        Ok(())
+    }
+
+    /// Verify an Ed25519 signature made by this identity.
+    pub fn verify(&self, message: &[u8], signature: &[u8]) -> Result<()> {
+        let key = decode_key(&self.signing_key)?;
+        UnparsedPublicKey::new(&ED25519, key)
+            .verify(message, signature)
+            .map_err(|_| anyhow!("signature does not match identity '{}'", self.name))
+    }
+
+    pub fn encryption_public(&self) -> Result<PublicKey> {
+        Ok(PublicKey::from(decode_key(&self.encryption_key)?))
+    }
+}
+
+/// A local party identity with its secret keys.
+pub struct Identity {
+    signing: Ed25519KeyPair,
+    encryption: StaticSecret,
+    public: PublicIdentity,
+}
+
+impl fmt::Debug for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Identity")
+            .field("public", &self.public)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Identity {
+    fn from_seeds(name: &str, signing: &[u8; 32], encryption: [u8; 32]) -> Result<Self> {
+        let signing = Ed25519KeyPair::from_seed_unchecked(signing)
+            .map_err(|_| anyhow!("invalid Ed25519 seed"))?;
+        let encryption = StaticSecret::from(encryption);
+        let public = PublicIdentity {
+            format: PUBLIC_FORMAT.to_string(),
+            name: name.to_string(),
+            signing_key: hex(signing.public_key().as_ref()),
+            encryption_key: hex(PublicKey::from(&encryption).as_bytes()),
+        };
+        Ok(Self {
+            signing,
+            encryption,
+            public,
+        })
+    }
+
+    pub fn public(&self) -> &PublicIdentity {
+        &self.public
+    }
+
+    /// Ed25519 signature over `message`.
+    pub fn sign(&self, message: &[u8]) -> Vec<u8> {
+        self.signing.sign(message).as_ref().to_vec()
+    }
+
+    /// X25519 shared secret with a peer; callers must run it through a KDF.
+    pub fn agree(&self, peer: &PublicIdentity) -> Result<[u8; 32]> {
+        let shared = self.encryption.diffie_hellman(&peer.encryption_public()?);
+        if !shared.was_contributory() {
+            return Err(anyhow!(
+                "peer '{}' has a low-order encryption key",
+                peer.name
+            ));
+        }
+        Ok(shared.to_bytes())
+    }
+}
+
+/// Whether a keystore entry is one of ours or an imported peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyKind {
+    Own,
+    Peer,
+}
+
+impl fmt::Display for KeyKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyKind::Own => write!(f, "own"),
+            KeyKind::Peer => write!(f, "peer"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct KeyEntry {
+    pub kind: KeyKind,
+    pub name: String,
+    pub fingerprint: String,
+}
+
+/// Long-term party keys on disk:
+/// `identities/<name>/{signing.key,encryption.key,public.json}` for our own
+/// identities and `peers/<name>.json` for imported public identities.
+#[derive(Debug, Clone)]
+pub struct Keystore {
+    root: PathBuf,
+}
+
+impl Keystore {
+    pub fn open(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// The keystore under [`config_dir`].
+    pub fn open_default() -> Result<Self> {
+        let dir =
+            config_dir().ok_or_else(|| anyhow!("cannot determine syqure config directory"))?;
+        Ok(Self::open(dir.join("keys")))
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn identity_dir(&self, name: &str) -> PathBuf {
+        self.root.join("identities").join(name)
+    }
+
+    fn peer_path(&self, name: &str) -> PathBuf {
+        self.root.join("peers").join(format!("{}.json", name))
+    }
+
+    /// Create a new identity. Refuses to replace an existing one unless `force`.
+    pub fn generate(&self, name: &str, force: bool) -> Result<PublicIdentity> {
+        check_name(name)?;
+        let dir = self.identity_dir(name);
+        if dir.join(PUBLIC_FILE).exists() && !force {
+            return Err(anyhow!(
+                "identity '{}' already exists in {} (use --force to replace it)",
+                name,
+                self.root.display()
+            ));
+        }
+        let rng = SystemRandom::new();
+        let mut signing = [0u8; 32];
+        let mut encryption = [0u8; 32];
+        rng.fill(&mut signing)
+            .and_then(|_| rng.fill(&mut encryption))
+            .map_err(|_| anyhow!("system random source failed"))?;
+        let identity = Identity::from_seeds(name, &signing, encryption)?;
+
+        create_private_dir(&self.root)?;
+        create_private_dir(&self.root.join("identities"))?;
+        create_private_dir(&dir)?;
+        write_private(&dir.join(SIGNING_KEY_FILE), &hex(&signing))?;
+        write_private(&dir.join(ENCRYPTION_KEY_FILE), &hex(&encryption))?;
+        std::fs::write(
+            dir.join(PUBLIC_FILE),
+            serde_json::to_vec_pretty(identity.public())?,
+        )?;
+        Ok(identity.public().clone())
+    }
+
+    /// Load one of our identities, refusing key files others can read.
+    pub fn identity(&self, name: &str) -> Result<Identity> {
+        check_name(name)?;
+        let dir = self.identity_dir(name);
+        if !dir.join(PUBLIC_FILE).exists() {
+            return Err(anyhow!(
+                "no identity '{}' in {} (run `syqure keys generate --name {}`)",
+                name,
+                self.root.display(),
+                name
+            ));
+        }
+        let signing = read_private(&dir.join(SIGNING_KEY_FILE))?;
+        let encryption = read_private(&dir.join(ENCRYPTION_KEY_FILE))?;
+        Identity::from_seeds(name, &signing, encryption)
+    }
+
+    /// Public half of one of our identities.
+    pub fn public(&self, name: &str) -> Result<PublicIdentity> {
+        Ok(self.identity(name)?.public)
+    }
+
+    /// Trust a peer's exported identity under `alias` (its own name by default).
+    /// Replacing a different key under the same name requires `force`.
+    pub fn import_peer(
+        &self,
+        identity: &PublicIdentity,
+        alias: Option<&str>,
+        force: bool,
+    ) -> Result<PublicIdentity> {
+        identity.validate()?;
+        let mut identity = identity.clone();
+        if let Some(alias) = alias {
+            check_name(alias)?;
+            identity.name = alias.to_string();
+        }
+        let path = self.peer_path(&identity.name);
+        if let Ok(existing) = self.peer(&identity.name) {
+            if existing.fingerprint() != identity.fingerprint() && !force {
+                return Err(anyhow!(
+                    "peer '{}' is already pinned to {} (use --force to replace it)",
+                    identity.name,
+                    existing.fingerprint()
+                ));
+            }
+        }
+        create_private_dir(&self.root)?;
+        create_private_dir(&self.root.join("peers"))?;
+        std::fs::write(&path, serde_json::to_vec_pretty(&identity)?)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        Ok(identity)
+    }
+
+    /// An imported peer identity.
+    pub fn peer(&self, name: &str) -> Result<PublicIdentity> {
+        check_name(name)?;
+        let path = self.peer_path(name);
+        let bytes = std::fs::read(&path)
+            .with_context(|| format!("no peer '{}' in {}", name, self.root.display()))?;
+        let identity: PublicIdentity = serde_json::from_slice(&bytes)
+            .with_context(|| format!("failed to parse {}", path.display()))?;
+        identity.validate()?;
+        Ok(identity)
+    }
+
+    /// Every identity and peer, ours first, each sorted by name.
+    pub fn list(&self) -> Result<Vec<KeyEntry>> {
+        let mut own = BTreeMap::new();
+        for name in dir_names(&self.root.join("identities"))? {
+            let path = self.identity_dir(&name).join(PUBLIC_FILE);
+            if let Ok(bytes) = std::fs::read(&path) {
+                let public: PublicIdentity = serde_json::from_slice(&bytes)
+                    .with_context(|| format!("failed to parse {}", path.display()))?;
+                own.insert(name, public.fingerprint());
+            }
+        }
+        let mut peers = BTreeMap::new();
+        for file in dir_names(&self.root.join("peers"))? {
+            if let Some(name) = file.strip_suffix(".json") {
+                peers.insert(name.to_string(), self.peer(name)?.fingerprint());
+            }
+        }
+        let entries = own
+            .into_iter()
+            .map(|(name, fingerprint)| (KeyKind::Own, name, fingerprint))
+            .chain(
+                peers
+                    .into_iter()
+                    .map(|(name, fingerprint)| (KeyKind::Peer, name, fingerprint)),
+            )
+            .map(|(kind, name, fingerprint)| KeyEntry {
+                kind,
+                name,
+                fingerprint,
+            })
+            .collect();
+        Ok(entries)
+    }
+}
+
+/// Names become file names, so keep them to a safe alphabet.
+fn check_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "invalid key name '{}': use letters, digits, '-', '_' and '.'",
+            name
+        ))
+    }
+}
+
+fn decode_key(text: &str) -> Result<[u8; 32]> {
+    unhex(text.trim())
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| anyhow!("expected 64 hex digits"))
+}
+
+fn dir_names(dir: &Path) -> Result<Vec<String>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("failed to read {}", dir.display())),
+    };
+    let mut names = Vec::new();
+    for entry in entries {
+        names.push(entry?.file_name().to_string_lossy().into_owned());
+    }
+    Ok(names)
+}
+
+pub(crate) fn create_private_dir(dir: &Path) -> Result<()> {
+    std::fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))?;
+    }
+    Ok(())
+}
+
+/// Create a new directory `{prefix}{random}` under `parent`, readable by the
+/// owner only. Never reuses an existing directory, so nobody else can have
+/// prepared it.
+pub(crate) fn create_unique_private_dir(parent: &Path, prefix: &str) -> Result<PathBuf> {
+    std::fs::create_dir_all(parent)
+        .with_context(|| format!("failed to create {}", parent.display()))?;
+    let rng = SystemRandom::new();
+    for _ in 0..16 {
+        let mut suffix = [0u8; 8];
+        rng.fill(&mut suffix)
+            .map_err(|_| anyhow!("system random source failed"))?;
+        let dir = parent.join(format!("{}{}", prefix, hex(&suffix)));
+        let mut builder = std::fs::DirBuilder::new();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::DirBuilderExt;
+            builder.mode(0o700);
+        }
+        match builder.create(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e).with_context(|| format!("failed to create {}", dir.display())),
+        }
+    }
+    Err(anyhow!(
+        "failed to create a unique directory in {}",
+        parent.display()
+    ))
+}
+
+/// Create (or truncate) `path` readable by the owner only, before any secret
+/// byte is written to it.
+pub(crate) fn create_private_file(path: &Path) -> Result<std::fs::File> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let file = options
+        .open(path)
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    // `mode` only applies on creation; tighten a pre-existing file too.
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(file)
+}
+
+fn write_private(path: &Path, hex_key: &str) -> Result<()> {
+    use std::io::Write;
+    let mut file = create_private_file(path)?;
+    writeln!(file, "{}", hex_key).with_context(|| format!("failed to write {}", path.display()))?;
+    Ok(())
+}
+
+fn read_private(path: &Path) -> Result<[u8; 32]> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(path)
+            .with_context(|| format!("failed to read {}", path.display()))?
+            .permissions()
+            .mode();
+        if mode & 0o077 != 0 {
+            return Err(anyhow!(
+                "{} is accessible by other users (mode {:o}); run chmod 600 on it",
+                path.display(),
+                mode & 0o777
+            ));
+        }
+    }
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    decode_key(&text).with_context(|| format!("corrupt key file {}", path.display()))
+}
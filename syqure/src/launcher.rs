@@ -0,0 +1,638 @@
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+use signal_hook::consts::SIGTERM;
+
+use crate::attest::{attest_job, JobAttestation};
+use crate::cluster::{ClusterConfig, Transport};
+use crate::config::{channel_span, SequreRuntimeConfig};
+use crate::inputs::StagedInputs;
+use crate::mhe::MheKeyConfig;
+use crate::ports::{PortAllocator, PortLease};
+use crate::preflight::{preflight, PreflightReport};
+use crate::results::{collect_outputs, mismatches, OutputMismatch, Outputs, ResultsDir};
+use crate::runner::{CompileOptions, Syqure};
+use crate::signals::{self, Interrupted};
+use crate::transport::file::{channel_dir, hello_path, run_proxy};
+use crate::transport::netem::NetProfile;
+use crate::transport::state::job_dir;
+use crate::transport::tls::run_tls_relay;
+use crate::transport::ChannelStats;
+
+/// All parties run on this host, so the handshake should complete almost at once.
+const LOCAL_PREFLIGHT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait for proxies and relays to report once every party has exited.
+const PROXY_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Options for launching every compute party of a program as a local process.
+#[derive(Debug, Clone)]
+pub struct LaunchOptions {
+    /// Number of compute parties, including the trusted dealer CP0.
+    pub parties: usize,
+    /// Port base exported as SEQURE_PORT_BASE (Sequre's default when unset).
+    pub port_base: Option<u16>,
+    /// Forward each party's output to this process, prefixed with `CP<n>`.
+    pub echo: bool,
+    /// Kill every party if the run takes longer than this.
+    pub run_timeout: Option<Duration>,
+    /// Check every party-to-party port before compiling (TCP jobs only).
+    pub preflight: bool,
+    /// How long parties get to exit on their own before being killed, once the
+    /// launcher stops them.
+    pub shutdown_grace: Duration,
+    /// Record every party's messages here (file transport only).
+    pub transcript_dir: Option<PathBuf>,
+    /// Emulated network conditions between parties (file transport only).
+    pub net_profile: Option<NetProfile>,
+    /// Reserve free ports for this run instead of using the configured ones, so
+    /// concurrent jobs on one host don't collide.
+    pub auto_ports: Option<PortAllocator>,
+    /// Cache every party's MHE keys (merged with `[job.mhe_keys]` for clusters).
+    pub mhe_keys: Option<MheKeyConfig>,
+}
+
+impl Default for LaunchOptions {
+    fn default() -> Self {
+        Self {
+            parties: 3,
+            port_base: None,
+            echo: true,
+            run_timeout: None,
+            preflight: true,
+            shutdown_grace: Duration::from_secs(5),
+            transcript_dir: None,
+            net_profile: None,
+            auto_ports: None,
+            mhe_keys: None,
+        }
+    }
+}
+
+/// Outcome of a single compute party process.
+#[derive(Debug, Clone, Default)]
+pub struct PartyResult {
+    pub pid: usize,
+    /// Exit code, or None if the process was terminated by a signal.
+    pub exit_code: Option<i32>,
+    /// The launcher stopped this party after another one failed.
+    pub stopped: bool,
+    /// Captured stdout from the party.
+    pub stdout: String,
+    /// Captured stderr from the party.
+    pub stderr: String,
+}
+
+impl PartyResult {
+    pub fn success(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+}
+
+/// Combined result of a multi-party run.
+#[derive(Debug, Clone, Default)]
+pub struct MultiPartyResult {
+    /// Executable of each party, indexed by pid; parties running the same
+    /// source share one build.
+    pub executables: Vec<PathBuf>,
+    /// Per-party results, indexed by pid.
+    pub parties: Vec<PartyResult>,
+    /// Attestation of the programs the parties ran.
+    pub attestation: Option<JobAttestation>,
+    /// The party whose exit brought the run down, if any.
+    pub first_failure: Option<PartyFailure>,
+    /// Traffic per directed channel; only collected when syqure relays the
+    /// messages (file and tls transports).
+    pub comm_stats: Vec<ChannelStats>,
+    /// Named outputs each party emitted through `syqure_results`, by pid.
+    pub outputs: BTreeMap<usize, Outputs>,
+    /// Problems that did not fail the run, such as MHE keys that could not be kept.
+    pub warnings: Vec<String>,
+}
+
+impl MultiPartyResult {
+    pub fn success(&self) -> bool {
+        self.first_failure.is_none() && self.parties.iter().all(PartyResult::success)
+    }
+
+    /// Outputs that parties reported with different values.
+    pub fn output_mismatches(&self) -> Vec<OutputMismatch> {
+        mismatches(&self.outputs)
+    }
+}
+
+/// The first party to exit unsuccessfully, and why.
+#[derive(Debug, Clone)]
+pub struct PartyFailure {
+    pub pid: usize,
+    pub exit_code: Option<i32>,
+    /// Last line the party wrote to stderr (usually the exception), or a
+    /// description of how it exited.
+    pub error: String,
+}
+
+impl PartyFailure {
+    fn from_result(party: &PartyResult) -> Self {
+        let last_line = |s: &str| {
+            s.lines()
+                .rev()
+                .find(|l| !l.trim().is_empty())
+                .map(str::to_string)
+        };
+        let error = last_line(&party.stderr)
+            .or_else(|| last_line(&party.stdout))
+            .unwrap_or_else(|| match party.exit_code {
+                Some(code) => format!("exit code {}", code),
+                None => "terminated by signal".to_string(),
+            });
+        Self {
+            pid: party.pid,
+            exit_code: party.exit_code,
+            error,
+        }
+    }
+}
+
+/// Compile `source` once and run CP0..CPn-1 as separate local processes.
+pub fn launch_local(
+    opts: &CompileOptions,
+    launch: &LaunchOptions,
+    source: impl AsRef<Path>,
+) -> Result<MultiPartyResult> {
+    if launch.transcript_dir.is_some() || launch.net_profile.is_some() {
+        return Err(anyhow!(
+            "transcripts and network emulation need the file transport (use --config)"
+        ));
+    }
+    let lease = reserve_ports(launch, channel_span(launch.parties))?;
+    let port_base = lease.as_ref().map(|l| l.base);
+    let parties = (0..launch.parties)
+        .map(|pid| PartyLaunch {
+            source: source.as_ref().to_path_buf(),
+            program_args: opts.program_args.clone(),
+            runtime: SequreRuntimeConfig {
+                port_base: port_base.or(launch.port_base).or(opts.runtime.port_base),
+                gmp_path: opts.runtime.gmp_path.clone(),
+                skip_mhe_setup: opts.runtime.skip_mhe_setup,
+                ..SequreRuntimeConfig::localhost(pid, launch.parties)
+            },
+            workdir: None,
+        })
+        .collect();
+    run_parties(opts, launch, parties, &[])
+}
+
+/// Run every party described by a cluster config on this host, starting a file
+/// proxy per party when the job uses the file transport.
+pub fn launch_cluster(
+    opts: &CompileOptions,
+    launch: &LaunchOptions,
+    cluster: &ClusterConfig,
+) -> Result<MultiPartyResult> {
+    let mut cluster = cluster.clone();
+    let lease = reserve_ports(launch, cluster.local_port_span())?;
+    if let Some(ref lease) = lease {
+        cluster.rebase_ports(lease.base);
+    }
+    let cluster = &cluster;
+    cluster.validate()?;
+    let relayed = launch.transcript_dir.is_some() || launch.net_profile.is_some();
+    if relayed && cluster.job.transport != Transport::File {
+        return Err(anyhow!(
+            "transcripts and network emulation need the file transport"
+        ));
+    }
+
+    // Each party's process only sees its own inputs.
+    let mut staged = Vec::new();
+    for party in cluster.parties.iter().filter(|p| !p.inputs.is_empty()) {
+        staged.push((
+            party.pid,
+            StagedInputs::stage(party.pid, &party.inputs)
+                .with_context(|| format!("CP{}: failed to stage inputs", party.pid))?,
+        ));
+    }
+    let parties = cluster
+        .parties
+        .iter()
+        .map(|party| {
+            let party_opts = cluster.compile_options(party.pid, opts);
+            PartyLaunch {
+                source: cluster.source(party.pid).to_path_buf(),
+                program_args: party_opts.program_args,
+                runtime: SequreRuntimeConfig {
+                    gmp_path: opts.runtime.gmp_path.clone(),
+                    skip_mhe_setup: party_opts.runtime.skip_mhe_setup,
+                    input_dir: staged
+                        .iter()
+                        .find(|(pid, _)| *pid == party.pid)
+                        .map(|(_, inputs)| inputs.dir.clone()),
+                    ..cluster.runtime_config(party.pid)
+                },
+                workdir: party.data_dir.clone(),
+            }
+        })
+        .collect();
+
+    // Channel folders and resume state only hold in-flight messages; stale ones
+    // would be replayed into the next run. Archived messages are kept.
+    let mut transient = Vec::new();
+    if let Some(ref dir) = cluster.job.message_dir {
+        if cluster.job.transport == Transport::File {
+            let dir = &job_dir(dir, cluster.job.id.as_deref());
+            let n = cluster.party_count();
+            for from in 0..n {
+                for to in (0..n).filter(|&to| to != from) {
+                    transient.push(channel_dir(dir, from, to));
+                }
+            }
+            if let Some(hellos) = hello_path(dir, 0).parent() {
+                transient.push(hellos.to_path_buf());
+            }
+            transient.push(dir.join("state"));
+        }
+    }
+
+    // Proxies and relays exit on their own once Sequre closes its sockets.
+    let (stats_tx, stats_rx) = mpsc::channel();
+    let mut proxies = 0;
+    for pid in 0..cluster.party_count() {
+        if let Some(mut proxy) = cluster.proxy_options(pid) {
+            proxy.transcript_dir = launch.transcript_dir.clone();
+            proxy.net_profile = launch.net_profile.clone();
+            let stats_tx = stats_tx.clone();
+            std::thread::spawn(move || match run_proxy(&proxy) {
+                Ok(stats) => {
+                    let _ = stats_tx.send(stats);
+                }
+                Err(e) => eprintln!("proxy CP{}: {:#}", proxy.pid, e),
+            });
+            proxies += 1;
+        }
+        if let Some(relay) = cluster.tls_relay_options(pid)? {
+            let stats_tx = stats_tx.clone();
+            std::thread::spawn(move || match run_tls_relay(&relay) {
+                Ok(stats) => {
+                    let _ = stats_tx.send(stats);
+                }
+                Err(e) => eprintln!("relay CP{}: {:#}", relay.pid, e),
+            });
+            proxies += 1;
+        }
+    }
+    drop(stats_tx);
+
+    let launch = LaunchOptions {
+        parties: cluster.party_count(),
+        run_timeout: launch.run_timeout.or(cluster.run_timeout()),
+        preflight: launch.preflight && cluster.job.transport == Transport::Tcp,
+        mhe_keys: cluster.mhe_keys(launch.mhe_keys.as_ref()),
+        ..launch.clone()
+    };
+    let mut result = run_parties(opts, &launch, parties, &transient)?;
+
+    // A proxy whose party never connected would wait forever, so don't block on it.
+    let deadline = Instant::now() + PROXY_DRAIN_TIMEOUT;
+    for _ in 0..proxies {
+        match stats_rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(stats) => result.comm_stats.extend(stats),
+            Err(_) => break,
+        }
+    }
+    result.comm_stats.sort_by_key(|c| (c.from, c.to));
+    Ok(result)
+}
+
+/// Take a port range for the run when the launch asks for one.
+fn reserve_ports(launch: &LaunchOptions, len: usize) -> Result<Option<PortLease>> {
+    let Some(ref allocator) = launch.auto_ports else {
+        return Ok(None);
+    };
+    let lease = allocator.reserve(len)?;
+    eprintln!(
+        "Reserved ports {}-{}",
+        lease.base,
+        lease.base as usize + len - 1
+    );
+    Ok(Some(lease))
+}
+
+/// Process-level settings for one party.
+struct PartyLaunch {
+    source: PathBuf,
+    program_args: Vec<String>,
+    runtime: SequreRuntimeConfig,
+    workdir: Option<PathBuf>,
+}
+
+fn run_parties(
+    opts: &CompileOptions,
+    launch: &LaunchOptions,
+    mut parties: Vec<PartyLaunch>,
+    transient_dirs: &[PathBuf],
+) -> Result<MultiPartyResult> {
+    for party in &parties {
+        party.runtime.validate()?;
+    }
+    let results = ResultsDir::create()?;
+    for (pid, party) in parties.iter_mut().enumerate() {
+        party.runtime.results_file = Some(results.file(pid));
+    }
+    let mut mhe_keys = Vec::new();
+    if let Some(ref keys) = launch.mhe_keys {
+        let n = parties.len();
+        for (pid, party) in parties.iter_mut().enumerate() {
+            if !party.runtime.skip_mhe_setup {
+                let session = keys.open(pid, n)?;
+                party.runtime.mhe_key_dir = Some(session.dir.clone());
+                mhe_keys.push((pid, session));
+            }
+        }
+    }
+    let attestation = attest_job(parties.iter().enumerate().map(|(pid, party)| {
+        let mut party_opts = opts.clone();
+        party_opts.program_args = party.program_args.clone();
+        party_opts.runtime.skip_mhe_setup = party.runtime.skip_mhe_setup;
+        (pid, party.source.clone(), party_opts)
+    }))?;
+    if launch.preflight {
+        preflight_parties(&parties, &attestation.hash)?;
+    }
+
+    // Program arguments only matter at run time, so each source is built once.
+    let mut build_opts = opts.clone();
+    build_opts.run_after_build = false;
+    let mut builds: BTreeMap<&Path, PathBuf> = BTreeMap::new();
+    let mut executables = Vec::with_capacity(parties.len());
+    for party in &parties {
+        if !builds.contains_key(party.source.as_path()) {
+            let built = Syqure::new(build_opts.clone()).compile_and_maybe_run(&party.source)?;
+            let executable = built
+                .output_path
+                .ok_or_else(|| anyhow!("codon build did not produce an executable"))?;
+            // Parties may run from their own data dir, so resolve the path up front.
+            let executable = executable.canonicalize().unwrap_or(executable);
+            builds.insert(&party.source, executable);
+        }
+        executables.push(builds[party.source.as_path()].clone());
+    }
+    if let Some(sig) = signals::received() {
+        clean_up(&parties, transient_dirs);
+        return Err(Interrupted(sig).into());
+    }
+
+    // Anything that can fail before the parties run fails before the first one
+    // starts, so an early return never leaves some of them unsupervised.
+    for dir in parties.iter().filter_map(|p| p.workdir.as_ref()) {
+        if let Err(e) = std::fs::create_dir_all(dir) {
+            clean_up(&parties, transient_dirs);
+            return Err(e).with_context(|| format!("failed to create {}", dir.display()));
+        }
+    }
+
+    let mut running = Vec::with_capacity(parties.len());
+    for (pid, party) in parties.iter().enumerate() {
+        let mut cmd = Command::new(&executables[pid]);
+        cmd.args(party.runtime.program_args(&party.program_args))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        party.runtime.apply(&mut cmd);
+        if let Some(ref dir) = party.workdir {
+            cmd.current_dir(dir);
+        }
+
+        let mut child = match cmd.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                shutdown(&mut running, SIGTERM, launch.shutdown_grace);
+                clean_up(&parties, transient_dirs);
+                return Err(e).with_context(|| format!("failed to start CP{}", pid));
+            }
+        };
+        let stdout = forward_lines(child.stdout.take(), pid, launch.echo, false);
+        let stderr = forward_lines(child.stderr.take(), pid, launch.echo, true);
+        running.push(RunningParty {
+            pid,
+            child,
+            exit_code: None,
+            stopped: false,
+            stdout,
+            stderr,
+        });
+    }
+
+    let deadline = launch.run_timeout.map(|t| Instant::now() + t);
+    let mut first_failed = None;
+    while running.iter().any(|p| p.exit_code.is_none()) {
+        let mut wait_error = None;
+        for party in running.iter_mut().filter(|p| p.exit_code.is_none()) {
+            match party.child.try_wait() {
+                Ok(Some(status)) => {
+                    party.exit_code = Some(status.code());
+                    if !status.success() {
+                        first_failed.get_or_insert(party.pid);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    wait_error = Some(
+                        anyhow::Error::from(e)
+                            .context(format!("failed to wait for CP{}", party.pid)),
+                    );
+                    break;
+                }
+            }
+        }
+        if let Some(e) = wait_error {
+            shutdown(&mut running, SIGTERM, launch.shutdown_grace);
+            clean_up(&parties, transient_dirs);
+            return Err(e);
+        }
+        if let Some(sig) = signals::received() {
+            eprintln!("Interrupted; stopping all parties (again to force)");
+            shutdown(&mut running, sig, launch.shutdown_grace);
+            clean_up(&parties, transient_dirs);
+            return Err(Interrupted(sig).into());
+        }
+        // One party going down leaves the rest blocked on dead sockets.
+        if let Some(pid) = first_failed {
+            if running.iter().any(|p| p.exit_code.is_none()) {
+                eprintln!("CP{} failed; stopping the other parties", pid);
+                shutdown(&mut running, SIGTERM, launch.shutdown_grace);
+            }
+            clean_up(&parties, transient_dirs);
+            break;
+        }
+        if deadline.is_some_and(|d| Instant::now() >= d) {
+            shutdown(&mut running, SIGTERM, launch.shutdown_grace);
+            clean_up(&parties, transient_dirs);
+            return Err(anyhow!(
+                "run timed out after {}s",
+                launch.run_timeout.unwrap_or_default().as_secs()
+            ));
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+
+    let parties: Vec<PartyResult> = running
+        .into_iter()
+        .map(|party| PartyResult {
+            pid: party.pid,
+            exit_code: party.exit_code.flatten(),
+            stopped: party.stopped,
+            stdout: party.stdout.join().unwrap_or_default(),
+            stderr: party.stderr.join().unwrap_or_default(),
+        })
+        .collect();
+    let first_failure = first_failed.map(|pid| PartyFailure::from_result(&parties[pid]));
+    let mut outputs = BTreeMap::new();
+    for party in &parties {
+        let party_outputs = collect_outputs(Some(&results.file(party.pid)), &party.stdout)?;
+        if !party_outputs.is_empty() {
+            outputs.insert(party.pid, party_outputs);
+        }
+    }
+    // Keys that cannot be kept cost the next run its setup; this run still stands.
+    let mut warnings = Vec::new();
+    for (pid, session) in mhe_keys {
+        if let Err(e) = session.finish() {
+            warnings.push(format!("CP{}: failed to keep MHE keys: {:#}", pid, e));
+        }
+    }
+
+    Ok(MultiPartyResult {
+        executables,
+        parties,
+        attestation: Some(attestation),
+        first_failure,
+        comm_stats: Vec::new(),
+        outputs,
+        warnings,
+    })
+}
+
+/// A spawned party the launcher is supervising.
+struct RunningParty {
+    pid: usize,
+    child: Child,
+    /// Set once the process has been reaped; the inner value is its exit code.
+    exit_code: Option<Option<i32>>,
+    stopped: bool,
+    stdout: JoinHandle<String>,
+    stderr: JoinHandle<String>,
+}
+
+/// Forward `sig` to every party that is still running, then kill whatever is
+/// left once `grace` has passed. Output threads see EOF and flush on their own.
+fn shutdown(running: &mut [RunningParty], sig: i32, grace: Duration) {
+    for party in running.iter_mut().filter(|p| p.exit_code.is_none()) {
+        send_signal(&mut party.child, sig);
+        party.stopped = true;
+    }
+    let deadline = Instant::now() + grace;
+    for party in running.iter_mut().filter(|p| p.exit_code.is_none()) {
+        loop {
+            match party.child.try_wait() {
+                Ok(Some(status)) => {
+                    party.exit_code = Some(status.code());
+                    break;
+                }
+                Ok(None) if Instant::now() < deadline => {
+                    std::thread::sleep(Duration::from_millis(50));
+                }
+                _ => {
+                    let _ = party.child.kill();
+                    party.exit_code = Some(party.child.wait().ok().and_then(|s| s.code()));
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Remove what killed parties leave behind: Sequre's `sock.*` files and the
+/// job's in-flight message folders.
+fn clean_up(parties: &[PartyLaunch], transient_dirs: &[PathBuf]) {
+    for party in parties {
+        signals::remove_sockets(party.workdir.as_deref().unwrap_or(Path::new(".")));
+    }
+    for dir in transient_dirs {
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
+
+#[cfg(unix)]
+pub(crate) fn send_signal(child: &mut Child, sig: i32) {
+    // A catchable signal lets Codon run its exit handlers; kill() would be SIGKILL.
+    unsafe {
+        libc::kill(child.id() as libc::pid_t, sig);
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn send_signal(child: &mut Child, _sig: i32) {
+    let _ = child.kill();
+}
+
+/// Run the preflight handshake for every party at once, before spending time on JIT.
+fn preflight_parties(parties: &[PartyLaunch], hash: &str) -> Result<()> {
+    let reports: Vec<Result<PreflightReport>> = std::thread::scope(|scope| {
+        let handles: Vec<_> = parties
+            .iter()
+            .map(|party| scope.spawn(|| preflight(&party.runtime, hash, LOCAL_PREFLIGHT_TIMEOUT)))
+            .collect();
+        handles
+            .into_iter()
+            .map(|h| {
+                h.join()
+                    .unwrap_or_else(|_| Err(anyhow!("preflight thread panicked")))
+            })
+            .collect()
+    });
+
+    let mut failed = false;
+    for report in reports {
+        let report = report?;
+        if !report.ok() {
+            report.print_table();
+            failed = true;
+        }
+    }
+    if failed {
+        return Err(anyhow!("preflight failed; see the link table above"));
+    }
+    Ok(())
+}
+
+fn forward_lines<R: Read + Send + 'static>(
+    stream: Option<R>,
+    pid: usize,
+    echo: bool,
+    is_stderr: bool,
+) -> JoinHandle<String> {
+    std::thread::spawn(move || {
+        let mut captured = String::new();
+        let Some(stream) = stream else {
+            return captured;
+        };
+        for line in BufReader::new(stream).lines().map_while(Result::ok) {
+            if echo {
+                if is_stderr {
+                    eprintln!("CP{} | {}", pid, line);
+                } else {
+                    println!("CP{} | {}", pid, line);
+                }
+            }
+            captured.push_str(&line);
+            captured.push('\n');
+        }
+        captured
+    })
+}
@@ -1,7 +1,29 @@
 pub mod analyze;
+pub mod attest;
 pub mod bundle;
+pub mod cluster;
+pub mod config;
+pub mod daemon;
 pub mod ffi;
+pub mod helpers;
+pub mod inputs;
+pub mod keys;
+pub mod launcher;
+pub mod mhe;
+pub mod package;
+pub mod party;
+pub mod ports;
+pub mod preflight;
+pub mod results;
 pub mod runner;
+pub mod signals;
+pub mod transport;
 
 pub use analyze::{analyze_file, Analysis};
+pub use attest::{attest, Attestation, JobAttestation};
+pub use cluster::{ClusterConfig, Transport};
+pub use config::{ConfigError, SequreRuntimeConfig};
+pub use launcher::{
+    launch_cluster, launch_local, LaunchOptions, MultiPartyResult, PartyFailure, PartyResult,
+};
 pub use runner::{CompileOptions, RunResult, Syqure};
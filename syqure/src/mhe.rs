@@ -0,0 +1,728 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::attest::hex;
+use crate::bundle;
+use crate::keys::{config_dir, create_private_dir};
+use crate::transport::envelope::MessageKey;
+use crate::transport::file::write_message;
+
+/// Directory this party's program loads its MHE keys from and saves them to.
+pub const ENV_MHE_KEY_DIR: &str = "SYQURE_MHE_KEY_DIR";
+
+/// Module programs import for an MHE setup that reuses cached keys; syqure
+/// writes it next to the source.
+pub const HELPER_MODULE: &str = "syqure_mhe";
+
+pub(crate) const HELPER_SOURCE: &str = r##"# Generated by syqure; rewritten on every run, do not edit.
+# MHE setup that reuses this party's key material from $SYQURE_MHE_KEY_DIR
+# ([job.mhe_keys] in syqure.toml, or --mhe-keys). Call `mpc()` from here instead
+# of `sequre.mpc()`; without a key directory it sets MHE up as usual.
+import os
+import prg
+import sys
+
+from pickler import dump, load
+
+from sequre.constants import RUN_TOGGLES, MPC_FIELD_SIZE, MPC_RING_SIZE, LATTISEQ_DEFAULT_SIGMA
+from sequre.mpc.env import MPCEnv
+from sequre.lattiseq.params import DEFAULT_PARAMS
+from sequre.lattiseq.ckks import new_parameters_from_literal
+from sequre.lattiseq.rlwe import SecretKey, PublicKey, RelinearizationKey, RotationKeySet
+from sequre.lattiseq.dckks import new_refresh_protocol
+
+from C import access(cobj, int) -> int
+from C import remove(cobj) -> int
+
+KEY_DIR_ENV = "SYQURE_MHE_KEY_DIR"
+KEY_SET_FILE = "key_set"
+PRECISION = u64(256)
+
+
+def _key_set(key_dir: str) -> str:
+    path = f"{key_dir}/{KEY_SET_FILE}"
+    if access(path.c_str(), 0) != 0:
+        return ""
+    with open(path) as f:
+        return f.read().strip()
+
+
+def _load(key_dir: str, name: str, T: type) -> T:
+    with gzopen(f"{key_dir}/{name}.gz") as f:
+        return load(f, T=T)
+
+
+def _store(data, key_dir: str, name: str):
+    with gzopen(f"{key_dir}/{name}.gz", "wb") as f:
+        dump(data, f)
+
+
+def setup(env):
+    """MHE setup for `env`, loading the cached keys when every party holds the same set."""
+    mhe = env.mhe
+    key_dir = os.getenv(KEY_DIR_ENV, default="")
+    if key_dir == "":
+        mhe.default_setup()
+        return
+
+    params = new_parameters_from_literal(DEFAULT_PARAMS)
+    mhe._set_params(params)
+    key_set = _key_set(key_dir)
+    # The keys are generated collectively, so one party's set is useless alone.
+    sets = env.comms.collect(key_set, include_trusted_dealer=True)
+    if key_set != "" and all(s == key_set for s in sets):
+        print(f"CP{env.pid}:\tLoading cached MHE keys {key_set} ...")
+        mhe.crypto_params.initialize(
+            _load(key_dir, "sk_shard", SecretKey),
+            _load(key_dir, "pk", PublicKey),
+            _load(key_dir, "rlk", RelinearizationKey),
+            _load(key_dir, "rtks", RotationKeySet),
+            PRECISION)
+        mhe.refresh_protocol = new_refresh_protocol(params, mhe.bootstrap_log_bound, LATTISEQ_DEFAULT_SIGMA)
+        print(f"CP{env.pid}:\tMHE initialized.")
+        return
+
+    remove(f"{key_dir}/{KEY_SET_FILE}".c_str())
+    mhe.collective_init(params, PRECISION)
+    crypto = mhe.crypto_params
+    _store(crypto.sk_shard, key_dir, "sk_shard")
+    _store(crypto.pk, key_dir, "pk")
+    _store(crypto.rlk, key_dir, "rlk")
+    _store(crypto.rotks, key_dir, "rtks")
+    # Every party draws the same id from the shared seed.
+    env.randomness.switch_seed(-1)
+    new_set = f"{prg.getrandbits(64)}"
+    env.randomness.restore_seed(-1)
+    with open(f"{key_dir}/{KEY_SET_FILE}", "w") as f:
+        f.write(new_set + "\n")
+    print(f"CP{env.pid}:\tSaved MHE keys {new_set}.")
+
+
+def mpc():
+    """`sequre.mpc()` with the cached-key MHE setup."""
+    command = sys.argv[1:]
+    flags = [arg for arg in command if arg.startswith('--')]
+    args = [arg for arg in command if arg not in flags]
+    toggles = {toggle: toggle in flags for toggle in RUN_TOGGLES}
+
+    modulus = MPC_RING_SIZE if toggles[RUN_TOGGLES.USE_RING_FLAG] else MPC_FIELD_SIZE
+    env = MPCEnv(int(args[0]), False, modulus)
+    if not toggles[RUN_TOGGLES.MHE_SETUP_SKIP_FLAG]:
+        setup(env)
+    return env
+"##;
+
+/// Bumped whenever the store layout or fingerprint below changes.
+const STORE_FORMAT: &str = "syqure-mhe-keys-v1";
+
+/// Written by syqure once a run has left a complete key set behind.
+const META_FILE: &str = "keys.json";
+
+/// Written by the helper after every key file is in place.
+const KEY_SET_FILE: &str = "key_set";
+
+/// Key files the helper writes.
+const KEY_FILES: &[&str] = &["sk_shard.gz", "pk.gz", "rlk.gz", "rtks.gz"];
+
+const SEALED_SUFFIX: &str = ".sealed";
+
+/// Group of jobs without a name of their own.
+pub const DEFAULT_GROUP: &str = "default";
+
+/// How cached keys are kept at rest.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum KeyProtection {
+    /// Plain files in an owner-only directory; refused once others can read it.
+    #[default]
+    OwnerOnly,
+    /// Sealed with `seal_key`; opened into a private directory for the run only.
+    Sealed,
+}
+
+impl fmt::Display for KeyProtection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyProtection::OwnerOnly => write!(f, "owner-only"),
+            KeyProtection::Sealed => write!(f, "sealed"),
+        }
+    }
+}
+
+impl FromStr for KeyProtection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "owner-only" => Ok(KeyProtection::OwnerOnly),
+            "sealed" => Ok(KeyProtection::Sealed),
+            other => Err(format!(
+                "unknown key protection '{}' (owner-only or sealed)",
+                other
+            )),
+        }
+    }
+}
+
+/// The `[job.mhe_keys]` table: reuse MHE key material across the runs of a job
+/// group instead of generating it every time.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MheKeyConfig {
+    /// Store root (default: `<config dir>/mhe-keys`); site-local.
+    #[serde(default)]
+    pub dir: Option<PathBuf>,
+    /// Jobs of one group share keys; every party must use the same name
+    /// (default: `[job].name`, else `default`).
+    #[serde(default)]
+    pub group: Option<String>,
+    #[serde(default)]
+    pub protection: KeyProtection,
+    /// Message key file (`syqure message-key`) sealing the keys at rest.
+    #[serde(default)]
+    pub seal_key: Option<PathBuf>,
+    /// Generate new keys once the cached ones are this many days old.
+    #[serde(default)]
+    pub max_age_days: Option<u64>,
+}
+
+/// Bookkeeping next to a cached key set.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyMeta {
+    pub format: String,
+    pub group: String,
+    pub pid: usize,
+    pub parties: usize,
+    /// SHA-256 over the bundle signature, group and party layout the keys were made for.
+    pub fingerprint: String,
+    pub protection: KeyProtection,
+    pub key_set: String,
+    pub created_at_ms: u64,
+}
+
+impl MheKeyConfig {
+    /// Store root, absolute since parties may run from their own data dir.
+    pub fn root(&self) -> Result<PathBuf> {
+        match self.dir {
+            Some(ref dir) if dir.is_relative() => Ok(std::env::current_dir()?.join(dir)),
+            Some(ref dir) => Ok(dir.clone()),
+            None => {
+                let dir = config_dir()
+                    .ok_or_else(|| anyhow!("cannot determine syqure config directory"))?;
+                Ok(dir.join("mhe-keys"))
+            }
+        }
+    }
+
+    pub fn group(&self) -> &str {
+        self.group.as_deref().unwrap_or(DEFAULT_GROUP)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        check_group(self.group())?;
+        match (self.protection, &self.seal_key) {
+            (KeyProtection::Sealed, None) => Err(anyhow!("sealed MHE keys need a seal_key file")),
+            (KeyProtection::OwnerOnly, Some(_)) => Err(anyhow!(
+                "seal_key only applies with protection = \"sealed\""
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Directory holding party `pid`'s keys for this group.
+    pub fn slot(&self, pid: usize) -> Result<PathBuf> {
+        Ok(self.root()?.join(self.group()).join(format!("CP{}", pid)))
+    }
+
+    /// Prepare party `pid`'s keys for one run of a `parties`-party job. Keys
+    /// made for another bundle, group or party count, or older than
+    /// `max_age_days`, are discarded first.
+    pub fn open(&self, pid: usize, parties: usize) -> Result<MheKeySession> {
+        self.validate()?;
+        let slot = self.slot(pid)?;
+        check_private(&slot)?;
+        if let Some(group_dir) = slot.parent() {
+            create_private_dir(group_dir)?;
+        }
+        create_private_dir(&slot)?;
+
+        let fingerprint = fingerprint(self.group(), pid, parties)?;
+        let meta = read_meta(&slot);
+        let stale = match meta {
+            None => has_key_files(&slot).then_some("incomplete"),
+            Some(ref meta) if meta.format != STORE_FORMAT || meta.fingerprint != fingerprint => {
+                Some("made for another bundle or party layout")
+            }
+            Some(ref meta) if meta.protection != self.protection => {
+                Some("stored with another protection")
+            }
+            Some(ref meta) if self.expired(meta) => Some("expired"),
+            Some(_) => None,
+        };
+        if let Some(reason) = stale {
+            eprintln!(
+                "CP{}: discarding MHE keys in {} ({})",
+                pid,
+                slot.display(),
+                reason
+            );
+            clear_slot(&slot)?;
+        }
+
+        let seal = match self.protection {
+            KeyProtection::OwnerOnly => None,
+            KeyProtection::Sealed => {
+                let path = self.seal_key.as_ref().expect("validated above");
+                Some(MessageKey::load(path)?)
+            }
+        };
+        let mut session = MheKeySession {
+            dir: slot.clone(),
+            cached: None,
+            slot,
+            group: self.group().to_string(),
+            pid,
+            parties,
+            fingerprint,
+            protection: self.protection,
+            seal,
+            work: None,
+        };
+        session.unseal()?;
+        session.cached = read_key_set(&session.dir);
+        match session.cached {
+            Some(ref set) => println!("CP{}: cached MHE keys {}", pid, set),
+            None => println!("CP{}: no cached MHE keys yet; this run generates them", pid),
+        }
+        Ok(session)
+    }
+
+    fn expired(&self, meta: &KeyMeta) -> bool {
+        let Some(days) = self.max_age_days else {
+            return false;
+        };
+        let max_age = Duration::from_secs(days * 24 * 60 * 60).as_millis() as u64;
+        now_ms().saturating_sub(meta.created_at_ms) > max_age
+    }
+}
+
+/// One party's cached keys, prepared for a single run.
+#[derive(Debug)]
+pub struct MheKeySession {
+    /// Directory to export as `SYQURE_MHE_KEY_DIR`.
+    pub dir: PathBuf,
+    /// Key set found in the store, if any.
+    pub cached: Option<String>,
+    slot: PathBuf,
+    group: String,
+    pid: usize,
+    parties: usize,
+    fingerprint: String,
+    protection: KeyProtection,
+    seal: Option<MessageKey>,
+    /// Private directory holding unsealed keys for the run.
+    work: Option<PathBuf>,
+}
+
+impl MheKeySession {
+    /// Keep the key set the run left behind: seal it when the store is sealed
+    /// and record it. Returns the key set now stored.
+    pub fn finish(self) -> Result<Option<String>> {
+        let Some(key_set) = read_key_set(&self.dir) else {
+            return Ok(None);
+        };
+        if let Some(ref key) = self.seal {
+            let cipher = key.channel(&self.seal_context(), self.pid, self.pid);
+            for (seq, name) in KEY_FILES.iter().enumerate() {
+                let plain = std::fs::read(self.dir.join(name))
+                    .with_context(|| format!("failed to read MHE key file {}", name))?;
+                let sealed = cipher.seal(seq as u64, &plain)?;
+                write_message(
+                    &self.slot.join(format!("{}{}", name, SEALED_SUFFIX)),
+                    &sealed,
+                )?;
+            }
+            write_message(
+                &self.slot.join(KEY_SET_FILE),
+                format!("{}\n", key_set).as_bytes(),
+            )?;
+        }
+        restrict_files(&self.slot)?;
+        if self.cached.as_deref() != Some(key_set.as_str()) {
+            let meta = KeyMeta {
+                format: STORE_FORMAT.to_string(),
+                group: self.group.clone(),
+                pid: self.pid,
+                parties: self.parties,
+                fingerprint: self.fingerprint.clone(),
+                protection: self.protection,
+                key_set: key_set.clone(),
+                created_at_ms: now_ms(),
+            };
+            write_message(
+                &self.slot.join(META_FILE),
+                &serde_json::to_vec_pretty(&meta)?,
+            )?;
+            println!("CP{}: stored MHE keys {}", self.pid, key_set);
+        }
+        Ok(Some(key_set))
+    }
+
+    /// Files to remove if the process is interrupted mid-run.
+    pub fn transient(&self) -> Option<&Path> {
+        self.work.as_deref()
+    }
+
+    fn seal_context(&self) -> String {
+        format!("mhe-keys/{}", self.group)
+    }
+
+    fn unseal(&mut self) -> Result<()> {
+        let Some(ref key) = self.seal else {
+            return Ok(());
+        };
+        let work = self.slot.join(format!("open-{}", std::process::id()));
+        if work.exists() {
+            std::fs::remove_dir_all(&work)?;
+        }
+        create_private_dir(&work)?;
+        self.dir = work.clone();
+        self.work = Some(work.clone());
+        let Some(key_set) = read_key_set(&self.slot) else {
+            return Ok(());
+        };
+        let cipher = key.channel(&self.seal_context(), self.pid, self.pid);
+        for (seq, name) in KEY_FILES.iter().enumerate() {
+            let path = self.slot.join(format!("{}{}", name, SEALED_SUFFIX));
+            let sealed = std::fs::read(&path)
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            let plain = cipher.open(seq as u64, &sealed).map_err(|_| {
+                anyhow!(
+                    "{} does not open with the seal key (wrong key or tampered)",
+                    path.display()
+                )
+            })?;
+            write_private(&work.join(name), &plain)?;
+        }
+        write_private(
+            &work.join(KEY_SET_FILE),
+            format!("{}\n", key_set).as_bytes(),
+        )?;
+        Ok(())
+    }
+}
+
+impl Drop for MheKeySession {
+    fn drop(&mut self) {
+        if let Some(ref work) = self.work {
+            let _ = std::fs::remove_dir_all(work);
+        }
+    }
+}
+
+/// What a group's store holds for one party.
+#[derive(Debug, Clone, Serialize)]
+pub struct StoredKeys {
+    pub group: String,
+    pub pid: usize,
+    pub dir: PathBuf,
+    /// None when the directory holds no complete key set.
+    pub meta: Option<KeyMeta>,
+}
+
+/// Every party slot under `root`, by group and pid.
+pub fn list(root: &Path) -> Result<Vec<StoredKeys>> {
+    let mut stored = Vec::new();
+    for group in read_dir_names(root)? {
+        let group_dir = root.join(&group);
+        for name in read_dir_names(&group_dir)? {
+            let Some(pid) = name.strip_prefix("CP").and_then(|p| p.parse().ok()) else {
+                continue;
+            };
+            let dir = group_dir.join(&name);
+            stored.push(StoredKeys {
+                meta: read_meta(&dir),
+                group: group.clone(),
+                pid,
+                dir,
+            });
+        }
+    }
+    stored.sort_by(|a, b| (&a.group, a.pid).cmp(&(&b.group, b.pid)));
+    Ok(stored)
+}
+
+/// Remove cached keys under `root`: one group, or every group, optionally for
+/// one party only. Returns the slots removed.
+pub fn clear(root: &Path, group: Option<&str>, pid: Option<usize>) -> Result<Vec<PathBuf>> {
+    if let Some(group) = group {
+        check_group(group)?;
+    }
+    let mut removed = Vec::new();
+    for slot in list(root)? {
+        if group.is_some_and(|g| g != slot.group) || pid.is_some_and(|p| p != slot.pid) {
+            continue;
+        }
+        std::fs::remove_dir_all(&slot.dir)
+            .with_context(|| format!("failed to remove {}", slot.dir.display()))?;
+        removed.push(slot.dir);
+    }
+    Ok(removed)
+}
+
+fn check_group(group: &str) -> Result<()> {
+    let valid = !group.is_empty()
+        && !group.starts_with('.')
+        && group
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !valid {
+        return Err(anyhow!(
+            "MHE key group '{}' may only use letters, digits, '-', '_' and '.' \
+             (set [job.mhe_keys].group)",
+            group
+        ));
+    }
+    Ok(())
+}
+
+fn fingerprint(group: &str, pid: usize, parties: usize) -> Result<String> {
+    let mut hasher = Sha256::new();
+    hasher.update(format!("{}\n", STORE_FORMAT).as_bytes());
+    hasher.update(format!("bundle {}\n", bundle::signature()?).as_bytes());
+    hasher.update(format!("group {}\n", group).as_bytes());
+    hasher.update(format!("party {} of {}\n", pid, parties).as_bytes());
+    Ok(hex(&hasher.finalize()))
+}
+
+/// Refuse a slot other users can get into, rather than quietly tightening it.
+fn check_private(dir: &Path) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if let Ok(meta) = std::fs::metadata(dir) {
+            let mode = meta.permissions().mode();
+            if mode & 0o077 != 0 {
+                return Err(anyhow!(
+                    "{} is accessible by other users (mode {:o}); run chmod 700 on it",
+                    dir.display(),
+                    mode & 0o777
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn restrict_files(dir: &Path) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                std::fs::set_permissions(entry.path(), std::fs::Permissions::from_mode(0o600))?;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+fn write_private(path: &Path, bytes: &[u8]) -> Result<()> {
+    std::fs::write(path, bytes).with_context(|| format!("failed to write {}", path.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(())
+}
+
+fn read_meta(slot: &Path) -> Option<KeyMeta> {
+    let bytes = std::fs::read(slot.join(META_FILE)).ok()?;
+    serde_json::from_slice(&bytes).ok()
+}
+
+fn read_key_set(dir: &Path) -> Option<String> {
+    let text = std::fs::read_to_string(dir.join(KEY_SET_FILE)).ok()?;
+    Some(text.trim().to_string()).filter(|set| !set.is_empty())
+}
+
+fn has_key_files(slot: &Path) -> bool {
+    read_dir_names(slot).is_ok_and(|names| !names.is_empty())
+}
+
+/// Empty a slot, keeping the directory and its permissions.
+fn clear_slot(slot: &Path) -> Result<()> {
+    for entry in std::fs::read_dir(slot)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            std::fs::remove_dir_all(&path)?;
+        } else {
+            std::fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+fn read_dir_names(dir: &Path) -> Result<Vec<String>> {
+    match std::fs::read_dir(dir) {
+        Ok(entries) => {
+            let mut names = Vec::new();
+            for entry in entries {
+                names.push(entry?.file_name().to_string_lossy().into_owned());
+            }
+            Ok(names)
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e).with_context(|| format!("failed to read {}", dir.display())),
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::create_unique_private_dir;
+
+    fn store(name: &str) -> PathBuf {
+        create_unique_private_dir(&std::env::temp_dir(), &format!("syqure-mhe-test-{}-", name))
+            .unwrap()
+    }
+
+    fn seal_key(root: &Path, byte: u8) -> PathBuf {
+        let path = root.join(format!("seal-{}.key", byte));
+        std::fs::write(&path, hex(&[byte; 32])).unwrap();
+        path
+    }
+
+    /// What the helper leaves behind after generating keys.
+    fn fake_run(session: &MheKeySession, key_set: &str) {
+        for name in KEY_FILES {
+            std::fs::write(session.dir.join(name), format!("{} {}", name, key_set)).unwrap();
+        }
+        std::fs::write(session.dir.join(KEY_SET_FILE), format!("{}\n", key_set)).unwrap();
+    }
+
+    fn cached(config: &MheKeyConfig, parties: usize) -> Option<String> {
+        config.open(0, parties).unwrap().cached.clone()
+    }
+
+    fn age_keys(config: &MheKeyConfig, days: u64) {
+        let path = config.slot(0).unwrap().join(META_FILE);
+        let mut meta = read_meta(path.parent().unwrap()).unwrap();
+        meta.created_at_ms -= days * 24 * 60 * 60 * 1000;
+        std::fs::write(&path, serde_json::to_vec(&meta).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn cached_keys_are_discarded_once_stale() {
+        let root = store("stale");
+        let mut config = MheKeyConfig {
+            dir: Some(root.clone()),
+            group: Some("sum".into()),
+            ..MheKeyConfig::default()
+        };
+        let session = config.open(0, 3).unwrap();
+        assert_eq!(session.cached, None);
+        fake_run(&session, "111");
+        assert_eq!(session.finish().unwrap().as_deref(), Some("111"));
+        assert_eq!(cached(&config, 3).as_deref(), Some("111"));
+
+        // Another party layout changes the fingerprint.
+        assert_eq!(cached(&config, 4), None);
+        assert!(!has_key_files(&config.slot(0).unwrap()));
+
+        let session = config.open(0, 3).unwrap();
+        fake_run(&session, "222");
+        session.finish().unwrap();
+        age_keys(&config, 2);
+        config.max_age_days = Some(3);
+        assert_eq!(cached(&config, 3).as_deref(), Some("222"));
+        config.max_age_days = Some(1);
+        assert_eq!(cached(&config, 3), None);
+
+        let session = config.open(0, 3).unwrap();
+        fake_run(&session, "333");
+        session.finish().unwrap();
+        let sealed = MheKeyConfig {
+            protection: KeyProtection::Sealed,
+            seal_key: Some(seal_key(&root, 1)),
+            ..config.clone()
+        };
+        assert_eq!(cached(&sealed, 3), None);
+
+        // A slot others can read is refused, not tightened.
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let slot = config.slot(0).unwrap();
+            std::fs::set_permissions(&slot, std::fs::Permissions::from_mode(0o755)).unwrap();
+            assert!(config.open(0, 3).is_err());
+        }
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn sealed_keys_round_trip() {
+        let root = store("sealed");
+        let config = MheKeyConfig {
+            dir: Some(root.clone()),
+            group: Some("sum".into()),
+            protection: KeyProtection::Sealed,
+            seal_key: Some(seal_key(&root, 1)),
+            max_age_days: None,
+        };
+        let slot = config.slot(0).unwrap();
+        let session = config.open(0, 3).unwrap();
+        let work = session.transient().unwrap().to_path_buf();
+        assert_eq!(session.dir, work);
+        fake_run(&session, "444");
+        assert_eq!(session.finish().unwrap().as_deref(), Some("444"));
+        assert!(!work.exists());
+        for name in KEY_FILES {
+            assert!(!slot.join(name).exists());
+            let sealed = std::fs::read(slot.join(format!("{}{}", name, SEALED_SUFFIX))).unwrap();
+            assert!(!sealed.windows(3).any(|w| w == b"444"));
+        }
+
+        let session = config.open(0, 3).unwrap();
+        assert_eq!(session.cached.as_deref(), Some("444"));
+        for name in KEY_FILES {
+            assert_eq!(
+                std::fs::read_to_string(session.dir.join(name)).unwrap(),
+                format!("{} 444", name)
+            );
+        }
+        drop(session);
+
+        let wrong = MheKeyConfig {
+            seal_key: Some(seal_key(&root, 2)),
+            ..config.clone()
+        };
+        let err = wrong.open(0, 3).unwrap_err().to_string();
+        assert!(err.contains("does not open with the seal key"), "{}", err);
+
+        let path = slot.join(format!("{}{}", KEY_FILES[1], SEALED_SUFFIX));
+        let mut tampered = std::fs::read(&path).unwrap();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        std::fs::write(&path, tampered).unwrap();
+        assert!(config.open(0, 3).is_err());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
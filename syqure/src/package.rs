@@ -0,0 +1,668 @@
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tar::{Archive, Builder, EntryType, Header};
+use zstd::stream::read::Decoder;
+use zstd::stream::write::Encoder;
+
+use crate::attest::{collect_sources, hex, relative_name, unhex, JobAttestation};
+use crate::cluster::{ClusterConfig, Transport, DEFAULT_CLUSTER_FILE};
+use crate::keys::{create_unique_private_dir, Identity, Keystore, PublicIdentity};
+use crate::mhe::MheKeyConfig;
+use crate::runner::CompileOptions;
+use crate::transport::tls::cert_fingerprint;
+
+/// Format tag of job packages; bump on incompatible changes.
+const PACKAGE_FORMAT: &str = "syqure-job-v1";
+
+const MANIFEST_FILE: &str = "manifest.json";
+const SIGNATURES_FILE: &str = "signatures.json";
+const SOURCE_DIR: &str = "src";
+
+/// Largest unpacked package accepted, so a hostile archive cannot fill the disk.
+const MAX_PACKAGE_BYTES: u64 = 256 << 20;
+
+/// What a party does in the job.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PartyRole {
+    pub pid: usize,
+    pub host: String,
+    /// `dealer` for CP0, `compute` otherwise.
+    pub role: String,
+    /// Package path of the program this party runs.
+    pub source: String,
+    /// Arguments this party's program gets.
+    pub program_args: Vec<String>,
+}
+
+/// `manifest.json`: what the package runs and the hash of every file in it.
+/// Signatures cover its exact bytes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobManifest {
+    pub format: String,
+    pub name: Option<String>,
+    /// Package path of the main source file.
+    pub source: String,
+    /// Attestation hash every party must reproduce before running.
+    pub expected_hash: String,
+    /// Signature of the Codon/Sequre bundle the job was packed with.
+    pub bundle_signature: String,
+    /// Attested compile and runtime options shared by every party (program
+    /// arguments are listed per party).
+    pub options: BTreeMap<String, String>,
+    pub transport: Transport,
+    pub parties: Vec<PartyRole>,
+    /// SHA-256 of every other file in the package, by package path.
+    pub files: BTreeMap<String, String>,
+    /// Version of syqure that packed the job.
+    pub packed_with: String,
+}
+
+impl JobManifest {
+    pub fn release(&self) -> bool {
+        self.options.get("release").map(String::as_str) == Some("true")
+    }
+}
+
+/// One signature over the manifest.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PackageSignature {
+    pub signer: PublicIdentity,
+    /// Ed25519 signature over `manifest.json`, hex.
+    pub signature: String,
+}
+
+impl PackageSignature {
+    fn new(identity: &Identity, manifest: &[u8]) -> Self {
+        Self {
+            signer: identity.public().clone(),
+            signature: hex(&identity.sign(manifest)),
+        }
+    }
+
+    fn verify(&self, manifest: &[u8]) -> Result<()> {
+        self.signer.validate()?;
+        let signature = unhex(&self.signature)
+            .ok_or_else(|| anyhow!("signature of '{}' is not hex", self.signer.name))?;
+        self.signer.verify(manifest, &signature)
+    }
+}
+
+/// Compile options a packaged job runs with; the same on every site.
+fn package_options(release: bool) -> CompileOptions {
+    CompileOptions {
+        release,
+        ..CompileOptions::default()
+    }
+}
+
+/// Pack `cluster`'s job into `out`, signed by `signer`. Program arguments and
+/// `skip_mhe_setup` from `opts` are folded into the packaged config; site-local
+/// settings (message directory and key, TLS keys, data dirs, inputs) are left out.
+pub fn pack(
+    cluster: &ClusterConfig,
+    opts: &CompileOptions,
+    signer: &Identity,
+    out: &Path,
+) -> Result<JobManifest> {
+    cluster.validate()?;
+    let main = cluster
+        .job
+        .source
+        .canonicalize()
+        .with_context(|| format!("source file not found: {}", cluster.job.source.display()))?;
+    let root = main.parent().map(Path::to_path_buf).unwrap_or_default();
+    let package_path = |path: &Path| -> Result<String> {
+        let name = relative_name(&root, path);
+        if name.starts_with("..") {
+            return Err(anyhow!(
+                "{} is outside {}; move it under the job source's directory to pack it",
+                path.display(),
+                root.display()
+            ));
+        }
+        Ok(format!("{}/{}", SOURCE_DIR, name))
+    };
+
+    let mut merged = cluster.clone();
+    merged
+        .job
+        .program_args
+        .extend(opts.program_args.iter().cloned());
+    merged.job.skip_mhe_setup |= opts.runtime.skip_mhe_setup;
+    // Attesting first writes the generated helpers the programs import, so
+    // they are packed along with them.
+    let attestation = merged.attest(&package_options(opts.release))?;
+
+    let mut files = BTreeMap::new();
+    let mut roles = Vec::new();
+    let mut packaged = merged.clone();
+    packaged.job.source = PathBuf::from(package_path(&main)?);
+    packaged.job.message_dir = None;
+    packaged.job.message_key = None;
+    // Each site decides where and how it keeps MHE keys; the group is shared.
+    packaged.job.mhe_keys = merged.mhe_keys(None).map(|keys| MheKeyConfig {
+        group: keys.group,
+        max_age_days: keys.max_age_days,
+        ..MheKeyConfig::default()
+    });
+    for party in &mut packaged.parties {
+        let source = merged.source(party.pid).canonicalize()?;
+        for path in collect_sources(&source)? {
+            if let Entry::Vacant(entry) = files.entry(package_path(&path)?) {
+                entry.insert(
+                    std::fs::read(&path)
+                        .with_context(|| format!("failed to read {}", path.display()))?,
+                );
+            }
+        }
+        if party.source.is_some() {
+            party.source = Some(PathBuf::from(package_path(&source)?));
+        }
+        roles.push(PartyRole {
+            pid: party.pid,
+            host: party.host.clone(),
+            role: if party.pid == 0 { "dealer" } else { "compute" }.to_string(),
+            source: package_path(&source)?,
+            program_args: merged
+                .compile_options(party.pid, &CompileOptions::default())
+                .program_args,
+        });
+        if party.cert.is_some() && party.cert_sha256.is_none() {
+            party.cert_sha256 = Some(cluster.fingerprint(party.pid)?);
+        }
+        party.cert = None;
+        party.key = None;
+        party.data_dir = None;
+        party.inputs.clear();
+    }
+    files.insert(
+        DEFAULT_CLUSTER_FILE.to_string(),
+        toml::to_string(&packaged)?.into_bytes(),
+    );
+
+    let first = attestation
+        .parties
+        .values()
+        .next()
+        .ok_or_else(|| anyhow!("job has no parties"))?;
+    let mut options = first.options.clone();
+    options.remove("program_args");
+    let manifest = JobManifest {
+        format: PACKAGE_FORMAT.to_string(),
+        name: cluster.job.name.clone(),
+        source: packaged.job.source.to_string_lossy().into_owned(),
+        expected_hash: attestation.hash.clone(),
+        bundle_signature: first.bundle_signature.clone(),
+        options,
+        transport: cluster.job.transport,
+        parties: roles,
+        files: files
+            .iter()
+            .map(|(name, bytes)| (name.clone(), hex(&Sha256::digest(bytes))))
+            .collect(),
+        packed_with: env!("CARGO_PKG_VERSION").to_string(),
+    };
+    let manifest_bytes = serde_json::to_vec_pretty(&manifest)?;
+    let signatures = vec![PackageSignature::new(signer, &manifest_bytes)];
+    files.insert(MANIFEST_FILE.to_string(), manifest_bytes);
+    files.insert(
+        SIGNATURES_FILE.to_string(),
+        serde_json::to_vec_pretty(&signatures)?,
+    );
+    write_archive(out, &files)?;
+    Ok(manifest)
+}
+
+/// Add `signer`'s signature to an existing package, e.g. once a site has
+/// reviewed it. Returns the number of signatures now on the package.
+pub fn countersign(package: &Path, signer: &Identity) -> Result<usize> {
+    let mut files = read_archive(package)?;
+    let (manifest_bytes, _) = check_contents(&files)?;
+    let mut signatures = read_signatures(&files)?;
+    for signature in &signatures {
+        signature.verify(&manifest_bytes)?;
+    }
+    let fingerprint = signer.public().fingerprint();
+    signatures.retain(|s| s.signer.fingerprint() != fingerprint);
+    signatures.push(PackageSignature::new(signer, &manifest_bytes));
+    files.insert(
+        SIGNATURES_FILE.to_string(),
+        serde_json::to_vec_pretty(&signatures)?,
+    );
+    write_archive(package, &files)?;
+    Ok(signatures.len())
+}
+
+/// A verified package unpacked on disk. The directory is removed when this is
+/// dropped unless the caller chose it.
+#[derive(Debug)]
+pub struct UnpackedJob {
+    pub dir: PathBuf,
+    pub manifest: JobManifest,
+    /// Keystore names of the trusted keys that signed the package.
+    pub trusted_signers: Vec<String>,
+    /// The attestation reproduced from the unpacked files.
+    pub attestation: JobAttestation,
+    temporary: bool,
+}
+
+impl Drop for UnpackedJob {
+    fn drop(&mut self) {
+        if self.temporary {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+}
+
+/// Settings a site supplies when running a packaged job.
+#[derive(Debug, Clone, Default)]
+pub struct SiteOptions {
+    /// Shared message directory (file transport).
+    pub message_dir: Option<PathBuf>,
+    /// Job key file (file transport).
+    pub message_key: Option<PathBuf>,
+    /// This party's certificate (tls transport); must match the packaged pin.
+    pub cert: Option<PathBuf>,
+    /// Private key for `cert`.
+    pub key: Option<PathBuf>,
+}
+
+impl UnpackedJob {
+    /// The packaged cluster config with `site`'s settings for party `pid` applied.
+    pub fn cluster(&self, pid: usize, site: &SiteOptions) -> Result<ClusterConfig> {
+        let mut cluster = ClusterConfig::load(self.dir.join(DEFAULT_CLUSTER_FILE))?;
+        cluster.job.message_dir = site.message_dir.clone();
+        cluster.job.message_key = site.message_key.clone();
+        let party = cluster
+            .parties
+            .iter_mut()
+            .find(|p| p.pid == pid)
+            .ok_or_else(|| anyhow!("the package has no party with pid {}", pid))?;
+        if let Some(ref cert) = site.cert {
+            let fingerprint = cert_fingerprint(cert)?;
+            if party.cert_sha256.as_deref() != Some(fingerprint.as_str()) {
+                return Err(anyhow!(
+                    "{} ({}) is not the certificate the package pins for CP{}",
+                    cert.display(),
+                    fingerprint,
+                    pid
+                ));
+            }
+            party.cert = Some(cert.clone());
+        }
+        party.key = site.key.clone();
+        Ok(cluster)
+    }
+}
+
+/// Unpack `package` into `into` (a fresh temporary directory when None) and
+/// check it: every file matches the manifest, at least one signature comes
+/// from a key in `keystore`, and the program attests to the expected hash with
+/// the local bundle.
+pub fn verify(package: &Path, into: Option<&Path>, keystore: &Keystore) -> Result<UnpackedJob> {
+    let files = read_archive(package)?;
+    let (manifest_bytes, manifest) = check_contents(&files)?;
+
+    let trusted: BTreeMap<String, String> = keystore
+        .list()?
+        .into_iter()
+        .map(|entry| (entry.fingerprint, entry.name))
+        .collect();
+    let mut trusted_signers = Vec::new();
+    let mut untrusted = Vec::new();
+    for signature in read_signatures(&files)? {
+        signature
+            .verify(&manifest_bytes)
+            .context("package manifest was modified after signing")?;
+        let fingerprint = signature.signer.fingerprint();
+        match trusted.get(&fingerprint) {
+            Some(name) => trusted_signers.push(name.clone()),
+            None => untrusted.push(format!("'{}' ({})", signature.signer.name, fingerprint)),
+        }
+    }
+    if trusted_signers.is_empty() {
+        let signers = if untrusted.is_empty() {
+            "nobody".to_string()
+        } else {
+            untrusted.join(", ")
+        };
+        return Err(anyhow!(
+            "package is not signed by a key in {} (signed by {}); import the packer's \
+             identity with `syqure keys import-peer` after checking its fingerprint",
+            keystore.root().display(),
+            signers
+        ));
+    }
+
+    let (dir, temporary) = match into {
+        Some(dir) => {
+            if std::fs::read_dir(dir).is_ok_and(|mut entries| entries.next().is_some()) {
+                return Err(anyhow!(
+                    "{} is not empty; unpack into a new directory",
+                    dir.display()
+                ));
+            }
+            (dir.to_path_buf(), false)
+        }
+        // Never a predictable path: another user could have planted it.
+        None => (
+            create_unique_private_dir(&std::env::temp_dir(), "syqure-job-")
+                .context("failed to create a directory to unpack the package")?,
+            true,
+        ),
+    };
+    let mut job = UnpackedJob {
+        dir,
+        manifest,
+        trusted_signers,
+        attestation: JobAttestation::default(),
+        temporary,
+    };
+    for (name, bytes) in &files {
+        let path = job.dir.join(name);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create {}", parent.display()))?;
+        }
+        std::fs::write(&path, bytes)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+    }
+
+    let cluster = ClusterConfig::load(job.dir.join(DEFAULT_CLUSTER_FILE))?;
+    if cluster.job.source != job.dir.join(&job.manifest.source) {
+        return Err(anyhow!(
+            "packaged syqure.toml runs {} but the manifest names {}",
+            cluster.job.source.display(),
+            job.manifest.source
+        ));
+    }
+    job.attestation = cluster.attest(&package_options(job.manifest.release()))?;
+    if job.attestation.hash != job.manifest.expected_hash {
+        let local_bundle = job
+            .attestation
+            .parties
+            .values()
+            .next()
+            .map(|a| a.bundle_signature.clone())
+            .unwrap_or_default();
+        if local_bundle != job.manifest.bundle_signature {
+            return Err(anyhow!(
+                "package was built for Codon/Sequre bundle {} but this syqure has {}",
+                job.manifest.bundle_signature,
+                local_bundle
+            ));
+        }
+        return Err(anyhow!(
+            "program attests to {} but the manifest expects {}",
+            job.attestation.hash,
+            job.manifest.expected_hash
+        ));
+    }
+    Ok(job)
+}
+
+/// The manifest, after checking that the package holds exactly the files it
+/// lists, with matching hashes.
+fn check_contents(files: &BTreeMap<String, Vec<u8>>) -> Result<(Vec<u8>, JobManifest)> {
+    let manifest_bytes = files
+        .get(MANIFEST_FILE)
+        .ok_or_else(|| anyhow!("package has no {}", MANIFEST_FILE))?
+        .clone();
+    let manifest: JobManifest =
+        serde_json::from_slice(&manifest_bytes).context("failed to parse package manifest")?;
+    if manifest.format != PACKAGE_FORMAT {
+        return Err(anyhow!(
+            "unsupported package format '{}' (expected {})",
+            manifest.format,
+            PACKAGE_FORMAT
+        ));
+    }
+    let mut problems = Vec::new();
+    let listed: BTreeSet<&str> = manifest.files.keys().map(String::as_str).collect();
+    for (name, bytes) in files {
+        if name == MANIFEST_FILE || name == SIGNATURES_FILE {
+            continue;
+        }
+        match manifest.files.get(name) {
+            Some(digest) if *digest == hex(&Sha256::digest(bytes)) => {}
+            Some(_) => problems.push(format!("{} does not match the manifest", name)),
+            None => problems.push(format!("{} is not listed in the manifest", name)),
+        }
+    }
+    for name in listed {
+        if !files.contains_key(name) {
+            problems.push(format!("{} is missing", name));
+        }
+    }
+    if !manifest.files.contains_key(&manifest.source) {
+        problems.push(format!("source {} is not in the package", manifest.source));
+    }
+    if problems.is_empty() {
+        Ok((manifest_bytes, manifest))
+    } else {
+        Err(anyhow!(
+            "corrupt job package:\n  - {}",
+            problems.join("\n  - ")
+        ))
+    }
+}
+
+fn read_signatures(files: &BTreeMap<String, Vec<u8>>) -> Result<Vec<PackageSignature>> {
+    match files.get(SIGNATURES_FILE) {
+        Some(bytes) => serde_json::from_slice(bytes).context("failed to parse package signatures"),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Every regular file in the archive, by path. Anything else (links, devices,
+/// absolute or `..` paths) is refused rather than skipped.
+fn read_archive(package: &Path) -> Result<BTreeMap<String, Vec<u8>>> {
+    let file =
+        File::open(package).with_context(|| format!("failed to open {}", package.display()))?;
+    let mut archive = Archive::new(Decoder::new(file)?);
+    let mut files = BTreeMap::new();
+    let mut total = 0u64;
+    for entry in archive
+        .entries()
+        .with_context(|| format!("failed to read {}", package.display()))?
+    {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let safe = path
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+        let name = path
+            .components()
+            .filter_map(|c| match c {
+                Component::Normal(part) => part.to_str(),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("/");
+        if !safe || name.is_empty() {
+            return Err(anyhow!(
+                "package entry {} escapes the package",
+                path.display()
+            ));
+        }
+        match entry.header().entry_type() {
+            EntryType::Directory => continue,
+            EntryType::Regular => {}
+            other => {
+                return Err(anyhow!(
+                    "package entry {} has unsupported type {:?}",
+                    name,
+                    other
+                ))
+            }
+        }
+        total += entry.size();
+        if total > MAX_PACKAGE_BYTES {
+            return Err(anyhow!(
+                "package unpacks to more than {} MiB",
+                MAX_PACKAGE_BYTES >> 20
+            ));
+        }
+        let mut bytes = Vec::new();
+        entry.read_to_end(&mut bytes)?;
+        if files.insert(name.clone(), bytes).is_some() {
+            return Err(anyhow!("package holds {} twice", name));
+        }
+    }
+    Ok(files)
+}
+
+/// Write `files` as a tar.zst, with fixed metadata so packing the same job
+/// twice gives the same archive.
+fn write_archive(out: &Path, files: &BTreeMap<String, Vec<u8>>) -> Result<()> {
+    let tmp = out.with_extension("partial");
+    let file = File::create(&tmp).with_context(|| format!("failed to create {}", tmp.display()))?;
+    let mut builder = Builder::new(Encoder::new(file, 0)?);
+    // The manifest goes first so `tar -t` shows what the package is at a glance.
+    let order = [MANIFEST_FILE, SIGNATURES_FILE]
+        .into_iter()
+        .chain(files.keys().map(String::as_str))
+        .filter(|name| files.contains_key(*name));
+    let mut written = BTreeSet::new();
+    for name in order {
+        if !written.insert(name) {
+            continue;
+        }
+        let bytes = &files[name];
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Regular);
+        header.set_size(bytes.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(0);
+        header.set_cksum();
+        builder.append_data(&mut header, name, bytes.as_slice())?;
+    }
+    builder.into_inner()?.finish()?;
+    std::fs::rename(&tmp, out).with_context(|| format!("failed to write {}", out.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A tar.zst whose entry names are written verbatim, bypassing the checks
+    /// `tar::Builder` applies when packing.
+    fn raw_package(name: &str, entries: &[(&str, EntryType)]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "syqure-package-test-{}-{}.tar.zst",
+            std::process::id(),
+            name
+        ));
+        let mut builder = Builder::new(Encoder::new(File::create(&path).unwrap(), 0).unwrap());
+        for (entry, kind) in entries {
+            let mut header = Header::new_gnu();
+            header.as_old_mut().name[..entry.len()].copy_from_slice(entry.as_bytes());
+            header.set_entry_type(*kind);
+            header.set_size(4);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append(&header, &b"data"[..]).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+        path
+    }
+
+    fn read(name: &str, entries: &[(&str, EntryType)]) -> Result<BTreeMap<String, Vec<u8>>> {
+        let path = raw_package(name, entries);
+        let files = read_archive(&path);
+        std::fs::remove_file(&path).unwrap();
+        files
+    }
+
+    #[test]
+    fn archives_keep_only_plain_relative_files() {
+        let files = read(
+            "ok",
+            &[
+                ("./src/main.codon", EntryType::Regular),
+                ("src/", EntryType::Directory),
+            ],
+        )
+        .unwrap();
+        assert_eq!(files.keys().collect::<Vec<_>>(), ["src/main.codon"]);
+    }
+
+    #[test]
+    fn archives_escaping_the_package_are_refused() {
+        for (case, entry) in [
+            ("parent", "../evil.codon"),
+            ("nested", "src/../../evil.codon"),
+            ("absolute", "/tmp/evil.codon"),
+        ] {
+            let err = read(case, &[(entry, EntryType::Regular)]).unwrap_err();
+            assert!(
+                err.to_string().contains("escapes the package"),
+                "{}: {}",
+                entry,
+                err
+            );
+        }
+        let err = read("link", &[("src/link", EntryType::Symlink)]).unwrap_err();
+        assert!(err.to_string().contains("unsupported type"), "{}", err);
+        let err = read(
+            "twice",
+            &[
+                ("a.codon", EntryType::Regular),
+                ("./a.codon", EntryType::Regular),
+            ],
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("twice"), "{}", err);
+    }
+
+    fn manifest(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let files: BTreeMap<String, String> = files
+            .iter()
+            .map(|(name, bytes)| (name.to_string(), hex(&Sha256::digest(bytes))))
+            .collect();
+        serde_json::to_vec(&serde_json::json!({
+            "format": PACKAGE_FORMAT,
+            "name": null,
+            "source": "src/main.codon",
+            "expected_hash": "",
+            "bundle_signature": "",
+            "options": {},
+            "transport": "tcp",
+            "parties": [],
+            "files": files,
+            "packed_with": "test",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn contents_must_match_the_manifest() {
+        let source = (String::from("src/main.codon"), b"print(1)".to_vec());
+        let mut files = BTreeMap::from([
+            (
+                MANIFEST_FILE.to_string(),
+                manifest(&[("src/main.codon", b"print(1)")]),
+            ),
+            source.clone(),
+        ]);
+        check_contents(&files).unwrap();
+
+        files.insert(source.0.clone(), b"print(2)".to_vec());
+        files.insert("extra.codon".into(), Vec::new());
+        let err = check_contents(&files).unwrap_err().to_string();
+        assert!(err.contains("src/main.codon does not match"), "{}", err);
+        assert!(err.contains("extra.codon is not listed"), "{}", err);
+
+        files.remove(&source.0);
+        let err = check_contents(&files).unwrap_err().to_string();
+        assert!(err.contains("src/main.codon is missing"), "{}", err);
+    }
+}
@@ -0,0 +1,306 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+
+use crate::attest::{attest, JobAttestation};
+use crate::cluster::{ClusterConfig, Transport};
+use crate::inputs::StagedInputs;
+use crate::mhe::{MheKeyConfig, MheKeySession};
+use crate::preflight::preflight;
+use crate::results::{collect_outputs, Outputs};
+use crate::runner::{CompileOptions, RunResult, Syqure};
+use crate::signals::{self, INTERRUPTED_EXIT_CODE};
+use crate::transport::file::{channel_dir, exchange_hellos, hello_path, run_proxy};
+use crate::transport::netem::NetProfile;
+use crate::transport::state::state_path;
+use crate::transport::tls;
+use crate::transport::ChannelStats;
+
+/// Preflight timeout when neither the caller nor the cluster sets one.
+pub const DEFAULT_PREFLIGHT_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// How long to wait for the proxy or relay to report once Sequre has exited.
+const STATS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Options for running one party of a job in this process.
+#[derive(Debug, Clone)]
+pub struct PartyOptions {
+    /// Compile options; `runtime` carries this party's pid and network settings.
+    pub compile: CompileOptions,
+    /// Program to run. With a cluster it replaces `[job].source` for every
+    /// party that doesn't name its own.
+    pub source: Option<PathBuf>,
+    pub cluster: Option<ClusterConfig>,
+    /// Private inputs, by name, on top of the party's `inputs` in the cluster.
+    pub inputs: BTreeMap<String, PathBuf>,
+    /// Compare program hashes with every peer before running.
+    pub preflight: bool,
+    /// Stop once the links are checked (tcp and tls transports).
+    pub check_only: bool,
+    /// Preflight timeout; the cluster's connect timeout when unset.
+    pub preflight_timeout: Option<Duration>,
+    /// Record every message here (file transport only).
+    pub transcript_dir: Option<PathBuf>,
+    /// Emulated network conditions (file transport only).
+    pub net_profile: Option<NetProfile>,
+    /// Wait for the proxy or relay's traffic counts after the run.
+    pub comm_stats: bool,
+    /// Cache MHE keys on top of (or instead of) the cluster's `[job.mhe_keys]`.
+    pub mhe_keys: Option<MheKeyConfig>,
+}
+
+impl Default for PartyOptions {
+    fn default() -> Self {
+        Self {
+            compile: CompileOptions::default(),
+            source: None,
+            cluster: None,
+            inputs: BTreeMap::new(),
+            preflight: true,
+            check_only: false,
+            preflight_timeout: None,
+            transcript_dir: None,
+            net_profile: None,
+            comm_stats: false,
+            mhe_keys: None,
+        }
+    }
+}
+
+/// What running one party produced.
+#[derive(Debug, Clone, Default)]
+pub struct PartyOutcome {
+    pub attestation: JobAttestation,
+    /// Sequre's captured output; None after a check-only run.
+    pub run: Option<RunResult>,
+    /// Traffic per channel (file and tls transports).
+    pub comm_stats: Vec<ChannelStats>,
+    /// Named outputs the program emitted.
+    pub outputs: Outputs,
+    /// Problems that did not fail the run, such as MHE keys that could not be kept.
+    pub warnings: Vec<String>,
+}
+
+/// Run one party: stage its inputs, attest the job, check the links to its
+/// peers, start the proxy or relay its transport needs, then run Sequre here.
+/// On SIGINT or SIGTERM the party's transient files are removed and the
+/// process exits, since there is no child process to forward the signal to.
+pub fn run_party(opts: &PartyOptions) -> Result<PartyOutcome> {
+    let pid = opts.compile.runtime.pid.unwrap_or_default();
+    let cluster = opts.cluster.clone().map(|mut cluster| {
+        if let Some(ref source) = opts.source {
+            cluster.job.source = source.clone();
+        }
+        cluster
+    });
+    let cluster = cluster.as_ref();
+    let base = &opts.compile;
+    let mut compile = match cluster {
+        Some(cluster) => {
+            cluster.validate()?;
+            cluster.compile_options(pid, base)
+        }
+        None => base.clone(),
+    };
+    compile.runtime.validate()?;
+
+    let mut input_files = cluster
+        .and_then(|c| c.party(pid))
+        .map(|p| p.inputs.clone())
+        .unwrap_or_default();
+    input_files.extend(opts.inputs.clone());
+    let staged = if input_files.is_empty() {
+        None
+    } else {
+        let staged = StagedInputs::stage(pid, &input_files)?;
+        compile.runtime.input_dir = Some(staged.dir.clone());
+        Some(staged)
+    };
+
+    let source = match (&opts.source, cluster) {
+        (_, Some(cluster)) => cluster.source(pid).to_path_buf(),
+        (Some(source), None) => source.clone(),
+        (None, None) => return Err(anyhow!("no source file given")),
+    };
+    let source = source.canonicalize().unwrap_or(source);
+    // Parties compare the whole job's hash, so each one also attests the
+    // programs its peers run.
+    let attestation = match cluster {
+        Some(cluster) => cluster.attest(base)?,
+        None => JobAttestation::from_parties([(pid, attest(&source, &compile)?)].into()),
+    };
+    println!("CP{}: program attestation {}", pid, attestation.hash);
+    if !attestation.is_uniform() {
+        attestation.print_parties();
+    }
+    let mut outcome = PartyOutcome {
+        attestation,
+        ..PartyOutcome::default()
+    };
+    let hash = outcome.attestation.hash.clone();
+
+    // Peers only talk to each other directly over TCP; file and tls links are
+    // brokered by the proxies and relays instead.
+    let transport = cluster.map(|c| c.job.transport).unwrap_or_default();
+    if transport == Transport::Tcp && opts.preflight {
+        let timeout = opts
+            .preflight_timeout
+            .or(cluster.map(ClusterConfig::connect_timeout))
+            .unwrap_or(DEFAULT_PREFLIGHT_TIMEOUT);
+        let report = preflight(&compile.runtime, &hash, timeout)?;
+        report.print_table();
+        report.ensure_ok()?;
+        if opts.check_only {
+            return Ok(outcome);
+        }
+    } else if opts.check_only && transport == Transport::File {
+        return Err(anyhow!(
+            "--check only applies to the tcp and tls transports"
+        ));
+    }
+
+    // TLS links are authenticated, and hellos compared, before Sequre starts.
+    let tls_links = match cluster.map(|c| c.tls_relay_options(pid)).transpose()? {
+        Some(Some(mut relay)) => {
+            relay.program_hash = opts.preflight.then(|| hash.clone());
+            let links = tls::establish(&relay)?;
+            links.report.print_table();
+            links.report.ensure_ok()?;
+            if opts.check_only {
+                return Ok(outcome);
+            }
+            Some((relay, links))
+        }
+        _ => None,
+    };
+    if transport != Transport::File {
+        if opts.transcript_dir.is_some() {
+            return Err(anyhow!("--record-transcript needs the file transport"));
+        }
+        if opts.net_profile.is_some() {
+            return Err(anyhow!("--net-profile needs the file transport"));
+        }
+    }
+
+    let key_config = match cluster {
+        Some(cluster) => cluster.mhe_keys(opts.mhe_keys.as_ref()),
+        None => opts.mhe_keys.clone(),
+    };
+    let mhe_keys = match key_config {
+        Some(keys) if !compile.runtime.skip_mhe_setup => {
+            let session = keys.open(pid, compile.runtime.party_count())?;
+            compile.runtime.mhe_key_dir = Some(session.dir.clone());
+            Some(session)
+        }
+        _ => None,
+    };
+
+    // Sequre runs in this process, so on a signal there is nothing to forward to:
+    // clean up this party's transient files and exit.
+    let mut transient: Vec<PathBuf> = staged.iter().map(|s| s.dir.clone()).collect();
+    transient.extend(
+        mhe_keys
+            .iter()
+            .filter_map(|s| s.transient().map(Path::to_path_buf)),
+    );
+    if let Some(proxy) = cluster.and_then(|c| c.proxy_options(pid)) {
+        let job = proxy.job_dir();
+        for peer in (0..proxy.parties).filter(|&p| p != pid) {
+            transient.push(channel_dir(&job, pid, peer));
+        }
+        transient.push(hello_path(&job, pid));
+        if let Some(state) = state_path(&job, pid, pid, pid).parent() {
+            transient.push(state.to_path_buf());
+        }
+    }
+    signals::install()?;
+    let finished = Arc::new(AtomicBool::new(false));
+    let _watcher = SignalWatcher(finished.clone());
+    std::thread::spawn(move || {
+        while !finished.load(Ordering::SeqCst) {
+            if let Some(sig) = signals::received() {
+                eprintln!("CP{}: interrupted by signal {}, cleaning up", pid, sig);
+                if let Ok(dir) = std::env::current_dir() {
+                    signals::remove_sockets(&dir);
+                }
+                for path in &transient {
+                    let _ = std::fs::remove_dir_all(path).or_else(|_| std::fs::remove_file(path));
+                }
+                std::process::exit(INTERRUPTED_EXIT_CODE);
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+    });
+
+    let (stats_tx, stats_rx) = mpsc::channel();
+    if let Some(mut proxy) = cluster.and_then(|c| c.proxy_options(pid)) {
+        proxy.transcript_dir = opts.transcript_dir.clone();
+        proxy.net_profile = opts.net_profile.clone();
+        if opts.preflight {
+            // Compare hashes before Sequre starts, so a mismatch never reaches the JIT.
+            let report = exchange_hellos(&proxy, &hash)?;
+            report.print_table();
+            report.ensure_ok()?;
+        }
+        let stats_tx = stats_tx.clone();
+        std::thread::spawn(move || match run_proxy(&proxy) {
+            Ok(stats) => {
+                let _ = stats_tx.send(stats);
+            }
+            Err(e) => eprintln!("proxy CP{}: {:#}", proxy.pid, e),
+        });
+    }
+    if let Some((relay, links)) = tls_links {
+        let stats_tx = stats_tx.clone();
+        std::thread::spawn(move || match tls::relay(&relay, links) {
+            Ok(stats) => {
+                let _ = stats_tx.send(stats);
+            }
+            Err(e) => eprintln!("relay CP{}: {:#}", relay.pid, e),
+        });
+    }
+    drop(stats_tx);
+    if let Some(dir) = cluster
+        .and_then(|c| c.party(pid))
+        .and_then(|p| p.data_dir.as_ref())
+    {
+        std::fs::create_dir_all(dir)?;
+        std::env::set_current_dir(dir)?;
+    }
+    let results_file = compile.runtime.results_file.clone();
+    let run = Syqure::new(compile).compile_and_maybe_run(&source);
+    // Keys generated before a later failure are still good for the next run,
+    // and keys that cannot be kept do not undo this one.
+    if let Some(Err(e)) = mhe_keys.map(MheKeySession::finish) {
+        outcome
+            .warnings
+            .push(format!("failed to keep MHE keys: {:#}", e));
+    }
+    let run = run.inspect_err(|_| {
+        for warning in &outcome.warnings {
+            eprintln!("warning: CP{}: {}", pid, warning);
+        }
+    })?;
+    outcome.outputs = collect_outputs(results_file.as_deref(), &run.stdout)?;
+    outcome.run = Some(run);
+
+    if opts.comm_stats {
+        // The proxy or relay finishes once Sequre has closed its sockets.
+        outcome.comm_stats = stats_rx.recv_timeout(STATS_TIMEOUT).unwrap_or_default();
+    }
+    Ok(outcome)
+}
+
+/// Stops the signal watcher thread once the party is done, so a long-lived
+/// caller does not collect one per run.
+struct SignalWatcher(Arc<AtomicBool>);
+
+impl Drop for SignalWatcher {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
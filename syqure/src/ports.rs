@@ -0,0 +1,206 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::net::{Ipv4Addr, TcpListener};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::keys::config_dir;
+
+/// Overrides where port lockfiles live. Point every user's jobs at the same
+/// directory to coordinate them across accounts.
+pub const ENV_PORT_LOCK_DIR: &str = "SYQURE_PORT_LOCK_DIR";
+
+/// Ports are reserved in aligned blocks of this size, one lockfile per block.
+pub const PORT_BLOCK: u16 = 100;
+
+/// Where and how `syqure local --auto-ports` looks for free ports.
+#[derive(Debug, Clone)]
+pub struct PortAllocator {
+    /// First port that may be handed out.
+    pub first: u16,
+    /// Last port that may be handed out. Stays below the Linux ephemeral range
+    /// by default, so outgoing connections never take a reserved port.
+    pub last: u16,
+    /// Directory holding the `ports-{base}.lock` files.
+    pub lock_dir: PathBuf,
+}
+
+impl Default for PortAllocator {
+    fn default() -> Self {
+        Self {
+            first: 20000,
+            last: 32699,
+            lock_dir: default_lock_dir(),
+        }
+    }
+}
+
+/// `$SYQURE_PORT_LOCK_DIR`, else a directory of the current user's:
+/// `$XDG_RUNTIME_DIR/syqure-ports`, else `ports` in the config dir. A shared
+/// directory in the temp dir would let another user pre-create or hold the
+/// lockfiles.
+pub fn default_lock_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os(ENV_PORT_LOCK_DIR) {
+        return PathBuf::from(dir);
+    }
+    if let Some(dir) = std::env::var_os("XDG_RUNTIME_DIR").filter(|d| !d.is_empty()) {
+        return PathBuf::from(dir).join("syqure-ports");
+    }
+    match config_dir() {
+        Some(dir) => dir.join("ports"),
+        None => std::env::temp_dir().join(format!("syqure-ports-{}", user_id())),
+    }
+}
+
+/// Ports reserved for one job. Each block stays locked while its lockfile is
+/// open, so the reservation ends when this is dropped or the process exits.
+#[derive(Debug)]
+pub struct PortLease {
+    pub base: u16,
+    pub len: usize,
+    locks: Vec<File>,
+}
+
+impl PortAllocator {
+    /// Reserve `len` consecutive ports that no other job holds and nothing on
+    /// this host is listening on.
+    pub fn reserve(&self, len: usize) -> Result<PortLease> {
+        let blocks = len.div_ceil(PORT_BLOCK as usize).max(1);
+        std::fs::create_dir_all(&self.lock_dir)
+            .with_context(|| format!("failed to create {}", self.lock_dir.display()))?;
+        let first_block = self.first.div_ceil(PORT_BLOCK) as usize;
+        let end_block = (self.last as usize + 1) / PORT_BLOCK as usize;
+        for start in first_block..end_block.saturating_sub(blocks - 1) {
+            let base = (start * PORT_BLOCK as usize) as u16;
+            let mut lease = PortLease {
+                base,
+                len,
+                locks: Vec::new(),
+            };
+            let mut locked = true;
+            for block in start..start + blocks {
+                match try_lock(&self.lock_path((block * PORT_BLOCK as usize) as u16))? {
+                    Some(file) => lease.locks.push(file),
+                    None => {
+                        locked = false;
+                        break;
+                    }
+                }
+            }
+            if locked && ports_free(base, len) {
+                return Ok(lease);
+            }
+        }
+        Err(anyhow!(
+            "no free range of {} ports in {}-{} (locks in {})",
+            len,
+            self.first,
+            self.last,
+            self.lock_dir.display()
+        ))
+    }
+
+    fn lock_path(&self, base: u16) -> PathBuf {
+        self.lock_dir.join(format!("ports-{}.lock", base))
+    }
+}
+
+/// Lock a block's lockfile without waiting. The lock belongs to the open file,
+/// so the kernel drops it when the holder exits, however it exits; the file
+/// itself is never removed, which would let two jobs lock different inodes.
+/// Returns None only when another job holds the lock.
+fn try_lock(path: &Path) -> Result<Option<File>> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+        .with_context(|| format!("failed to open port lock {}", path.display()))?;
+    if !lock_exclusive(&file).with_context(|| format!("failed to lock {}", path.display()))? {
+        return Ok(None);
+    }
+    // Only a hint for whoever looks at the lock directory.
+    if file.set_len(0).is_ok() {
+        let _ = writeln!(file, "{}", std::process::id());
+    }
+    Ok(Some(file))
+}
+
+/// Sequre listens on all interfaces, so check the ports the same way.
+fn ports_free(base: u16, len: usize) -> bool {
+    (0..len).all(|offset| {
+        u16::try_from(base as usize + offset)
+            .is_ok_and(|port| TcpListener::bind((Ipv4Addr::UNSPECIFIED, port)).is_ok())
+    })
+}
+
+/// Ok(false) when the lock is held elsewhere.
+#[cfg(unix)]
+fn lock_exclusive(file: &File) -> io::Result<bool> {
+    use std::os::unix::io::AsRawFd;
+    // SAFETY: the descriptor is owned by `file` and stays open for the call.
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+    let err = io::Error::last_os_error();
+    if err.kind() == io::ErrorKind::WouldBlock {
+        Ok(false)
+    } else {
+        Err(err)
+    }
+}
+
+#[cfg(not(unix))]
+fn lock_exclusive(file: &File) -> io::Result<bool> {
+    match file.try_lock() {
+        Ok(()) => Ok(true),
+        Err(std::fs::TryLockError::WouldBlock) => Ok(false),
+        Err(std::fs::TryLockError::Error(e)) => Err(e),
+    }
+}
+
+#[cfg(unix)]
+fn user_id() -> u32 {
+    // SAFETY: getuid has no preconditions and cannot fail.
+    unsafe { libc::getuid() }
+}
+
+#[cfg(not(unix))]
+fn user_id() -> String {
+    std::env::var("USERNAME").unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn held_blocks_are_skipped_and_released_on_drop() {
+        let lock_dir =
+            std::env::temp_dir().join(format!("syqure-ports-test-{}", std::process::id()));
+        let ports = PortAllocator {
+            lock_dir: lock_dir.clone(),
+            ..PortAllocator::default()
+        };
+        let first = ports.reserve(10).unwrap();
+        let second = ports.reserve(10).unwrap();
+        assert_ne!(first.base, second.base);
+
+        // A lock left behind by a dead holder is just a file nobody has locked.
+        let base = first.base;
+        drop(first);
+        assert!(ports.lock_path(base).exists());
+        assert!(try_lock(&ports.lock_path(base)).unwrap().is_some());
+        let held = ports.lock_path(second.base);
+        assert!(try_lock(&held).unwrap().is_none());
+        drop(second);
+        assert!(try_lock(&held).unwrap().is_some());
+
+        // Failing to open a lockfile is an error, not a held block.
+        let missing = lock_dir.join("missing").join("ports-0.lock");
+        assert!(try_lock(&missing).is_err());
+        std::fs::remove_dir_all(&lock_dir).unwrap();
+    }
+}
@@ -0,0 +1,266 @@
+use std::fmt;
+use std::io::{ErrorKind, Read};
+use std::net::{Ipv4Addr, TcpListener, TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{channel_port, SequreRuntimeConfig, DEFAULT_PORT_BASE};
+use crate::transport::{frame_len, write_frame, FRAME_HEADER_LEN};
+
+/// Version string exchanged in the hello message.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Upper bound on a hello frame, so a stray client can't make us allocate much.
+const MAX_HELLO_LEN: usize = 64 * 1024;
+
+/// Small message each party sends over every link before Sequre starts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hello {
+    pub pid: usize,
+    pub version: String,
+    /// Attestation hash of the program this party is about to run.
+    pub program_hash: String,
+}
+
+impl Hello {
+    pub fn new(pid: usize, program_hash: &str) -> Self {
+        Self {
+            pid,
+            version: VERSION.to_string(),
+            program_hash: program_hash.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkState {
+    Up,
+    /// The peer could not be reached or did not answer in time.
+    Down(String),
+    /// The peer answered but disagrees on version, program or identity.
+    Mismatch(String),
+}
+
+impl fmt::Display for LinkState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkState::Up => write!(f, "up"),
+            LinkState::Down(why) => write!(f, "down: {}", why),
+            LinkState::Mismatch(why) => write!(f, "mismatch: {}", why),
+        }
+    }
+}
+
+/// Status of the link between this party and one peer.
+#[derive(Debug, Clone)]
+pub struct LinkStatus {
+    pub peer: usize,
+    /// Address this party listened on or connected to.
+    pub address: String,
+    pub state: LinkState,
+}
+
+/// Result of checking every link of one party.
+#[derive(Debug, Clone)]
+pub struct PreflightReport {
+    pub local: Hello,
+    pub links: Vec<LinkStatus>,
+}
+
+impl PreflightReport {
+    pub fn ok(&self) -> bool {
+        self.links.iter().all(|l| l.state == LinkState::Up)
+    }
+
+    pub fn print_table(&self) {
+        println!(
+            "Preflight CP{} (syqure {}, program {})",
+            self.local.pid,
+            self.local.version,
+            short_hash(&self.local.program_hash)
+        );
+        println!("  {:<10} {:<24} STATUS", "LINK", "ADDRESS");
+        for link in &self.links {
+            println!(
+                "  {:<10} {:<24} {}",
+                format!("CP{}-CP{}", self.local.pid, link.peer),
+                link.address,
+                link.state
+            );
+        }
+    }
+
+    /// Error out with the status table's content unless every link is up.
+    pub fn ensure_ok(&self) -> Result<()> {
+        if self.ok() {
+            return Ok(());
+        }
+        let failed: Vec<String> = self
+            .links
+            .iter()
+            .filter(|l| l.state != LinkState::Up)
+            .map(|l| format!("CP{}-CP{} {}", self.local.pid, l.peer, l.state))
+            .collect();
+        Err(anyhow!("preflight failed: {}", failed.join("; ")))
+    }
+}
+
+fn short_hash(hash: &str) -> &str {
+    &hash[..hash.len().min(12)]
+}
+
+/// Check every peer link of `runtime.pid` on Sequre's own channel ports: the lower
+/// pid listens, the higher pid connects, and both sides swap a [`Hello`].
+/// The connector hangs up first so the listening port is free for Sequre afterwards.
+pub fn preflight(
+    runtime: &SequreRuntimeConfig,
+    program_hash: &str,
+    timeout: Duration,
+) -> Result<PreflightReport> {
+    runtime.validate()?;
+    let pid = runtime
+        .pid
+        .ok_or_else(|| anyhow!("preflight needs a party id"))?;
+    let parties = runtime.party_count();
+    let base = runtime.port_base.unwrap_or(DEFAULT_PORT_BASE);
+    let local = Hello::new(pid, program_hash);
+
+    let deadline = Instant::now() + timeout;
+    let mut handles = Vec::new();
+    for peer in (0..parties).filter(|&p| p != pid) {
+        let port = channel_port(base, parties, pid, peer)
+            .ok_or_else(|| anyhow!("port for channel {}<->{} overflows", pid, peer))?;
+        let host = runtime.cp_ips[peer].clone();
+        let local = local.clone();
+        handles.push(thread::spawn(move || {
+            let (address, result) = if pid < peer {
+                (
+                    format!("0.0.0.0:{}", port),
+                    listen_hello(port, &local, deadline),
+                )
+            } else {
+                let address = format!("{}:{}", host, port);
+                let result = connect_hello(&address, &local, deadline);
+                (address, result)
+            };
+            let state = match result {
+                Ok(remote) => compare(&local, &remote, peer),
+                Err(e) => LinkState::Down(format!("{:#}", e)),
+            };
+            LinkStatus {
+                peer,
+                address,
+                state,
+            }
+        }));
+    }
+
+    let mut links = Vec::with_capacity(handles.len());
+    for handle in handles {
+        links.push(
+            handle
+                .join()
+                .map_err(|_| anyhow!("preflight thread panicked"))?,
+        );
+    }
+    Ok(PreflightReport { local, links })
+}
+
+pub(crate) fn compare(local: &Hello, remote: &Hello, peer: usize) -> LinkState {
+    if remote.pid != peer {
+        return LinkState::Mismatch(format!("expected CP{}, peer says CP{}", peer, remote.pid));
+    }
+    if remote.version != local.version {
+        return LinkState::Mismatch(format!("syqure {} != {}", remote.version, local.version));
+    }
+    if remote.program_hash != local.program_hash {
+        return LinkState::Mismatch(format!(
+            "program {} != {}",
+            short_hash(&remote.program_hash),
+            short_hash(&local.program_hash)
+        ));
+    }
+    LinkState::Up
+}
+
+fn listen_hello(port: u16, local: &Hello, deadline: Instant) -> Result<Hello> {
+    let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, port))
+        .with_context(|| format!("failed to bind to port {}", port))?;
+    listener.set_nonblocking(true)?;
+    let mut stream = loop {
+        match listener.accept() {
+            Ok((stream, _)) => break stream,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                if Instant::now() >= deadline {
+                    return Err(anyhow!("no connection from peer"));
+                }
+                thread::sleep(Duration::from_millis(100));
+            }
+            Err(e) => return Err(e.into()),
+        }
+    };
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(remaining(deadline)))?;
+
+    let remote = recv_hello(&mut stream)?;
+    send_hello(&mut stream, local)?;
+    // Wait for the connector to close so TIME_WAIT lands on its side.
+    let _ = stream.read(&mut [0u8; 1]);
+    Ok(remote)
+}
+
+fn connect_hello(address: &str, local: &Hello, deadline: Instant) -> Result<Hello> {
+    let addr = address
+        .to_socket_addrs()
+        .with_context(|| format!("cannot resolve {}", address))?
+        .next()
+        .ok_or_else(|| anyhow!("cannot resolve {}", address))?;
+    let mut stream = loop {
+        match TcpStream::connect_timeout(&addr, Duration::from_secs(1)) {
+            Ok(stream) => break stream,
+            Err(e) if Instant::now() >= deadline => {
+                return Err(e).context("peer not reachable");
+            }
+            Err(_) => thread::sleep(Duration::from_millis(250)),
+        }
+    };
+    stream.set_read_timeout(Some(remaining(deadline)))?;
+
+    send_hello(&mut stream, local)?;
+    recv_hello(&mut stream)
+}
+
+fn send_hello(stream: &mut TcpStream, hello: &Hello) -> Result<()> {
+    let body = serde_json::to_vec(hello)?;
+    let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + body.len());
+    frame.extend_from_slice(&(body.len() as i64).to_le_bytes());
+    frame.extend_from_slice(&body);
+    write_frame(stream, &frame)?;
+    Ok(())
+}
+
+fn recv_hello(stream: &mut TcpStream) -> Result<Hello> {
+    let mut header = [0u8; FRAME_HEADER_LEN];
+    stream
+        .read_exact(&mut header)
+        .context("no hello from peer")?;
+    let len = frame_len(&header)?;
+    if len > MAX_HELLO_LEN {
+        return Err(anyhow!(
+            "peer sent a {} byte hello (not a syqure party?)",
+            len
+        ));
+    }
+    let mut body = vec![0u8; len];
+    stream.read_exact(&mut body).context("truncated hello")?;
+    serde_json::from_slice(&body).context("peer sent an invalid hello (not a syqure party?)")
+}
+
+fn remaining(deadline: Instant) -> Duration {
+    deadline
+        .saturating_duration_since(Instant::now())
+        .max(Duration::from_millis(100))
+}
@@ -0,0 +1,221 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::keys::create_unique_private_dir;
+
+/// File a party appends its named outputs to, one JSON `{"name", "value"}`
+/// object per line.
+pub const ENV_RESULTS_FILE: &str = "SYQURE_RESULTS_FILE";
+
+/// Prefix of an output line on stdout, for runs without a results file.
+pub const OUTPUT_MARKER: &str = "@@syqure-output ";
+
+/// Module programs import to report outputs; syqure writes it next to the source.
+pub const HELPER_MODULE: &str = "syqure_results";
+
+pub(crate) const HELPER_SOURCE: &str = r##"# Generated by syqure; rewritten on every run, do not edit.
+# Reports named outputs to the launcher: appended to $SYQURE_RESULTS_FILE when
+# it is set, otherwise printed as marked lines on stdout. Each output is one
+# JSON object, so values survive whatever characters they contain.
+import os
+
+RESULTS_FILE_ENV = "SYQURE_RESULTS_FILE"
+OUTPUT_MARKER = "@@syqure-output "
+HEX_DIGITS = "0123456789abcdef"
+
+
+def _json_str(text: str) -> str:
+    out = '"'
+    for c in text:
+        if c == '"' or c == "\\":
+            out += "\\" + c
+        elif ord(c) < 0x20:
+            out += "\\u00" + HEX_DIGITS[ord(c) >> 4] + HEX_DIGITS[ord(c) & 15]
+        else:
+            out += c
+    return out + '"'
+
+
+def _json(value) -> str:
+    if isinstance(value, bool):
+        return "true" if value else "false"
+    elif isinstance(value, int):
+        return str(value)
+    elif isinstance(value, float):
+        # JSON has no NaN or infinity; keep them readable as text.
+        if value != value or value == float("inf") or value == -float("inf"):
+            return _json_str(str(value))
+        return str(value)
+    elif isinstance(value, str):
+        return _json_str(value)
+    elif hasattr(value, "tolist"):
+        return _json(value.tolist())
+    elif hasattr(value, "__iter__"):
+        return "[" + ", ".join(_json(v) for v in value) + "]"
+    else:
+        return _json_str(str(value))
+
+
+def emit(name: str, value):
+    """Record `value` (a number, bool, string, or a list or array of them) as output `name`."""
+    line = '{"name": ' + _json_str(name) + ', "value": ' + _json(value) + "}"
+    path = os.getenv(RESULTS_FILE_ENV, default="")
+    if path == "":
+        print(f"{OUTPUT_MARKER}{line}")
+    else:
+        with open(path, "a") as f:
+            f.write(line + "\n")
+"##;
+
+/// Named outputs of one party.
+pub type Outputs = BTreeMap<String, Value>;
+
+/// Outputs gathered from a party's results file and its stdout markers. A name
+/// emitted twice keeps its last value.
+pub fn collect_outputs(results_file: Option<&Path>, stdout: &str) -> Result<Outputs> {
+    let mut outputs = Outputs::new();
+    for line in stdout.lines() {
+        if let Some(line) = line.strip_prefix(OUTPUT_MARKER) {
+            insert_line(&mut outputs, line);
+        }
+    }
+    if let Some(path) = results_file {
+        match std::fs::read_to_string(path) {
+            Ok(text) => text
+                .lines()
+                .for_each(|line| insert_line(&mut outputs, line)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+        }
+    }
+    Ok(outputs)
+}
+
+#[derive(Deserialize)]
+struct OutputLine {
+    name: String,
+    value: Value,
+}
+
+/// Lines that are not an output object (e.g. one cut short by a crash) are skipped.
+fn insert_line(outputs: &mut Outputs, line: &str) {
+    if let Ok(output) = serde_json::from_str::<OutputLine>(line) {
+        outputs.insert(output.name, output.value);
+    }
+}
+
+/// An output that parties reported with different values.
+#[derive(Debug, Clone, Serialize)]
+pub struct OutputMismatch {
+    pub name: String,
+    /// Value per party that reported the output.
+    pub values: BTreeMap<usize, Value>,
+}
+
+/// Outputs that more than one party reported but with different values.
+/// Revealed values are the same on every party that received them.
+pub fn mismatches(outputs: &BTreeMap<usize, Outputs>) -> Vec<OutputMismatch> {
+    let mut by_name: BTreeMap<&str, BTreeMap<usize, Value>> = BTreeMap::new();
+    for (&pid, party) in outputs {
+        for (name, value) in party {
+            by_name.entry(name).or_default().insert(pid, value.clone());
+        }
+    }
+    by_name
+        .into_iter()
+        .filter(|(_, values)| {
+            let mut iter = values.values();
+            let first = iter.next();
+            iter.any(|v| Some(v) != first)
+        })
+        .map(|(name, values)| OutputMismatch {
+            name: name.to_string(),
+            values,
+        })
+        .collect()
+}
+
+/// Per-run directory of results files, removed when dropped.
+#[derive(Debug)]
+pub struct ResultsDir {
+    pub dir: PathBuf,
+}
+
+impl ResultsDir {
+    /// Create a fresh owner-only directory in the temp dir; parties' outputs can
+    /// be secret, and a predictable path could be prepared by another user.
+    pub fn create() -> Result<Self> {
+        let dir = create_unique_private_dir(&std::env::temp_dir(), "syqure-results-")
+            .context("failed to create a results directory")?;
+        Ok(Self { dir })
+    }
+
+    /// Results file of party `pid`.
+    pub fn file(&self, pid: usize) -> PathBuf {
+        self.dir.join(format!("CP{}.jsonl", pid))
+    }
+}
+
+impl Drop for ResultsDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn outputs_keep_their_json_values() {
+        let stdout = concat!(
+            "CP1: sum = 5\n",
+            "@@syqure-output {\"name\": \"sum\", \"value\": 5}\n",
+            "@@syqure-output {\"name\": \"label\", \"value\": \"True\\tor False\"}\n",
+            "@@syqure-output {\"name\": \"cut\", \"val\n",
+        );
+        let outputs = collect_outputs(None, stdout).unwrap();
+        assert_eq!(outputs.len(), 2);
+        assert_eq!(outputs["sum"], json!(5));
+        assert_eq!(outputs["label"], json!("True\tor False"));
+    }
+
+    #[test]
+    fn results_files_are_read_after_stdout() {
+        let results = ResultsDir::create().unwrap();
+        let file = results.file(1);
+        std::fs::write(
+            &file,
+            "{\"name\": \"flags\", \"value\": [true, false]}\n{\"name\": \"sum\", \"value\": 7}\n",
+        )
+        .unwrap();
+        let stdout = "@@syqure-output {\"name\": \"sum\", \"value\": 5}\n";
+        let outputs = collect_outputs(Some(&file), stdout).unwrap();
+        assert_eq!(outputs["flags"], json!([true, false]));
+        assert_eq!(outputs["sum"], json!(7));
+        assert!(collect_outputs(Some(&results.file(2)), "")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn results_dirs_are_private_and_unique() {
+        let first = ResultsDir::create().unwrap();
+        let second = ResultsDir::create().unwrap();
+        assert_ne!(first.dir, second.dir);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&first.dir).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o700);
+        }
+        let dir = first.dir.clone();
+        drop(first);
+        assert!(!dir.exists());
+    }
+}
@@ -3,7 +3,9 @@ use std::path::{Path, PathBuf};
 use anyhow::{anyhow, Result};
 
 use crate::bundle::ensure_bundle;
+use crate::config::SequreRuntimeConfig;
 use crate::ffi::{sy_codon_build_exe, sy_codon_run, SyCompileOpts};
+use crate::helpers;
 
 /// Options that control how syqure invokes Codon/Sequre.
 #[derive(Debug, Clone)]
@@ -22,6 +24,8 @@ pub struct CompileOptions {
     pub linker_flags: String,
     /// Suppress compiler warnings.
     pub quiet: bool,
+    /// Party settings exported to Sequre when the program runs.
+    pub runtime: SequreRuntimeConfig,
 }
 
 impl Default for CompileOptions {
@@ -36,6 +40,7 @@ impl Default for CompileOptions {
             libs: Vec::new(),
             linker_flags: String::new(),
             quiet: true,
+            runtime: SequreRuntimeConfig::default(),
         }
     }
 }
@@ -68,6 +73,7 @@ impl Syqure {
         if !source.exists() {
             return Err(anyhow!("source file not found: {}", source.display()));
         }
+        helpers::write_helpers(source)?;
 
         // Ensure Codon finds its stdlib and plugins by exporting CODON_PATH when missing.
         // Point CODON_PATH directly at the bundled stdlib (what Codon expects), and bundle plugins.
@@ -87,9 +93,11 @@ impl Syqure {
         let plugin = resolve_plugin_path(&codon_root, &self.opts.plugin);
 
         if self.opts.run_after_build {
+            self.opts.runtime.validate()?;
+            self.opts.runtime.export();
             let result = sy_codon_run(
                 &self.make_opts(source, /*standalone=*/ false, plugin.clone()),
-                &self.opts.program_args,
+                &self.opts.runtime.program_args(&self.opts.program_args),
             );
             if result.status != 0 {
                 return Err(anyhow!("codon run failed: {}", result.error));
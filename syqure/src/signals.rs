@@ -0,0 +1,68 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+
+use anyhow::Result;
+use signal_hook::consts::{SIGINT, SIGTERM};
+
+/// Conventional exit status after an interrupt (128 + SIGINT).
+pub const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+/// Returned by the launcher when a run was torn down because of a signal.
+#[derive(Debug, thiserror::Error)]
+#[error("interrupted by signal {0}")]
+pub struct Interrupted(pub i32);
+
+struct SignalState {
+    pending: Arc<AtomicBool>,
+    last: Arc<AtomicUsize>,
+}
+
+static STATE: OnceLock<SignalState> = OnceLock::new();
+
+/// Catch SIGINT and SIGTERM so the launcher can stop parties and clean up. A
+/// second signal exits at once with [`INTERRUPTED_EXIT_CODE`]. Idempotent.
+pub fn install() -> Result<()> {
+    if STATE.get().is_some() {
+        return Ok(());
+    }
+    let state = SignalState {
+        pending: Arc::new(AtomicBool::new(false)),
+        last: Arc::new(AtomicUsize::new(0)),
+    };
+    for sig in [SIGINT, SIGTERM] {
+        // Registration order matters: the shutdown check sees the flag from the
+        // previous signal before this one sets it.
+        signal_hook::flag::register_conditional_shutdown(
+            sig,
+            INTERRUPTED_EXIT_CODE,
+            state.pending.clone(),
+        )?;
+        signal_hook::flag::register_usize(sig, state.last.clone(), sig as usize)?;
+        signal_hook::flag::register(sig, state.pending.clone())?;
+    }
+    let _ = STATE.set(state);
+    Ok(())
+}
+
+/// The signal received since [`install`], if any.
+pub fn received() -> Option<i32> {
+    let state = STATE.get()?;
+    if !state.pending.load(Ordering::SeqCst) {
+        return None;
+    }
+    Some(state.last.load(Ordering::SeqCst) as i32)
+}
+
+/// Remove the `sock.*` files Sequre leaves in a party's working directory when
+/// it does not get to exit cleanly.
+pub fn remove_sockets(dir: &Path) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        if entry.file_name().to_string_lossy().starts_with("sock.") {
+            let _ = std::fs::remove_file(entry.path());
+        }
+    }
+}
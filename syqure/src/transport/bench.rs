@@ -0,0 +1,182 @@
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+
+use super::file::{connect_with_retry, run_proxy, FileProxyOptions};
+use super::watch::DirWatcher;
+use super::{read_frame, write_frame};
+use crate::config::{channel_port, MIN_PARTIES};
+
+/// Settings for `syqure bench-transport`.
+#[derive(Debug, Clone)]
+pub struct BenchOptions {
+    /// Request/response rounds between CP0 and CP1 per mode.
+    pub rounds: usize,
+    /// Payload bytes per message.
+    pub message_size: usize,
+    /// Poll interval of the polling mode.
+    pub poll_interval: Duration,
+    /// First port used; each party takes a block of 100 above it.
+    pub port_base: u16,
+    /// Scratch message directory (a temp dir by default).
+    pub data_dir: PathBuf,
+}
+
+impl Default for BenchOptions {
+    fn default() -> Self {
+        Self {
+            rounds: 1000,
+            message_size: 64,
+            poll_interval: Duration::from_millis(10),
+            port_base: 23000,
+            data_dir: std::env::temp_dir().join(format!("syqure-bench-{}", std::process::id())),
+        }
+    }
+}
+
+/// Round-trip throughput of one way of picking up message files.
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchResult {
+    pub mode: String,
+    pub rounds: usize,
+    pub secs: f64,
+    pub rounds_per_sec: f64,
+    /// Process CPU time (user + system) spent during the run, where available.
+    pub cpu_secs: Option<f64>,
+}
+
+/// Relay a ping-pong between two parties through the file proxy, once polling
+/// and once with inotify when the platform has it.
+pub fn bench_file_transport(opts: &BenchOptions) -> Result<Vec<BenchResult>> {
+    // Polling runs on the ports at port_base, inotify on the block 1000 above.
+    if opts.port_base > u16::MAX - 1000 - 100 * MIN_PARTIES as u16 {
+        return Err(anyhow!("port base {} is too high", opts.port_base));
+    }
+    let mut results = vec![bench_mode(opts, false, opts.port_base)?];
+    std::fs::create_dir_all(&opts.data_dir)?;
+    if DirWatcher::new(&opts.data_dir, true, opts.poll_interval).is_notify() {
+        results.push(bench_mode(opts, true, opts.port_base + 1000)?);
+    }
+    let _ = std::fs::remove_dir_all(&opts.data_dir);
+    Ok(results)
+}
+
+fn bench_mode(opts: &BenchOptions, use_inotify: bool, port_base: u16) -> Result<BenchResult> {
+    let mode = if use_inotify { "inotify" } else { "poll" };
+    let data_dir = opts.data_dir.join(mode);
+    let _ = std::fs::remove_dir_all(&data_dir);
+    let parties = MIN_PARTIES;
+    let bases: Vec<u16> = (0..parties as u16).map(|p| port_base + 100 * p).collect();
+    let cpu_start = cpu_time();
+
+    let proxies: Vec<_> = (0..parties)
+        .map(|pid| {
+            let proxy = FileProxyOptions {
+                pid,
+                parties,
+                data_dir: data_dir.clone(),
+                base_port: bases[pid],
+                poll_interval: opts.poll_interval,
+                use_inotify,
+                connect_timeout: Duration::from_secs(10),
+                ..FileProxyOptions::default()
+            };
+            thread::spawn(move || run_proxy(&proxy))
+        })
+        .collect();
+
+    // Stand in for Sequre on every channel; only CP0 <-> CP1 carries traffic.
+    let mut endpoints = Vec::new();
+    for (pid, &base) in bases.iter().enumerate() {
+        for peer in (0..parties).filter(|&p| p != pid) {
+            let port = channel_port(base, parties, pid, peer)
+                .ok_or_else(|| anyhow!("port for channel {}<->{} overflows", pid, peer))?;
+            let (rounds, size) = (opts.rounds, opts.message_size);
+            endpoints.push(thread::spawn(move || -> Result<Option<Duration>> {
+                let mut stream = if pid < peer {
+                    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+                        .with_context(|| format!("failed to bind to port {}", port))?;
+                    listener.accept()?.0
+                } else {
+                    connect_with_retry(port, Duration::from_secs(10))?
+                };
+                stream.set_nodelay(true)?;
+                match (pid, peer) {
+                    (0, 1) => ping(&mut stream, rounds, size).map(Some),
+                    (1, 0) => pong(&mut stream, rounds).map(|_| None),
+                    _ => Ok(None),
+                }
+            }));
+        }
+    }
+
+    let mut elapsed = None;
+    for endpoint in endpoints {
+        let result = endpoint
+            .join()
+            .unwrap_or_else(|_| Err(anyhow!("benchmark thread panicked")))?;
+        elapsed = elapsed.or(result);
+    }
+    for proxy in proxies {
+        proxy
+            .join()
+            .unwrap_or_else(|_| Err(anyhow!("proxy thread panicked")))?;
+    }
+    let cpu_secs = cpu_start
+        .zip(cpu_time())
+        .map(|(start, end)| (end - start).as_secs_f64());
+    let _ = std::fs::remove_dir_all(&data_dir);
+
+    let secs = elapsed
+        .ok_or_else(|| anyhow!("benchmark produced no timing"))?
+        .as_secs_f64();
+    Ok(BenchResult {
+        mode: mode.to_string(),
+        rounds: opts.rounds,
+        secs,
+        rounds_per_sec: opts.rounds as f64 / secs,
+        cpu_secs,
+    })
+}
+
+fn ping(stream: &mut TcpStream, rounds: usize, size: usize) -> Result<Duration> {
+    let mut frame = (size as i64).to_le_bytes().to_vec();
+    frame.resize(frame.len() + size, 0xa5);
+    let start = Instant::now();
+    for _ in 0..rounds {
+        write_frame(stream, &frame)?;
+        read_frame(stream)?.ok_or_else(|| anyhow!("peer closed during benchmark"))?;
+    }
+    Ok(start.elapsed())
+}
+
+fn pong(stream: &mut TcpStream, rounds: usize) -> Result<()> {
+    for _ in 0..rounds {
+        let frame = read_frame(stream)?.ok_or_else(|| anyhow!("peer closed during benchmark"))?;
+        write_frame(stream, &frame)?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn cpu_time() -> Option<Duration> {
+    let mut usage = std::mem::MaybeUninit::<libc::rusage>::uninit();
+    // SAFETY: getrusage fills the struct on success.
+    let usage = unsafe {
+        if libc::getrusage(libc::RUSAGE_SELF, usage.as_mut_ptr()) != 0 {
+            return None;
+        }
+        usage.assume_init()
+    };
+    let tv = |t: libc::timeval| Duration::new(t.tv_sec as u64, t.tv_usec as u32 * 1000);
+    Some(tv(usage.ru_utime) + tv(usage.ru_stime))
+}
+
+#[cfg(not(unix))]
+fn cpu_time() -> Option<Duration> {
+    None
+}
@@ -0,0 +1,288 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::{frame_len, FRAME_HEADER_LEN};
+use crate::attest::hex;
+
+/// Leads a compressed frame: `COMPRESSED_MAGIC | frame length (u64 LE) | zstd data`.
+pub const COMPRESSED_MAGIC: &[u8; 4] = b"SQZ1";
+
+/// Leads a `.request` file that lists the chunk files holding the message.
+pub const MANIFEST_MAGIC: &[u8; 4] = b"SQM1";
+
+/// Frames from this size up are compressed.
+pub const DEFAULT_COMPRESS_THRESHOLD: usize = 64 * 1024;
+
+/// Messages larger than this are split into chunk files.
+pub const DEFAULT_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+/// Largest message the file transport accepts. Lengths read from compressed
+/// frame headers and chunk manifests are checked against it before anything is
+/// allocated for them.
+pub const MAX_MESSAGE_LEN: u64 = 4 << 30;
+
+/// Fast level: shares are mostly random field elements, so a higher level costs
+/// time without buying much.
+const ZSTD_LEVEL: i32 = 1;
+
+const COMPRESSED_HEADER_LEN: usize = COMPRESSED_MAGIC.len() + 8;
+
+/// Whether `data` is exactly one plaintext frame. Compressed frames, manifests
+/// and sealed envelopes never are, which is how the receiver tells them apart.
+pub fn is_frame(data: &[u8]) -> bool {
+    frame_len(data).is_ok_and(|len| data.len() == FRAME_HEADER_LEN + len)
+}
+
+/// Compress `frame` when it is at least `threshold` bytes and zstd makes it
+/// smaller; otherwise return it unchanged.
+pub fn compress(frame: &[u8], threshold: Option<usize>) -> Result<Vec<u8>> {
+    if threshold.is_none_or(|t| frame.len() < t) {
+        return Ok(frame.to_vec());
+    }
+    let packed = zstd::bulk::compress(frame, ZSTD_LEVEL).context("failed to compress frame")?;
+    if COMPRESSED_HEADER_LEN + packed.len() >= frame.len() {
+        return Ok(frame.to_vec());
+    }
+    let mut data = Vec::with_capacity(COMPRESSED_HEADER_LEN + packed.len());
+    data.extend_from_slice(COMPRESSED_MAGIC);
+    data.extend_from_slice(&(frame.len() as u64).to_le_bytes());
+    data.extend_from_slice(&packed);
+    Ok(data)
+}
+
+/// Undo [`compress`], passing plain frames through.
+pub fn decompress(data: Vec<u8>) -> Result<Vec<u8>> {
+    if is_frame(&data) || !data.starts_with(COMPRESSED_MAGIC) {
+        return Ok(data);
+    }
+    let len = data
+        .get(COMPRESSED_MAGIC.len()..COMPRESSED_HEADER_LEN)
+        .and_then(|b| b.try_into().ok())
+        .map(u64::from_le_bytes)
+        .ok_or_else(|| anyhow!("truncated compressed frame"))?;
+    if len > MAX_MESSAGE_LEN {
+        return Err(anyhow!(
+            "refusing a compressed frame of {} bytes (limit {})",
+            len,
+            MAX_MESSAGE_LEN
+        ));
+    }
+    let len = usize::try_from(len).context("compressed frame too large")?;
+    let frame = zstd::bulk::decompress(&data[COMPRESSED_HEADER_LEN..], len)
+        .context("failed to decompress frame")?;
+    if frame.len() != len || !is_frame(&frame) {
+        return Err(anyhow!(
+            "compressed frame does not decode to a {}-byte frame",
+            len
+        ));
+    }
+    Ok(frame)
+}
+
+/// One chunk file of a split message.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChunkInfo {
+    pub len: u64,
+    pub sha256: String,
+}
+
+/// Contents of the `.request` file of a message split into chunks. The chunks
+/// are published first, so a receiver that sees the manifest only has to wait
+/// for a sync tool that reorders files.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Length of the reassembled message.
+    pub len: u64,
+    pub sha256: String,
+    pub chunks: Vec<ChunkInfo>,
+}
+
+impl Manifest {
+    /// Split `data` into `chunk_size` pieces and describe them.
+    pub fn split(data: &[u8], chunk_size: usize) -> (Manifest, Vec<&[u8]>) {
+        let chunks: Vec<&[u8]> = data.chunks(chunk_size.max(1)).collect();
+        let manifest = Manifest {
+            len: data.len() as u64,
+            sha256: hex(&Sha256::digest(data)),
+            chunks: chunks
+                .iter()
+                .map(|chunk| ChunkInfo {
+                    len: chunk.len() as u64,
+                    sha256: hex(&Sha256::digest(chunk)),
+                })
+                .collect(),
+        };
+        (manifest, chunks)
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut data = MANIFEST_MAGIC.to_vec();
+        serde_json::to_writer(&mut data, self)?;
+        Ok(data)
+    }
+
+    /// Parse a `.request` file, or None when it is not a manifest.
+    pub fn decode(data: &[u8]) -> Option<Result<Manifest>> {
+        if is_frame(data) || !data.starts_with(MANIFEST_MAGIC) {
+            return None;
+        }
+        Some(
+            serde_json::from_slice(&data[MANIFEST_MAGIC.len()..])
+                .context("invalid chunk manifest")
+                .and_then(|manifest: Manifest| manifest.validate().map(|()| manifest)),
+        )
+    }
+
+    /// Refuse lengths that disagree with each other or exceed
+    /// [`MAX_MESSAGE_LEN`]; the manifest is not authenticated.
+    pub fn validate(&self) -> Result<()> {
+        if self.len > MAX_MESSAGE_LEN {
+            return Err(anyhow!(
+                "chunk manifest claims {} bytes (limit {})",
+                self.len,
+                MAX_MESSAGE_LEN
+            ));
+        }
+        let total = self
+            .chunks
+            .iter()
+            .try_fold(0u64, |total, chunk| total.checked_add(chunk.len));
+        if total != Some(self.len) {
+            return Err(anyhow!(
+                "chunk manifest lengths do not add up to {} bytes",
+                self.len
+            ));
+        }
+        Ok(())
+    }
+
+    /// Check a chunk that has fully arrived.
+    pub fn check_chunk(&self, index: usize, chunk: &[u8]) -> Result<()> {
+        let info = &self.chunks[index];
+        if hex(&Sha256::digest(chunk)) != info.sha256 {
+            return Err(anyhow!("chunk {} does not match its manifest hash", index));
+        }
+        Ok(())
+    }
+
+    /// Check the reassembled message.
+    pub fn check(&self, data: &[u8]) -> Result<()> {
+        if data.len() as u64 != self.len || hex(&Sha256::digest(data)) != self.sha256 {
+            return Err(anyhow!("reassembled message does not match its manifest"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(payload: &[u8]) -> Vec<u8> {
+        let mut frame = (payload.len() as i64).to_le_bytes().to_vec();
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn compress_round_trip() {
+        let plain = frame(&vec![7u8; 4096]);
+        let packed = compress(&plain, Some(1024)).unwrap();
+        assert!(packed.starts_with(COMPRESSED_MAGIC));
+        assert!(packed.len() < plain.len());
+        assert!(!is_frame(&packed));
+        assert_eq!(decompress(packed).unwrap(), plain);
+    }
+
+    #[test]
+    fn small_and_disabled_frames_pass_through() {
+        let plain = frame(&vec![7u8; 4096]);
+        assert_eq!(compress(&plain, None).unwrap(), plain);
+        assert_eq!(compress(&plain, Some(plain.len() + 1)).unwrap(), plain);
+        assert_eq!(decompress(plain.clone()).unwrap(), plain);
+    }
+
+    #[test]
+    fn decompress_refuses_oversize_lengths() {
+        let mut packed = compress(&frame(&vec![7u8; 4096]), Some(0)).unwrap();
+        packed[COMPRESSED_MAGIC.len()..COMPRESSED_HEADER_LEN]
+            .copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(decompress(packed.clone()).is_err());
+        packed[COMPRESSED_MAGIC.len()..COMPRESSED_HEADER_LEN]
+            .copy_from_slice(&(MAX_MESSAGE_LEN + 1).to_le_bytes());
+        assert!(decompress(packed).is_err());
+    }
+
+    #[test]
+    fn decompress_refuses_tampered_frames() {
+        let plain = frame(&vec![7u8; 4096]);
+        let packed = compress(&plain, Some(0)).unwrap();
+
+        let mut wrong_len = packed.clone();
+        wrong_len[COMPRESSED_MAGIC.len()..COMPRESSED_HEADER_LEN]
+            .copy_from_slice(&(plain.len() as u64 - 1).to_le_bytes());
+        assert!(decompress(wrong_len).is_err());
+
+        assert!(decompress(packed[..COMPRESSED_HEADER_LEN - 1].to_vec()).is_err());
+        assert!(decompress(packed[..packed.len() - 1].to_vec()).is_err());
+    }
+
+    #[test]
+    fn manifest_round_trip() {
+        let data: Vec<u8> = (0..=255u8).cycle().take(1000).collect();
+        let (manifest, chunks) = Manifest::split(&data, 300);
+        assert_eq!(chunks.len(), 4);
+        let decoded = Manifest::decode(&manifest.encode().unwrap())
+            .unwrap()
+            .unwrap();
+        for (index, chunk) in chunks.iter().enumerate() {
+            decoded.check_chunk(index, chunk).unwrap();
+        }
+        decoded.check(&chunks.concat()).unwrap();
+        assert!(Manifest::decode(&frame(b"SQM1")).is_none());
+    }
+
+    #[test]
+    fn manifest_refuses_tampered_data() {
+        let data = vec![1u8; 1000];
+        let (manifest, chunks) = Manifest::split(&data, 300);
+        let mut chunk = chunks[1].to_vec();
+        chunk[0] ^= 1;
+        assert!(manifest.check_chunk(1, &chunk).is_err());
+        assert!(manifest.check(&data[1..]).is_err());
+        let mut altered = data.clone();
+        altered[999] ^= 1;
+        assert!(manifest.check(&altered).is_err());
+    }
+
+    #[test]
+    fn manifest_refuses_inconsistent_lengths() {
+        let (mut manifest, _) = Manifest::split(&[1u8; 1000], 300);
+        manifest.len = u64::MAX;
+        assert!(Manifest::decode(&manifest.encode().unwrap())
+            .unwrap()
+            .is_err());
+
+        let (mut manifest, _) = Manifest::split(&[1u8; 1000], 300);
+        manifest.len = 999;
+        assert!(manifest.validate().is_err());
+
+        let (mut manifest, _) = Manifest::split(&[1u8; 1000], 300);
+        manifest.chunks[0].len = u64::MAX;
+        assert!(manifest.validate().is_err());
+
+        let oversize = Manifest {
+            len: MAX_MESSAGE_LEN + 2,
+            sha256: String::new(),
+            chunks: vec![
+                ChunkInfo {
+                    len: MAX_MESSAGE_LEN / 2 + 1,
+                    sha256: String::new(),
+                };
+                2
+            ],
+        };
+        assert!(oversize.validate().is_err());
+    }
+}
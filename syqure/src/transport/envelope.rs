@@ -0,0 +1,254 @@
+use std::fmt;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+use ring::hkdf::{Salt, HKDF_SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+
+use crate::attest::{hex, unhex};
+use crate::keys::create_private_file;
+
+/// Leads every sealed message file; bump the version on any format change.
+pub const MAGIC: &[u8; 4] = b"SQE1";
+
+/// Length of a message key in bytes.
+pub const KEY_LEN: usize = 32;
+
+/// HKDF salt separating envelope keys from any other use of the job key.
+const KDF_SALT: &[u8] = b"syqure file envelope v1";
+
+/// Why a message file was refused.
+#[derive(Debug, thiserror::Error)]
+pub enum IntegrityError {
+    #[error("message {seq} on {from}_to_{to} is not a sealed envelope")]
+    NotSealed { from: usize, to: usize, seq: u64 },
+    #[error(
+        "message {seq} on {from}_to_{to} failed authentication \
+         (tampered, replayed, reordered or sealed with another key)"
+    )]
+    Forged { from: usize, to: usize, seq: u64 },
+}
+
+/// Job-wide secret from which every channel key is derived. Each party of a job
+/// holds the same key file (`message_key` in the cluster config).
+#[derive(Clone)]
+pub struct MessageKey([u8; KEY_LEN]);
+
+impl fmt::Debug for MessageKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("MessageKey(..)")
+    }
+}
+
+impl MessageKey {
+    /// Read a key file holding 64 hex digits.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read message key {}", path.display()))?;
+        let key = unhex(text.trim())
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| {
+                anyhow!(
+                    "message key {} must be {} hex digits",
+                    path.display(),
+                    2 * KEY_LEN
+                )
+            })?;
+        Ok(Self(key))
+    }
+
+    /// Write a fresh random key to `path`, readable by the owner only.
+    pub fn generate(path: &Path) -> Result<Self> {
+        let mut key = [0u8; KEY_LEN];
+        SystemRandom::new()
+            .fill(&mut key)
+            .map_err(|_| anyhow!("system random source failed"))?;
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        writeln!(create_private_file(path)?, "{}", hex(&key))
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        Ok(Self(key))
+    }
+
+    /// Cipher for the `from` -> `to` channel of job `job_id`. Every directed
+    /// channel of every job gets its own key, so a message cannot be moved to
+    /// another channel or job either.
+    pub fn channel(&self, job_id: &str, from: usize, to: usize) -> ChannelCipher {
+        let info = format!("{}/{}_to_{}", job_id, from, to);
+        let info = [info.as_bytes()];
+        let mut key = [0u8; KEY_LEN];
+        Salt::new(HKDF_SHA256, KDF_SALT)
+            .extract(&self.0)
+            .expand(&info, &CHACHA20_POLY1305)
+            .and_then(|okm| okm.fill(&mut key))
+            .expect("HKDF output matches the AEAD key length");
+        let key = UnboundKey::new(&CHACHA20_POLY1305, &key).expect("valid ChaCha20 key length");
+        ChannelCipher {
+            key: LessSafeKey::new(key),
+            rng: SystemRandom::new(),
+            from,
+            to,
+        }
+    }
+}
+
+/// Seals and opens the messages of one directed channel:
+/// `MAGIC | nonce | ChaCha20-Poly1305(frame)`, with the channel and sequence
+/// number as associated data.
+pub struct ChannelCipher {
+    key: LessSafeKey,
+    rng: SystemRandom,
+    from: usize,
+    to: usize,
+}
+
+impl ChannelCipher {
+    fn aad(&self, seq: u64) -> [u8; 28] {
+        let mut aad = [0u8; 28];
+        aad[..4].copy_from_slice(MAGIC);
+        aad[4..12].copy_from_slice(&(self.from as u64).to_le_bytes());
+        aad[12..20].copy_from_slice(&(self.to as u64).to_le_bytes());
+        aad[20..].copy_from_slice(&seq.to_le_bytes());
+        aad
+    }
+
+    /// Encrypt message `seq` of this channel.
+    pub fn seal(&self, seq: u64, frame: &[u8]) -> Result<Vec<u8>> {
+        let mut nonce = [0u8; NONCE_LEN];
+        self.rng
+            .fill(&mut nonce)
+            .map_err(|_| anyhow!("system random source failed"))?;
+        let mut sealed =
+            Vec::with_capacity(MAGIC.len() + NONCE_LEN + frame.len() + CHACHA20_POLY1305.tag_len());
+        sealed.extend_from_slice(MAGIC);
+        sealed.extend_from_slice(&nonce);
+        let mut body = frame.to_vec();
+        self.key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(self.aad(seq)),
+                &mut body,
+            )
+            .map_err(|_| anyhow!("failed to seal message {}", seq))?;
+        sealed.extend_from_slice(&body);
+        Ok(sealed)
+    }
+
+    /// Decrypt message `seq`, refusing anything not sealed for exactly this
+    /// channel and position.
+    pub fn open(&self, seq: u64, sealed: &[u8]) -> Result<Vec<u8>, IntegrityError> {
+        let (from, to) = (self.from, self.to);
+        let header = MAGIC.len() + NONCE_LEN;
+        if sealed.len() < header + CHACHA20_POLY1305.tag_len() || !is_sealed(sealed) {
+            return Err(IntegrityError::NotSealed { from, to, seq });
+        }
+        let nonce = Nonce::try_assume_unique_for_key(&sealed[MAGIC.len()..header])
+            .map_err(|_| IntegrityError::NotSealed { from, to, seq })?;
+        let mut body = sealed[header..].to_vec();
+        let len = self
+            .key
+            .open_in_place(nonce, Aad::from(self.aad(seq)), &mut body)
+            .map_err(|_| IntegrityError::Forged { from, to, seq })?
+            .len();
+        body.truncate(len);
+        Ok(body)
+    }
+}
+
+/// Whether a message file looks like a sealed envelope.
+pub fn is_sealed(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(byte: u8) -> MessageKey {
+        MessageKey([byte; KEY_LEN])
+    }
+
+    #[test]
+    fn round_trip() {
+        let cipher = key(1).channel("job", 0, 1);
+        let sealed = cipher.seal(7, b"frame").unwrap();
+        assert!(is_sealed(&sealed));
+        assert_eq!(cipher.open(7, &sealed).unwrap(), b"frame");
+        assert_eq!(cipher.open(0, &cipher.seal(0, b"").unwrap()).unwrap(), b"");
+    }
+
+    #[test]
+    fn refuses_tampered_messages() {
+        let cipher = key(1).channel("job", 0, 1);
+        let sealed = cipher.seal(0, b"frame").unwrap();
+        for index in [MAGIC.len(), MAGIC.len() + NONCE_LEN, sealed.len() - 1] {
+            let mut tampered = sealed.clone();
+            tampered[index] ^= 1;
+            assert!(matches!(
+                cipher.open(0, &tampered),
+                Err(IntegrityError::Forged { seq: 0, .. })
+            ));
+        }
+        assert!(matches!(
+            cipher.open(0, &sealed[..sealed.len() - 1]),
+            Err(IntegrityError::Forged { .. })
+        ));
+    }
+
+    #[test]
+    fn refuses_wrong_position_channel_job_or_key() {
+        let cipher = key(1).channel("job", 0, 1);
+        let sealed = cipher.seal(3, b"frame").unwrap();
+        assert!(matches!(
+            cipher.open(4, &sealed),
+            Err(IntegrityError::Forged { seq: 4, .. })
+        ));
+        for other in [
+            key(1).channel("job", 1, 0),
+            key(1).channel("job", 0, 2),
+            key(1).channel("other-job", 0, 1),
+            key(2).channel("job", 0, 1),
+        ] {
+            assert!(matches!(
+                other.open(3, &sealed),
+                Err(IntegrityError::Forged { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn refuses_unsealed_messages() {
+        let cipher = key(1).channel("job", 0, 1);
+        let mut frame = 5i64.to_le_bytes().to_vec();
+        frame.extend_from_slice(b"frame");
+        assert!(!is_sealed(&frame));
+        assert!(matches!(
+            cipher.open(0, &frame),
+            Err(IntegrityError::NotSealed { .. })
+        ));
+        assert!(matches!(
+            cipher.open(0, MAGIC),
+            Err(IntegrityError::NotSealed { .. })
+        ));
+    }
+
+    #[test]
+    fn loads_generated_keys() {
+        let path =
+            std::env::temp_dir().join(format!("syqure-envelope-test-{}.key", std::process::id()));
+        let generated = MessageKey::generate(&path).unwrap();
+        assert_eq!(generated.0, MessageKey::load(&path).unwrap().0);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        std::fs::write(&path, "not hex\n").unwrap();
+        assert!(MessageKey::load(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
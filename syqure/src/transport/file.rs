@@ -0,0 +1,671 @@
+use std::net::{Ipv4Addr, Shutdown, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+
+use super::codec::{self, is_frame, Manifest, DEFAULT_CHUNK_SIZE, DEFAULT_COMPRESS_THRESHOLD};
+use super::envelope::{is_sealed, ChannelCipher, MessageKey};
+use super::netem::{LinkShaper, NetProfile};
+use super::state::{
+    ack_path, check_job_id, delivered_leftovers, job_dir, retire, scan_next_seq, state_path, Ack,
+    ChannelState, Retention,
+};
+use super::transcript::{self, ChannelRecorder, TranscriptMeta};
+use super::watch::DirWatcher;
+use super::{read_frame, write_frame, ChannelStats};
+use crate::config::{channel_port, DEFAULT_PORT_BASE, MIN_PARTIES};
+use crate::preflight::{compare, Hello, LinkState, LinkStatus, PreflightReport};
+
+/// Settings for one party's TCP-to-file proxy (the sbproxy protocol in files.md).
+#[derive(Debug, Clone)]
+pub struct FileProxyOptions {
+    /// Party this proxy serves.
+    pub pid: usize,
+    /// Number of parties in the job.
+    pub parties: usize,
+    /// Shared message directory holding the `{from}_to_{to}` channel folders.
+    pub data_dir: PathBuf,
+    /// Keeps this job's channels in their own folder of `data_dir`, so several
+    /// jobs can share one synced directory.
+    pub job_id: Option<String>,
+    /// What to do with message files once they are delivered.
+    pub retention: Retention,
+    /// Port base of this party's Sequre process.
+    pub base_port: u16,
+    /// How often to look for the next inbound `.request` file when polling.
+    pub poll_interval: Duration,
+    /// Wake on new message files through inotify (Linux) instead of polling.
+    pub use_inotify: bool,
+    /// How long to keep retrying while Sequre is still compiling.
+    pub connect_timeout: Duration,
+    /// When set, swap hellos with every peer through the message directory and
+    /// refuse to relay unless all of them attest to the same program.
+    pub program_hash: Option<String>,
+    /// Record every frame this party sends and receives under `CP{pid}/` here.
+    pub transcript_dir: Option<PathBuf>,
+    /// Emulated latency, bandwidth and loss on this party's outbound channels.
+    pub net_profile: Option<NetProfile>,
+    /// Job key file; when set, message files are sealed per channel and
+    /// anything that fails to open is refused.
+    pub message_key: Option<PathBuf>,
+    /// Compress frames of at least this many bytes; None disables compression.
+    pub compress_threshold: Option<usize>,
+    /// Split messages larger than this into chunk files; None disables chunking.
+    pub chunk_size: Option<usize>,
+}
+
+impl Default for FileProxyOptions {
+    fn default() -> Self {
+        Self {
+            pid: 0,
+            parties: 3,
+            data_dir: PathBuf::from("sandbox/mpc_messages"),
+            job_id: None,
+            retention: Retention::Delete,
+            base_port: DEFAULT_PORT_BASE,
+            poll_interval: Duration::from_millis(10),
+            use_inotify: true,
+            connect_timeout: Duration::from_secs(120),
+            program_hash: None,
+            transcript_dir: None,
+            net_profile: None,
+            message_key: None,
+            compress_threshold: Some(DEFAULT_COMPRESS_THRESHOLD),
+            chunk_size: Some(DEFAULT_CHUNK_SIZE),
+        }
+    }
+}
+
+impl FileProxyOptions {
+    /// Folder holding this job's channels (`data_dir/{job_id}` or `data_dir`).
+    pub fn job_dir(&self) -> PathBuf {
+        job_dir(&self.data_dir, self.job_id.as_deref())
+    }
+}
+
+/// Directory holding messages sent from `from` to `to`.
+pub fn channel_dir(data_dir: &Path, from: usize, to: usize) -> PathBuf {
+    data_dir.join(format!("{}_to_{}", from, to))
+}
+
+/// Path of message `seq` on the `from` -> `to` channel.
+pub fn message_path(data_dir: &Path, from: usize, to: usize, seq: u64) -> PathBuf {
+    channel_dir(data_dir, from, to).join(format!("{:08}.request", seq))
+}
+
+/// Path of chunk `index` of message `seq`, listed in that message's manifest.
+pub fn chunk_path(data_dir: &Path, from: usize, to: usize, seq: u64, index: usize) -> PathBuf {
+    channel_dir(data_dir, from, to).join(format!("{:08}.chunk{:04}", seq, index))
+}
+
+/// Write a frame atomically: temp file first, then rename into place.
+pub fn write_message(path: &Path, frame: &[u8]) -> Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    std::fs::write(&tmp, frame).with_context(|| format!("failed to write {}", tmp.display()))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("failed to publish {}", path.display()))?;
+    Ok(())
+}
+
+/// Where party `pid` publishes its hello for the file-transport attestation check.
+pub fn hello_path(data_dir: &Path, pid: usize) -> PathBuf {
+    data_dir.join("hello").join(format!("CP{}.json", pid))
+}
+
+/// Publish this party's hello in the message directory and wait for every peer's.
+/// The file-transport counterpart of [`crate::preflight::preflight`].
+pub fn exchange_hellos(opts: &FileProxyOptions, program_hash: &str) -> Result<PreflightReport> {
+    let local = Hello::new(opts.pid, program_hash);
+    let own = hello_path(&opts.job_dir(), opts.pid);
+    if let Some(dir) = own.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    write_message(&own, &serde_json::to_vec(&local)?)?;
+
+    let deadline = Instant::now() + opts.connect_timeout;
+    let mut links = Vec::new();
+    for peer in (0..opts.parties).filter(|&p| p != opts.pid) {
+        let path = hello_path(&opts.job_dir(), peer);
+        let state = loop {
+            match std::fs::read(&path) {
+                Ok(bytes) => match serde_json::from_slice::<Hello>(&bytes) {
+                    Ok(remote) => break compare(&local, &remote, peer),
+                    Err(e) => break LinkState::Mismatch(format!("invalid hello: {}", e)),
+                },
+                Err(_) if Instant::now() < deadline => thread::sleep(opts.poll_interval),
+                Err(_) => break LinkState::Down("no hello from peer".to_string()),
+            }
+        };
+        links.push(LinkStatus {
+            peer,
+            address: path.display().to_string(),
+            state,
+        });
+    }
+    Ok(PreflightReport { local, links })
+}
+
+/// Proxy every channel of one party between Sequre's TCP sockets and the message
+/// directory. Returns the traffic on this party's outbound channels once Sequre
+/// has closed all of its connections.
+pub fn run_proxy(opts: &FileProxyOptions) -> Result<Vec<ChannelStats>> {
+    if opts.parties < MIN_PARTIES {
+        return Err(anyhow!(
+            "Sequre requires at least {} parties, got {}",
+            MIN_PARTIES,
+            opts.parties
+        ));
+    }
+    if opts.pid >= opts.parties {
+        return Err(anyhow!(
+            "pid {} is out of range for {} parties",
+            opts.pid,
+            opts.parties
+        ));
+    }
+
+    if let Some(ref id) = opts.job_id {
+        check_job_id(id)?;
+    }
+    if opts.message_key.is_some() && opts.job_id.is_none() {
+        return Err(anyhow!(
+            "a message key needs a job id; without one every run sealed with the key \
+             shares its channel keys, so an earlier run's messages would open in this one"
+        ));
+    }
+    if let Some(ref hash) = opts.program_hash {
+        let report = exchange_hellos(opts, hash)?;
+        report.print_table();
+        report.ensure_ok()?;
+    }
+
+    let message_key = opts
+        .message_key
+        .as_deref()
+        .map(MessageKey::load)
+        .transpose()?;
+
+    let started = Instant::now();
+    let transcript = match opts.transcript_dir {
+        Some(ref dir) => {
+            let meta = TranscriptMeta {
+                pid: opts.pid,
+                parties: opts.parties,
+            };
+            Some((transcript::create(dir, &meta)?, started))
+        }
+        None => None,
+    };
+
+    let job = opts.job_dir();
+    let mut handles = Vec::new();
+    for peer in (0..opts.parties).filter(|&p| p != opts.pid) {
+        std::fs::create_dir_all(channel_dir(&job, opts.pid, peer))?;
+        std::fs::create_dir_all(channel_dir(&job, peer, opts.pid))?;
+        let recorders = match transcript {
+            Some((ref dir, started)) => Some((
+                ChannelRecorder::create(dir, opts.pid, peer, started)?,
+                ChannelRecorder::create(dir, peer, opts.pid, started)?,
+            )),
+            None => None,
+        };
+        let shaper = opts
+            .net_profile
+            .as_ref()
+            .map(|profile| profile.shaper(opts.pid, peer, started));
+        let ciphers = message_key.as_ref().map(|key| {
+            let job_id = opts.job_id.as_deref().unwrap_or_default();
+            (
+                key.channel(job_id, opts.pid, peer),
+                key.channel(job_id, peer, opts.pid),
+            )
+        });
+        let opts = opts.clone();
+        handles.push(thread::spawn(move || {
+            run_channel(&opts, peer, recorders, shaper, ciphers)
+        }));
+    }
+
+    let mut first_err = None;
+    let mut stats = Vec::new();
+    for handle in handles {
+        let result = handle
+            .join()
+            .unwrap_or_else(|_| Err(anyhow!("proxy channel thread panicked")));
+        match result {
+            Ok(channel) => stats.push(channel),
+            Err(e) => {
+                first_err.get_or_insert(e);
+            }
+        }
+    }
+    match first_err {
+        Some(e) => Err(e),
+        None => Ok(stats),
+    }
+}
+
+fn run_channel(
+    opts: &FileProxyOptions,
+    peer: usize,
+    recorders: Option<(ChannelRecorder, ChannelRecorder)>,
+    shaper: Option<LinkShaper>,
+    ciphers: Option<(ChannelCipher, ChannelCipher)>,
+) -> Result<ChannelStats> {
+    let port = channel_port(opts.base_port, opts.parties, opts.pid, peer)
+        .ok_or_else(|| anyhow!("port for channel {}<->{} overflows", opts.pid, peer))?;
+
+    // Sequre's lower pid listens; the proxy takes the opposite role on its side.
+    let stream = if opts.pid < peer {
+        connect_with_retry(port, opts.connect_timeout)?
+    } else {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+            .with_context(|| format!("failed to bind to port {}", port))?;
+        let (stream, _) = listener.accept()?;
+        stream
+    };
+    stream.set_nodelay(true)?;
+    eprintln!(
+        "proxy CP{}: channel {}<->{} on port {}",
+        opts.pid, opts.pid, peer, port
+    );
+
+    let closed = Arc::new(AtomicBool::new(false));
+    let stats = Arc::new(Mutex::new(ChannelStats::new(opts.pid, peer)));
+    let (out_recorder, in_recorder) = recorders.unzip();
+    let (out_cipher, in_cipher) = ciphers.unzip();
+    let outbound = {
+        let mut reader = stream.try_clone()?;
+        let (opts, closed, stats) = (opts.clone(), closed.clone(), stats.clone());
+        thread::spawn(move || {
+            let result = forward_outbound(
+                &mut reader,
+                &opts,
+                peer,
+                &stats,
+                out_recorder,
+                shaper,
+                out_cipher,
+            );
+            closed.store(true, Ordering::SeqCst);
+            result
+        })
+    };
+    let control = stream.try_clone()?;
+    let inbound = forward_inbound(stream, opts, peer, &closed, &stats, in_recorder, in_cipher);
+    closed.store(true, Ordering::SeqCst);
+    if inbound.is_err() {
+        // Hang up so Sequre fails instead of waiting for a message that will
+        // never be delivered.
+        let _ = control.shutdown(Shutdown::Both);
+    }
+
+    outbound
+        .join()
+        .unwrap_or_else(|_| Err(anyhow!("outbound thread panicked")))?;
+    inbound?;
+    let stats = stats.lock().map_err(|_| anyhow!("stats lock poisoned"))?;
+    Ok(stats.clone())
+}
+
+pub(crate) fn connect_with_retry(port: u16, timeout: Duration) -> Result<TcpStream> {
+    let start = Instant::now();
+    loop {
+        match TcpStream::connect((Ipv4Addr::LOCALHOST, port)) {
+            Ok(stream) => return Ok(stream),
+            // Connection refused is expected while Sequre is still compiling.
+            Err(_) if start.elapsed() < timeout => thread::sleep(Duration::from_millis(250)),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("failed to connect to Sequre on port {}", port))
+            }
+        }
+    }
+}
+
+/// Sequre -> files: each frame read from TCP becomes the next `.request` file.
+fn forward_outbound(
+    reader: &mut TcpStream,
+    opts: &FileProxyOptions,
+    peer: usize,
+    stats: &Mutex<ChannelStats>,
+    mut recorder: Option<ChannelRecorder>,
+    mut shaper: Option<LinkShaper>,
+    cipher: Option<ChannelCipher>,
+) -> Result<()> {
+    let job = opts.job_dir();
+    // Resume after the last number this channel used, even if the state file
+    // lags behind what was already published.
+    let state = state_path(&job, opts.pid, opts.pid, peer);
+    let first_seq = ChannelState::load(&state)?.next_seq.max(scan_next_seq(
+        &channel_dir(&job, opts.pid, peer),
+        &["request", "chunk", "ack"],
+    )?);
+    let publish = |seq: u64, frame: &[u8]| {
+        let mut data = codec::compress(frame, opts.compress_threshold)?;
+        if let Some(ref cipher) = cipher {
+            data = cipher.seal(seq, &data)?;
+        }
+        let path = message_path(&job, opts.pid, peer, seq);
+        match opts.chunk_size {
+            Some(size) if data.len() > size => {
+                let (manifest, chunks) = Manifest::split(&data, size);
+                for (index, chunk) in chunks.into_iter().enumerate() {
+                    write_message(&chunk_path(&job, opts.pid, peer, seq, index), chunk)?;
+                }
+                // The manifest goes last so the message only appears once complete.
+                write_message(&path, &manifest.encode()?)?;
+            }
+            _ => write_message(&path, &data)?,
+        }
+        ChannelState { next_seq: seq + 1 }.save(&state)
+    };
+    thread::scope(|scope| {
+        // Shaped frames are published by their own thread, so reading from
+        // Sequre never stalls on emulated latency.
+        let (tx, rx) = mpsc::channel::<(u64, Instant, Vec<u8>)>();
+        let publisher = shaper.is_some().then(|| {
+            scope.spawn(move || -> Result<()> {
+                for (seq, at, frame) in rx {
+                    thread::sleep(at.saturating_duration_since(Instant::now()));
+                    publish(seq, &frame)?;
+                }
+                Ok(())
+            })
+        });
+
+        let mut seq = first_seq;
+        while let Some(frame) = read_frame(reader)? {
+            if let Some(ref mut recorder) = recorder {
+                recorder.record(&frame)?;
+            }
+            if let Ok(mut stats) = stats.lock() {
+                stats.record_sent(frame.len());
+            }
+            match shaper {
+                Some(ref mut shaper) => {
+                    let at = shaper.schedule(frame.len());
+                    if tx.send((seq, at, frame)).is_err() {
+                        break;
+                    }
+                }
+                None => publish(seq, &frame)?,
+            }
+            seq += 1;
+        }
+        drop(tx);
+        match publisher {
+            Some(handle) => handle
+                .join()
+                .unwrap_or_else(|_| Err(anyhow!("publisher thread panicked"))),
+            None => Ok(()),
+        }
+    })
+}
+
+/// Files -> Sequre: wait for the next `.request` file, deliver it, acknowledge
+/// it and apply the retention policy.
+fn forward_inbound(
+    mut writer: TcpStream,
+    opts: &FileProxyOptions,
+    peer: usize,
+    closed: &AtomicBool,
+    stats: &Mutex<ChannelStats>,
+    mut recorder: Option<ChannelRecorder>,
+    cipher: Option<ChannelCipher>,
+) -> Result<()> {
+    let job = opts.job_dir();
+    let dir = channel_dir(&job, peer, opts.pid);
+    // Watch before the first look, so no file can land unnoticed in between.
+    let watcher = DirWatcher::new(&dir, opts.use_inotify, opts.poll_interval);
+    // Acknowledged messages were delivered before a restart; never deliver twice.
+    let state = state_path(&job, opts.pid, peer, opts.pid);
+    let mut seq = ChannelState::load(&state)?
+        .next_seq
+        .max(scan_next_seq(&dir, &["ack"])?);
+    retire(
+        &job,
+        peer,
+        opts.pid,
+        &delivered_leftovers(&dir, seq)?,
+        opts.retention,
+    )?;
+    loop {
+        let path = message_path(&job, peer, opts.pid, seq);
+        if let Some((data, chunks)) = read_message(&job, peer, opts.pid, seq)? {
+            let ack = Ack::new(seq, &data);
+            let data = match cipher {
+                Some(ref cipher) => cipher
+                    .open(seq, &data)
+                    .with_context(|| format!("refusing {}", path.display()))?,
+                None if is_sealed(&data) && !is_frame(&data) => {
+                    return Err(anyhow!(
+                        "{} is sealed but this proxy has no message key",
+                        path.display()
+                    ))
+                }
+                None => data,
+            };
+            let frame =
+                codec::decompress(data).with_context(|| format!("refusing {}", path.display()))?;
+            if let Ok(mut stats) = stats.lock() {
+                stats.record_reply();
+            }
+            if let Some(ref mut recorder) = recorder {
+                recorder.record(&frame)?;
+            }
+            if let Err(e) = write_frame(&mut writer, &frame) {
+                // Sequre hung up first; nothing is left to deliver to.
+                if closed.load(Ordering::SeqCst) {
+                    return Ok(());
+                }
+                return Err(e.into());
+            }
+            ack.write(&ack_path(&job, peer, opts.pid, seq))?;
+            ChannelState { next_seq: seq + 1 }.save(&state)?;
+            let mut files = chunks;
+            files.push(path);
+            retire(&job, peer, opts.pid, &files, opts.retention)?;
+            seq += 1;
+            continue;
+        }
+        if closed.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        watcher.wait();
+    }
+}
+
+/// Read message `seq` of a channel, reassembling it from chunk files if it was
+/// split. Returns None until the message and all of its chunks have arrived,
+/// and the chunk paths to delete once it has been delivered.
+fn read_message(
+    data_dir: &Path,
+    from: usize,
+    to: usize,
+    seq: u64,
+) -> Result<Option<(Vec<u8>, Vec<PathBuf>)>> {
+    let path = message_path(data_dir, from, to, seq);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let data =
+        std::fs::read(&path).with_context(|| format!("failed to read {}", path.display()))?;
+    let manifest = match Manifest::decode(&data) {
+        Some(manifest) => manifest.with_context(|| format!("refusing {}", path.display()))?,
+        None => return Ok(Some((data, Vec::new()))),
+    };
+
+    // Grow with the chunks that actually arrived, not with the claimed length.
+    let mut assembled = Vec::new();
+    let mut chunks = Vec::with_capacity(manifest.chunks.len());
+    for (index, info) in manifest.chunks.iter().enumerate() {
+        let chunk_path = chunk_path(data_dir, from, to, seq, index);
+        // A sync tool may deliver the manifest before its chunks.
+        match std::fs::metadata(&chunk_path) {
+            Ok(meta) if meta.len() == info.len => {}
+            _ => return Ok(None),
+        }
+        let chunk = std::fs::read(&chunk_path)
+            .with_context(|| format!("failed to read {}", chunk_path.display()))?;
+        manifest
+            .check_chunk(index, &chunk)
+            .with_context(|| format!("refusing {}", chunk_path.display()))?;
+        assembled.extend_from_slice(&chunk);
+        chunks.push(chunk_path);
+    }
+    manifest
+        .check(&assembled)
+        .with_context(|| format!("refusing {}", path.display()))?;
+    Ok(Some((assembled, chunks)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::state::archive_dir;
+
+    #[test]
+    fn read_message_refuses_forged_manifests() {
+        let dir = std::env::temp_dir().join(format!("syqure-file-test-{}", std::process::id()));
+        std::fs::create_dir_all(channel_dir(&dir, 0, 1)).unwrap();
+        let path = message_path(&dir, 0, 1, 0);
+
+        let mut forged = codec::MANIFEST_MAGIC.to_vec();
+        forged.extend_from_slice(br#"{"len":18446744073709551615,"sha256":"","chunks":[]}"#);
+        write_message(&path, &forged).unwrap();
+        let err = read_message(&dir, 0, 1, 0).unwrap_err();
+        assert!(format!("{:#}", err).contains("refusing"));
+
+        let data = vec![3u8; 100];
+        let (manifest, chunks) = Manifest::split(&data, 40);
+        for (index, chunk) in chunks.iter().enumerate() {
+            write_message(&chunk_path(&dir, 0, 1, 1, index), chunk).unwrap();
+        }
+        write_message(&message_path(&dir, 0, 1, 1), &manifest.encode().unwrap()).unwrap();
+        let (message, paths) = read_message(&dir, 0, 1, 1).unwrap().unwrap();
+        assert_eq!(message, data);
+        assert_eq!(paths.len(), 3);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn frame(payload: &[u8]) -> Vec<u8> {
+        let mut frame = (payload.len() as i64).to_le_bytes().to_vec();
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    fn socket_pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        (client, server)
+    }
+
+    fn test_options(name: &str) -> FileProxyOptions {
+        let data_dir = std::env::temp_dir().join(format!(
+            "syqure-forward-test-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&data_dir);
+        FileProxyOptions {
+            pid: 1,
+            data_dir,
+            job_id: Some("job".into()),
+            use_inotify: false,
+            compress_threshold: None,
+            chunk_size: Some(64),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn outbound_resumes_after_published_messages() {
+        let opts = test_options("outbound");
+        let job = opts.job_dir();
+        let dir = channel_dir(&job, 1, 2);
+        // The state file lags behind an ack the peer already wrote; a stale
+        // partial write must not count.
+        ChannelState { next_seq: 2 }
+            .save(&state_path(&job, 1, 1, 2))
+            .unwrap();
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("00000003.ack"), b"{}").unwrap();
+        std::fs::write(dir.join("00000009.request.tmp"), b"").unwrap();
+
+        let (mut sequre, mut proxy) = socket_pair();
+        let large = vec![7u8; 150];
+        write_frame(&mut sequre, &frame(b"first")).unwrap();
+        write_frame(&mut sequre, &frame(&large)).unwrap();
+        sequre.shutdown(Shutdown::Write).unwrap();
+        let stats = Mutex::new(ChannelStats::new(1, 2));
+        forward_outbound(&mut proxy, &opts, 2, &stats, None, None, None).unwrap();
+
+        assert_eq!(
+            std::fs::read(message_path(&job, 1, 2, 4)).unwrap(),
+            frame(b"first")
+        );
+        let (message, chunks) = read_message(&job, 1, 2, 5).unwrap().unwrap();
+        assert_eq!(message, frame(&large));
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(
+            ChannelState::load(&state_path(&job, 1, 1, 2))
+                .unwrap()
+                .next_seq,
+            6
+        );
+        std::fs::remove_dir_all(&opts.data_dir).unwrap();
+    }
+
+    #[test]
+    fn inbound_resumes_after_acked_messages() {
+        let opts = FileProxyOptions {
+            retention: Retention::Archive,
+            ..test_options("inbound")
+        };
+        let job = opts.job_dir();
+        let dir = channel_dir(&job, 0, 1);
+        // Message 0 was delivered and acked before a restart, but not retired.
+        std::fs::create_dir_all(&dir).unwrap();
+        for seq in 0..3u64 {
+            let payload = format!("message {}", seq);
+            write_message(&message_path(&job, 0, 1, seq), &frame(payload.as_bytes())).unwrap();
+        }
+        Ack::new(0, &frame(b"message 0"))
+            .write(&ack_path(&job, 0, 1, 0))
+            .unwrap();
+
+        let (mut sequre, proxy) = socket_pair();
+        let closed = AtomicBool::new(true);
+        let stats = Mutex::new(ChannelStats::new(1, 0));
+        forward_inbound(proxy, &opts, 0, &closed, &stats, None, None).unwrap();
+
+        let mut delivered = Vec::new();
+        while let Some(frame) = read_frame(&mut sequre).unwrap() {
+            delivered.push(frame);
+        }
+        assert_eq!(delivered, [frame(b"message 1"), frame(b"message 2")]);
+        for seq in 0..3 {
+            assert!(ack_path(&job, 0, 1, seq).exists());
+            assert!(!message_path(&job, 0, 1, seq).exists());
+        }
+        assert_eq!(
+            std::fs::read_dir(archive_dir(&job, 0, 1)).unwrap().count(),
+            3
+        );
+        assert_eq!(
+            ChannelState::load(&state_path(&job, 1, 0, 1))
+                .unwrap()
+                .next_seq,
+            3
+        );
+        std::fs::remove_dir_all(&opts.data_dir).unwrap();
+    }
+}
@@ -0,0 +1,172 @@
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+use serde::{Serialize, Serializer};
+
+pub mod bench;
+pub mod codec;
+pub mod envelope;
+pub mod file;
+pub mod netem;
+pub mod state;
+pub mod tls;
+pub mod transcript;
+pub mod watch;
+
+/// Sequre prefixes every message with its length as a native (little-endian) i64.
+pub const FRAME_HEADER_LEN: usize = 8;
+
+/// Read one `[8-byte length][data]` frame, returning it with its header intact.
+/// Returns Ok(None) when the stream closes cleanly between frames.
+pub fn read_frame(reader: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+    let Some(header) = read_header(reader)? else {
+        return Ok(None);
+    };
+    let len = frame_len(&header)?;
+    let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + len);
+    frame.extend_from_slice(&header);
+    frame.resize(FRAME_HEADER_LEN + len, 0);
+    reader.read_exact(&mut frame[FRAME_HEADER_LEN..])?;
+    Ok(Some(frame))
+}
+
+/// [`read_frame`] for frames from a peer: refuses payloads over `max_len`
+/// bytes and grows the buffer with the bytes that actually arrive, so a forged
+/// header cannot make it allocate.
+pub fn read_frame_max(reader: &mut impl Read, max_len: u64) -> io::Result<Option<Vec<u8>>> {
+    let Some(header) = read_header(reader)? else {
+        return Ok(None);
+    };
+    let len = frame_len(&header)?;
+    if len as u64 > max_len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("refusing a {} byte frame (limit {})", len, max_len),
+        ));
+    }
+    let mut frame = header.to_vec();
+    let read = reader.take(len as u64).read_to_end(&mut frame)?;
+    if read < len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(Some(frame))
+}
+
+fn read_header(reader: &mut impl Read) -> io::Result<Option<[u8; FRAME_HEADER_LEN]>> {
+    let mut header = [0u8; FRAME_HEADER_LEN];
+    let mut filled = 0;
+    while filled < FRAME_HEADER_LEN {
+        match reader.read(&mut header[filled..]) {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(Some(header))
+}
+
+/// Write a frame produced by [`read_frame`] (header included).
+pub fn write_frame(writer: &mut impl Write, frame: &[u8]) -> io::Result<()> {
+    writer.write_all(frame)?;
+    writer.flush()
+}
+
+/// Payload length encoded in a frame header.
+pub fn frame_len(header: &[u8]) -> io::Result<usize> {
+    let bytes: [u8; FRAME_HEADER_LEN] = header
+        .get(..FRAME_HEADER_LEN)
+        .and_then(|h| h.try_into().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "truncated frame header"))?;
+    usize::try_from(i64::from_le_bytes(bytes))
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "negative frame length"))
+}
+
+/// Traffic on one directed `{from}_to_{to}` channel, as seen by the sender's relay.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ChannelStats {
+    pub from: usize,
+    pub to: usize,
+    /// Bytes sent, including the 8-byte frame headers.
+    pub bytes: u64,
+    pub messages: u64,
+    /// Sends that were answered by a message from `to` before the next send.
+    pub round_trips: u64,
+    /// Wall time `from` spent waiting for those answers.
+    #[serde(rename = "wait_secs", serialize_with = "secs_f64")]
+    pub wait: Duration,
+    /// Start of the exchange still waiting for an answer.
+    #[serde(skip)]
+    awaiting_since: Option<Instant>,
+}
+
+impl ChannelStats {
+    pub fn new(from: usize, to: usize) -> Self {
+        Self {
+            from,
+            to,
+            ..Self::default()
+        }
+    }
+
+    /// Count one outbound frame.
+    pub fn record_sent(&mut self, frame_len: usize) {
+        self.bytes += frame_len as u64;
+        self.messages += 1;
+        self.awaiting_since.get_or_insert_with(Instant::now);
+    }
+
+    /// Count a frame arriving on the reverse channel.
+    pub fn record_reply(&mut self) {
+        if let Some(since) = self.awaiting_since.take() {
+            self.round_trips += 1;
+            self.wait += since.elapsed();
+        }
+    }
+}
+
+fn secs_f64<S: Serializer>(d: &Duration, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_f64(d.as_secs_f64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(payload: &[u8]) -> Vec<u8> {
+        let mut frame = (payload.len() as i64).to_le_bytes().to_vec();
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn bounded_frames_round_trip() {
+        let mut stream = [frame(b"hello"), frame(b"")].concat();
+        let mut reader = stream.as_slice();
+        assert_eq!(
+            read_frame_max(&mut reader, 16).unwrap().unwrap(),
+            frame(b"hello")
+        );
+        assert_eq!(
+            read_frame_max(&mut reader, 16).unwrap().unwrap(),
+            frame(b"")
+        );
+        assert!(read_frame_max(&mut reader, 16).unwrap().is_none());
+
+        stream.truncate(FRAME_HEADER_LEN + 2);
+        let err = read_frame_max(&mut stream.as_slice(), 16).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn oversized_and_negative_headers_are_refused() {
+        let huge = i64::MAX.to_le_bytes();
+        let err = read_frame_max(&mut &huge[..], codec::MAX_MESSAGE_LEN).unwrap_err();
+        assert!(err.to_string().contains("refusing"), "{}", err);
+        let err = read_frame_max(&mut &frame(b"too long")[..], 4).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let negative = (-1i64).to_le_bytes();
+        assert!(read_frame_max(&mut &negative[..], 16).is_err());
+    }
+}
@@ -0,0 +1,332 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use super::codec::MAX_MESSAGE_LEN;
+
+/// Emulated network conditions for the relay (`--net-profile wan.toml`).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NetProfile {
+    /// Conditions for every channel without its own entry.
+    #[serde(default)]
+    pub default: LinkProfile,
+    /// Per-channel overrides keyed by `{from}_to_{to}`; unset fields fall back
+    /// to `[default]`.
+    #[serde(default, rename = "channel")]
+    pub channels: BTreeMap<String, LinkProfile>,
+    /// Seed for jitter and loss, so runs are repeatable.
+    #[serde(default)]
+    pub seed: u64,
+}
+
+/// Conditions on one directed channel.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LinkProfile {
+    /// One-way delay added to every message.
+    pub latency_ms: Option<f64>,
+    /// Uniform random extra delay in `[0, jitter_ms]`.
+    pub jitter_ms: Option<f64>,
+    /// Link capacity; messages queue behind each other at this rate.
+    pub bandwidth_mbps: Option<f64>,
+    /// Chance a message is lost. The stream is reliable, so a loss costs a
+    /// retransmission timeout rather than the message.
+    pub loss_percent: Option<f64>,
+    /// Windows during which the link delivers nothing.
+    pub pauses: Option<Vec<Pause>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Pause {
+    /// Seconds after the relay starts.
+    pub at_secs: f64,
+    pub duration_secs: f64,
+}
+
+/// Retransmission timeout charged for a lost message (Linux's minimum RTO).
+const MIN_RTO: Duration = Duration::from_millis(200);
+
+/// Longest delay a profile may add to one message, in seconds. Keeps every
+/// delay the shaper computes far inside what `Duration` and `Instant` hold.
+const MAX_DELAY_SECS: f64 = 365.0 * 24.0 * 3600.0;
+
+impl NetProfile {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let profile: NetProfile =
+            toml::from_str(&text).with_context(|| format!("failed to parse {}", path.display()))?;
+        profile.validate()?;
+        Ok(profile)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        for (name, link) in std::iter::once(("default", &self.default))
+            .chain(self.channels.iter().map(|(k, v)| (k.as_str(), v)))
+        {
+            let pauses = link.pauses.iter().flatten();
+            let non_negative = [link.latency_ms, link.jitter_ms, link.loss_percent]
+                .into_iter()
+                .flatten()
+                .chain(pauses.flat_map(|p| [p.at_secs, p.duration_secs]));
+            if non_negative
+                .map(|v| v.is_finite() && v >= 0.0)
+                .any(|ok| !ok)
+            {
+                return Err(anyhow!(
+                    "{}: delays, loss and pauses must be finite and not negative",
+                    name
+                ));
+            }
+            if link.loss_percent.is_some_and(|p| p >= 100.0) {
+                return Err(anyhow!("{}: loss_percent must be below 100", name));
+            }
+            if link.bandwidth_mbps.is_some_and(|b| b.is_nan() || b <= 0.0) {
+                return Err(anyhow!("{}: bandwidth_mbps must be positive", name));
+            }
+            let delay_ms = link.latency_ms.unwrap_or(0.0) + link.jitter_ms.unwrap_or(0.0);
+            if delay_ms / 1000.0 > MAX_DELAY_SECS {
+                return Err(anyhow!(
+                    "{}: latency_ms and jitter_ms add up to more than a year",
+                    name
+                ));
+            }
+            let transmit_secs = |mbps: f64| MAX_MESSAGE_LEN as f64 * 8.0 / (mbps * 1e6);
+            if link
+                .bandwidth_mbps
+                .is_some_and(|b| transmit_secs(b) > MAX_DELAY_SECS)
+            {
+                return Err(anyhow!(
+                    "{}: bandwidth_mbps is too low to send a {} byte message within a year",
+                    name,
+                    MAX_MESSAGE_LEN
+                ));
+            }
+            if link
+                .pauses
+                .iter()
+                .flatten()
+                .any(|p| p.at_secs + p.duration_secs > MAX_DELAY_SECS)
+            {
+                return Err(anyhow!("{}: pauses must end within a year", name));
+            }
+        }
+        for name in self.channels.keys() {
+            let valid = name
+                .split_once("_to_")
+                .is_some_and(|(a, b)| a.parse::<usize>().is_ok() && b.parse::<usize>().is_ok());
+            if !valid {
+                return Err(anyhow!("channel '{}' is not of the form i_to_j", name));
+            }
+        }
+        Ok(())
+    }
+
+    /// Effective conditions on the `from` -> `to` channel.
+    pub fn link(&self, from: usize, to: usize) -> LinkProfile {
+        let default = &self.default;
+        let Some(link) = self.channels.get(&format!("{}_to_{}", from, to)) else {
+            return default.clone();
+        };
+        LinkProfile {
+            latency_ms: link.latency_ms.or(default.latency_ms),
+            jitter_ms: link.jitter_ms.or(default.jitter_ms),
+            bandwidth_mbps: link.bandwidth_mbps.or(default.bandwidth_mbps),
+            loss_percent: link.loss_percent.or(default.loss_percent),
+            pauses: link.pauses.clone().or_else(|| default.pauses.clone()),
+        }
+    }
+
+    /// Shaper for one directed channel; `started` anchors the pause windows.
+    pub fn shaper(&self, from: usize, to: usize, started: Instant) -> LinkShaper {
+        // Distinct stream per channel, still determined by the seed.
+        let seed =
+            self.seed ^ ((from as u64) << 32 | to as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        LinkShaper {
+            link: self.link(from, to),
+            started,
+            free_at: started,
+            last_delivery: started,
+            rng: seed | 1,
+        }
+    }
+}
+
+/// Decides when each message on a channel may be delivered. Messages still
+/// pipeline: latency delays them, only bandwidth makes them queue.
+#[derive(Debug, Clone)]
+pub struct LinkShaper {
+    link: LinkProfile,
+    started: Instant,
+    /// When the emulated link finishes sending everything queued so far.
+    free_at: Instant,
+    last_delivery: Instant,
+    rng: u64,
+}
+
+impl LinkShaper {
+    /// Delivery time of a `len`-byte message handed to the link now.
+    pub fn schedule(&mut self, len: usize) -> Instant {
+        let now = Instant::now();
+        let transmit = match self.link.bandwidth_mbps {
+            Some(mbps) => Duration::from_secs_f64(len as f64 * 8.0 / (mbps * 1e6)),
+            None => Duration::ZERO,
+        };
+        self.free_at = self.free_at.max(now) + transmit;
+
+        let mut delay = ms(self.link.latency_ms.unwrap_or(0.0));
+        if let Some(jitter) = self.link.jitter_ms {
+            delay += ms(jitter * self.next_unit());
+        }
+        if let Some(loss) = self.link.loss_percent {
+            while self.next_unit() * 100.0 < loss {
+                delay += MIN_RTO.max(ms(2.0 * self.link.latency_ms.unwrap_or(0.0)));
+            }
+        }
+
+        // A stream never reorders, whatever the jitter says.
+        let mut at = (self.free_at + delay).max(self.last_delivery);
+        for pause in self.link.pauses.iter().flatten() {
+            let start = self.started + Duration::from_secs_f64(pause.at_secs);
+            let end = start + Duration::from_secs_f64(pause.duration_secs);
+            if at >= start && at < end {
+                at = end;
+            }
+        }
+        self.last_delivery = at;
+        at
+    }
+
+    /// Uniform in [0, 1) from xorshift64*.
+    fn next_unit(&mut self) -> f64 {
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        let x = self.rng.wrapping_mul(0x2545_f491_4f6c_dd1d);
+        (x >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+fn ms(v: f64) -> Duration {
+    Duration::from_secs_f64(v / 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(text: &str) -> NetProfile {
+        let profile: NetProfile = toml::from_str(text).unwrap();
+        profile.validate().unwrap();
+        profile
+    }
+
+    #[test]
+    fn bandwidth_queues_messages_in_order() {
+        // 8 Mbit/s: a 1 MB message takes a second on the wire.
+        let started = Instant::now();
+        let mut shaper = profile("[default]\nbandwidth_mbps = 8").shaper(0, 1, started);
+        let at: Vec<Instant> = [1_000_000, 500_000, 1_000_000]
+            .into_iter()
+            .map(|len| shaper.schedule(len))
+            .collect();
+        assert!(at[0] >= started + Duration::from_secs(1));
+        assert_eq!(at[1] - at[0], Duration::from_millis(500));
+        assert_eq!(at[2] - at[1], Duration::from_secs(1));
+    }
+
+    #[test]
+    fn latency_delays_without_queueing() {
+        let mut shaper = profile("[default]\nlatency_ms = 50").shaper(0, 1, Instant::now());
+        for _ in 0..3 {
+            let before = Instant::now();
+            let at = shaper.schedule(1_000_000);
+            assert!(at >= before + Duration::from_millis(50));
+            assert!(at <= Instant::now() + Duration::from_millis(50));
+        }
+    }
+
+    #[test]
+    fn jitter_never_reorders() {
+        let started = Instant::now();
+        let mut shaper = profile("seed = 7\n[default]\njitter_ms = 100").shaper(0, 1, started);
+        let at: Vec<Instant> = (0..200).map(|_| shaper.schedule(100)).collect();
+        assert!(at.windows(2).all(|w| w[0] <= w[1]));
+        assert!(at
+            .iter()
+            .any(|&t| t > Instant::now() + Duration::from_millis(50)));
+        assert!(at
+            .iter()
+            .all(|&t| t <= Instant::now() + Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn pauses_hold_messages_until_the_window_ends() {
+        let started = Instant::now();
+        let net = profile(
+            "[default]\npauses = [{ at_secs = 0, duration_secs = 1 }, \
+             { at_secs = 60, duration_secs = 1 }]",
+        );
+        let mut shaper = net.shaper(0, 1, started);
+        assert_eq!(shaper.schedule(10), started + Duration::from_secs(1));
+        assert_eq!(shaper.schedule(10), started + Duration::from_secs(1));
+
+        let mut later = net.shaper(0, 1, started - Duration::from_secs(2));
+        assert!(later.schedule(10) <= Instant::now());
+    }
+
+    #[test]
+    fn channels_fall_back_to_default() {
+        let net = profile(
+            "[default]\nlatency_ms = 100\nbandwidth_mbps = 100\n\
+             [channel.\"0_to_1\"]\nlatency_ms = 1\n\
+             [channel.\"1_to_2\"]\npauses = []",
+        );
+        let link = net.link(0, 1);
+        assert_eq!(link.latency_ms, Some(1.0));
+        assert_eq!(link.bandwidth_mbps, Some(100.0));
+        assert_eq!(net.link(1, 0).latency_ms, Some(100.0));
+        assert_eq!(net.link(1, 2).latency_ms, Some(100.0));
+        assert!(net.link(1, 2).pauses.unwrap().is_empty());
+
+        let before = Instant::now();
+        let fast = net.shaper(0, 1, before).schedule(0);
+        let slow = net.shaper(1, 0, before).schedule(0);
+        assert!(fast < before + Duration::from_millis(100));
+        assert!(slow >= before + Duration::from_millis(100));
+    }
+
+    #[test]
+    fn validate_refuses_delays_beyond_a_duration() {
+        for (text, expected) in [
+            ("[default]\nbandwidth_mbps = 1e-300", "too low"),
+            ("[default]\nbandwidth_mbps = 0", "must be positive"),
+            ("[default]\nlatency_ms = 1e300", "more than a year"),
+            ("[channel.\"0_to_1\"]\njitter_ms = 1e20", "more than a year"),
+            (
+                "[default]\npauses = [{ at_secs = 1e300, duration_secs = 1 }]",
+                "within a year",
+            ),
+            ("[default]\nlatency_ms = -1", "not negative"),
+            ("[channel.\"0-1\"]\nlatency_ms = 1", "i_to_j"),
+        ] {
+            let net: NetProfile = toml::from_str(text).unwrap();
+            let err = net.validate().unwrap_err().to_string();
+            assert!(err.contains(expected), "{}: {}", text, err);
+        }
+
+        // The slowest and longest accepted settings still schedule.
+        let net = profile(
+            "[default]\nbandwidth_mbps = 0.002\nlatency_ms = 1e9\n\
+             pauses = [{ at_secs = 1e6, duration_secs = 1e6 }]",
+        );
+        net.shaper(0, 1, Instant::now())
+            .schedule(MAX_MESSAGE_LEN as usize);
+    }
+}
@@ -0,0 +1,395 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::file::{channel_dir, write_message};
+use crate::attest::hex;
+
+/// What happens to a message file once it has been delivered and acknowledged.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Retention {
+    /// Remove it; only the `.ack` remains.
+    #[default]
+    Delete,
+    /// Move it under `archive/{from}_to_{to}/` for auditing.
+    Archive,
+}
+
+impl fmt::Display for Retention {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Retention::Delete => write!(f, "delete"),
+            Retention::Archive => write!(f, "archive"),
+        }
+    }
+}
+
+impl FromStr for Retention {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "delete" => Ok(Retention::Delete),
+            "archive" => Ok(Retention::Archive),
+            other => Err(format!(
+                "unknown retention '{}' (expected delete or archive)",
+                other
+            )),
+        }
+    }
+}
+
+/// Folder holding one job's channels, hellos, state and archive. Jobs without an
+/// ID use the message directory itself.
+pub fn job_dir(data_dir: &Path, job_id: Option<&str>) -> PathBuf {
+    match job_id {
+        Some(id) => data_dir.join(id),
+        None => data_dir.to_path_buf(),
+    }
+}
+
+/// Job IDs name a folder in the shared directory, so keep them to a safe alphabet.
+pub fn check_job_id(id: &str) -> Result<()> {
+    let valid = !id.is_empty()
+        && !id.starts_with('.')
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "invalid job id '{}': use letters, digits, '-', '_' and '.'",
+            id
+        ))
+    }
+}
+
+/// Receipt the receiver leaves next to message `seq` once Sequre has it.
+pub fn ack_path(job_dir: &Path, from: usize, to: usize, seq: u64) -> PathBuf {
+    channel_dir(job_dir, from, to).join(format!("{:08}.ack", seq))
+}
+
+/// Where party `pid` keeps its position on the `from` -> `to` channel.
+pub fn state_path(job_dir: &Path, pid: usize, from: usize, to: usize) -> PathBuf {
+    job_dir
+        .join("state")
+        .join(format!("CP{}", pid))
+        .join(format!("{}_to_{}.json", from, to))
+}
+
+/// Where delivered messages go under [`Retention::Archive`].
+pub fn archive_dir(job_dir: &Path, from: usize, to: usize) -> PathBuf {
+    job_dir.join("archive").join(format!("{}_to_{}", from, to))
+}
+
+/// Persisted position on one directed channel: the next sequence number to
+/// publish (sender) or to deliver (receiver).
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct ChannelState {
+    pub next_seq: u64,
+}
+
+impl ChannelState {
+    /// Load a saved position; a missing file means the channel is new.
+    pub fn load(path: &Path) -> Result<Self> {
+        match std::fs::read(path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .with_context(|| format!("corrupt channel state {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("failed to read {}", path.display())),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        write_message(path, &serde_json::to_vec(self)?)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Ack {
+    pub seq: u64,
+    /// Size and SHA-256 of the `.request` file as received.
+    pub bytes: u64,
+    pub sha256: String,
+    pub delivered_at_ms: u64,
+}
+
+impl Ack {
+    pub fn new(seq: u64, message: &[u8]) -> Self {
+        let delivered_at_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        Ack {
+            seq,
+            bytes: message.len() as u64,
+            sha256: hex(&Sha256::digest(message)),
+            delivered_at_ms,
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        write_message(path, &serde_json::to_vec(self)?)
+    }
+}
+
+/// One past the highest sequence number among the files in a channel folder
+/// whose extension starts with one of `kinds` (`request`, `chunk`, `ack`).
+pub fn scan_next_seq(channel_dir: &Path, kinds: &[&str]) -> Result<u64> {
+    Ok(numbered_files(channel_dir, kinds)?
+        .into_iter()
+        .map(|(seq, _)| seq + 1)
+        .max()
+        .unwrap_or(0))
+}
+
+/// Message and chunk files numbered below `next_seq`: delivered and acked, but
+/// left behind by a proxy that stopped before applying the retention policy.
+pub fn delivered_leftovers(channel_dir: &Path, next_seq: u64) -> Result<Vec<PathBuf>> {
+    Ok(numbered_files(channel_dir, &["request", "chunk"])?
+        .into_iter()
+        .filter(|&(seq, _)| seq < next_seq)
+        .map(|(_, path)| path)
+        .collect())
+}
+
+fn numbered_files(channel_dir: &Path, kinds: &[&str]) -> Result<Vec<(u64, PathBuf)>> {
+    let entries = match std::fs::read_dir(channel_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(e).with_context(|| format!("failed to read {}", channel_dir.display()))
+        }
+    };
+    let mut files = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        let Some((seq, ext)) = name.split_once('.') else {
+            continue;
+        };
+        if ext.ends_with(".tmp") || !kinds.iter().any(|k| ext.starts_with(k)) {
+            continue;
+        }
+        if let Ok(seq) = seq.parse::<u64>() {
+            files.push((seq, entry.path()));
+        }
+    }
+    Ok(files)
+}
+
+/// Apply `retention` to the files of a delivered message.
+pub fn retire(
+    job_dir: &Path,
+    from: usize,
+    to: usize,
+    files: &[PathBuf],
+    retention: Retention,
+) -> Result<()> {
+    match retention {
+        Retention::Delete => {
+            for file in files {
+                std::fs::remove_file(file)
+                    .with_context(|| format!("failed to remove {}", file.display()))?;
+            }
+        }
+        Retention::Archive => {
+            let archive = archive_dir(job_dir, from, to);
+            std::fs::create_dir_all(&archive)?;
+            for file in files {
+                let name = file
+                    .file_name()
+                    .ok_or_else(|| anyhow!("{} has no file name", file.display()))?;
+                std::fs::rename(file, archive.join(name))
+                    .with_context(|| format!("failed to archive {}", file.display()))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Remove a finished job from a shared message directory, or only its archive.
+pub fn gc_job(data_dir: &Path, job_id: &str, archive_only: bool) -> Result<PathBuf> {
+    check_job_id(job_id)?;
+    let dir = job_dir(data_dir, Some(job_id));
+    let target = if archive_only {
+        dir.join("archive")
+    } else {
+        dir
+    };
+    if !target.exists() {
+        return Err(anyhow!("nothing to remove at {}", target.display()));
+    }
+    std::fs::remove_dir_all(&target)
+        .with_context(|| format!("failed to remove {}", target.display()))?;
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("syqure-state-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn touch(dir: &Path, names: &[&str]) {
+        std::fs::create_dir_all(dir).unwrap();
+        for name in names {
+            std::fs::write(dir.join(name), name).unwrap();
+        }
+    }
+
+    fn names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn channel_state_round_trip() {
+        let job = scratch("state");
+        let path = state_path(&job, 1, 0, 1);
+        assert_eq!(ChannelState::load(&path).unwrap().next_seq, 0);
+        ChannelState { next_seq: 42 }.save(&path).unwrap();
+        assert_eq!(ChannelState::load(&path).unwrap().next_seq, 42);
+        assert_eq!(names(path.parent().unwrap()), ["0_to_1.json"]);
+
+        std::fs::write(&path, b"{").unwrap();
+        assert!(ChannelState::load(&path).is_err());
+        std::fs::remove_dir_all(&job).unwrap();
+    }
+
+    #[test]
+    fn resume_scan_skips_partial_writes() {
+        let job = scratch("scan");
+        let dir = channel_dir(&job, 0, 1);
+        assert_eq!(scan_next_seq(&dir, &["request"]).unwrap(), 0);
+
+        touch(
+            &dir,
+            &[
+                "00000000.request",
+                "00000000.ack",
+                "00000001.request",
+                "00000001.ack",
+                "00000002.chunk0000",
+                "00000002.chunk0001",
+                "00000002.request",
+                "00000003.request.tmp",
+                "00000004.chunk0000.tmp",
+                "notes.txt",
+            ],
+        );
+        assert_eq!(
+            scan_next_seq(&dir, &["request", "chunk", "ack"]).unwrap(),
+            3
+        );
+        assert_eq!(scan_next_seq(&dir, &["ack"]).unwrap(), 2);
+
+        // A restarted receiver resumes at 2 and finds 0 and 1 still waiting
+        // for retention.
+        let mut leftovers = delivered_leftovers(&dir, 2).unwrap();
+        leftovers.sort();
+        assert_eq!(
+            leftovers,
+            [dir.join("00000000.request"), dir.join("00000001.request")]
+        );
+        assert_eq!(delivered_leftovers(&dir, 0).unwrap().len(), 0);
+        std::fs::remove_dir_all(&job).unwrap();
+    }
+
+    #[test]
+    fn retire_deletes_or_archives() {
+        let job = scratch("retire");
+        let dir = channel_dir(&job, 2, 0);
+        touch(
+            &dir,
+            &[
+                "00000000.request",
+                "00000000.ack",
+                "00000001.chunk0000",
+                "00000001.request",
+                "00000001.ack",
+            ],
+        );
+
+        retire(
+            &job,
+            2,
+            0,
+            &delivered_leftovers(&dir, 1).unwrap(),
+            Retention::Delete,
+        )
+        .unwrap();
+        assert_eq!(
+            names(&dir),
+            [
+                "00000000.ack",
+                "00000001.ack",
+                "00000001.chunk0000",
+                "00000001.request"
+            ]
+        );
+
+        retire(
+            &job,
+            2,
+            0,
+            &delivered_leftovers(&dir, 2).unwrap(),
+            Retention::Archive,
+        )
+        .unwrap();
+        assert_eq!(names(&dir), ["00000000.ack", "00000001.ack"]);
+        assert_eq!(
+            names(&archive_dir(&job, 2, 0)),
+            ["00000001.chunk0000", "00000001.request"]
+        );
+
+        // Archived files no longer count towards the next sequence number.
+        assert_eq!(scan_next_seq(&dir, &["request", "chunk"]).unwrap(), 0);
+        assert_eq!(scan_next_seq(&dir, &["ack"]).unwrap(), 2);
+        std::fs::remove_dir_all(&job).unwrap();
+    }
+
+    #[test]
+    fn gc_job_refuses_bad_ids() {
+        let data = scratch("gc");
+        let outside = data.with_extension("outside");
+        std::fs::create_dir_all(&outside).unwrap();
+        for id in ["", "..", ".hidden", "../outside", "a/b", "job id"] {
+            assert!(gc_job(&data, id, false).is_err(), "accepted '{}'", id);
+        }
+        assert!(outside.exists());
+        std::fs::remove_dir_all(&outside).unwrap();
+
+        let job = job_dir(&data, Some("job-1.a"));
+        touch(&archive_dir(&job, 0, 1), &["00000000.request"]);
+        touch(&channel_dir(&job, 0, 1), &["00000000.ack"]);
+        assert_eq!(gc_job(&data, "job-1.a", true).unwrap(), job.join("archive"));
+        assert_eq!(names(&job), ["0_to_1"]);
+        assert!(gc_job(&data, "job-1.a", true).is_err());
+        assert_eq!(gc_job(&data, "job-1.a", false).unwrap(), job);
+        assert!(!job.exists());
+        assert!(gc_job(&data, "job-1.a", false).is_err());
+        std::fs::remove_dir_all(&data).unwrap();
+    }
+}
@@ -0,0 +1,604 @@
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
+use rustls::{
+    ClientConfig, ClientConnection, DigitallySignedStruct, DistinguishedName, ServerConfig,
+    ServerConnection, SignatureScheme, StreamOwned,
+};
+use sha2::{Digest, Sha256};
+
+use super::codec::MAX_MESSAGE_LEN;
+use super::file::connect_with_retry;
+use super::{read_frame, read_frame_max, write_frame, ChannelStats, FRAME_HEADER_LEN};
+use crate::attest::hex;
+use crate::config::{channel_port, MIN_PARTIES};
+use crate::keys::create_private_file;
+use crate::preflight::{compare, Hello, LinkState, LinkStatus, PreflightReport};
+
+/// First byte on every relay connection, sent by the dialing party: which way
+/// the data flows. Each direction gets its own connection so neither needs locking.
+const TO_LISTENER: u8 = 0;
+const TO_DIALER: u8 = 1;
+
+/// Upper bound on the hello frame, as in the TCP preflight.
+const MAX_HELLO_LEN: usize = 64 * 1024;
+
+/// Handshakes in flight at once; further connections are dropped until one ends.
+const MAX_PENDING_HANDSHAKES: usize = 16;
+
+/// How often the relay listener checks for connections and its deadline.
+const ACCEPT_POLL: Duration = Duration::from_millis(50);
+
+/// Peers are identified by pinned certificate hashes, not by name.
+const SERVER_NAME: &str = "syqure-party";
+
+/// One remote party as seen by the relay.
+#[derive(Debug, Clone)]
+pub struct TlsPeer {
+    pub pid: usize,
+    /// `host:port` of the peer's relay listener.
+    pub address: String,
+    /// SHA-256 of the peer's DER certificate.
+    pub fingerprint: String,
+}
+
+/// Settings for one party's mutually authenticated TLS relay.
+#[derive(Debug, Clone)]
+pub struct TlsRelayOptions {
+    pub pid: usize,
+    pub parties: usize,
+    /// Port base of this party's Sequre process (all channels on localhost).
+    pub base_port: u16,
+    /// Port the relay accepts connections from higher-numbered peers on.
+    pub listen_port: u16,
+    /// PEM certificate and private key of this party.
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    /// Every other party.
+    pub peers: Vec<TlsPeer>,
+    /// How long to wait for peers and for Sequre.
+    pub connect_timeout: Duration,
+    /// When set, refuse peers whose hello carries a different program hash.
+    pub program_hash: Option<String>,
+}
+
+/// SHA-256 fingerprint of the first certificate in a PEM file.
+pub fn cert_fingerprint(path: &Path) -> Result<String> {
+    let cert = CertificateDer::from_pem_file(path)
+        .with_context(|| format!("failed to read certificate {}", path.display()))?;
+    Ok(fingerprint(&cert))
+}
+
+fn fingerprint(cert: &CertificateDer<'_>) -> String {
+    hex(&Sha256::digest(cert.as_ref()))
+}
+
+/// Write a self-signed certificate and key for party `pid` into `dir`, returning
+/// the certificate path and its fingerprint.
+pub fn generate_identity(dir: &Path, pid: usize) -> Result<(PathBuf, String)> {
+    std::fs::create_dir_all(dir)?;
+    let certified = rcgen::generate_simple_self_signed(vec![format!("cp{}.syqure", pid)])?;
+    let cert_path = dir.join(format!("cp{}.pem", pid));
+    let key_path = dir.join(format!("cp{}.key", pid));
+    std::fs::write(&cert_path, certified.cert.pem())?;
+    create_private_file(&key_path)?
+        .write_all(certified.key_pair.serialize_pem().as_bytes())
+        .with_context(|| format!("failed to write {}", key_path.display()))?;
+    Ok((cert_path, fingerprint(certified.cert.der())))
+}
+
+/// A TLS stream used in one direction only.
+trait Link: Read + Write + Send {
+    /// Send close_notify so the reader sees a clean end of stream.
+    fn close(&mut self);
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()>;
+}
+
+impl Link for StreamOwned<ClientConnection, TcpStream> {
+    fn close(&mut self) {
+        self.conn.send_close_notify();
+        let _ = self.flush();
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        self.sock.set_read_timeout(timeout)
+    }
+}
+
+impl Link for StreamOwned<ServerConnection, TcpStream> {
+    fn close(&mut self) {
+        self.conn.send_close_notify();
+        let _ = self.flush();
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        self.sock.set_read_timeout(timeout)
+    }
+}
+
+/// An accepted or dialed connection: peer pid, whether this side reads from
+/// it, and the stream.
+type PeerLink = (usize, bool, Box<dyn Link>);
+type LinkPair = (Box<dyn Link>, Box<dyn Link>);
+
+/// Authenticated connections to every peer, after the hello exchange.
+pub struct TlsLinks {
+    /// Peer pid -> (outbound, inbound).
+    links: BTreeMap<usize, LinkPair>,
+    pub report: PreflightReport,
+}
+
+/// Dial lower-numbered peers and accept higher-numbered ones, two connections
+/// each, then swap hellos over them.
+pub fn establish(opts: &TlsRelayOptions) -> Result<TlsLinks> {
+    if opts.parties < MIN_PARTIES || opts.pid >= opts.parties {
+        return Err(anyhow!(
+            "invalid relay: pid {} of {} parties",
+            opts.pid,
+            opts.parties
+        ));
+    }
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let certs = CertificateDer::pem_file_iter(&opts.cert)
+        .with_context(|| format!("failed to read {}", opts.cert.display()))?
+        .collect::<Result<Vec<_>, _>>()?;
+    let key = PrivateKeyDer::from_pem_file(&opts.key)
+        .with_context(|| format!("failed to read {}", opts.key.display()))?;
+    let peers: BTreeMap<usize, &TlsPeer> = opts.peers.iter().map(|p| (p.pid, p)).collect();
+    for pid in (0..opts.parties).filter(|&p| p != opts.pid) {
+        if !peers.contains_key(&pid) {
+            return Err(anyhow!("no certificate pinned for CP{}", pid));
+        }
+    }
+
+    let deadline = Instant::now() + opts.connect_timeout;
+    let (tx, rx) = mpsc::channel();
+
+    let higher: Vec<usize> = (opts.pid + 1..opts.parties).collect();
+    if !higher.is_empty() {
+        let allowed: Arc<BTreeMap<String, usize>> = Arc::new(
+            higher
+                .iter()
+                .map(|pid| (peers[pid].fingerprint.clone(), *pid))
+                .collect(),
+        );
+        let verifier = PinnedClients {
+            allowed: allowed.clone(),
+            provider: provider.clone(),
+        };
+        let config = ServerConfig::builder_with_provider(provider.clone())
+            .with_protocol_versions(&[&rustls::version::TLS13])?
+            .with_client_cert_verifier(Arc::new(verifier))
+            .with_single_cert(certs.clone(), key.clone_key())?;
+        let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, opts.listen_port))
+            .with_context(|| format!("failed to bind relay port {}", opts.listen_port))?;
+        let (config, tx, pid) = (Arc::new(config), tx.clone(), opts.pid);
+        thread::spawn(move || accept_peers(listener, config, allowed, tx, pid, deadline));
+    }
+
+    for pid in 0..opts.pid {
+        let peer = peers[&pid];
+        let verifier = PinnedServer {
+            fingerprint: peer.fingerprint.clone(),
+            pid,
+            provider: provider.clone(),
+        };
+        let config = Arc::new(
+            ClientConfig::builder_with_provider(provider.clone())
+                .with_protocol_versions(&[&rustls::version::TLS13])?
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(verifier))
+                .with_client_auth_cert(certs.clone(), key.clone_key())?,
+        );
+        for direction in [TO_LISTENER, TO_DIALER] {
+            let (config, tx, address) = (config.clone(), tx.clone(), peer.address.clone());
+            thread::spawn(move || {
+                let result = dial_peer(&address, config, direction, deadline);
+                let _ = tx.send(result.map(|link| (pid, direction == TO_DIALER, link)));
+            });
+        }
+    }
+    drop(tx);
+
+    // Each peer contributes one outbound and one inbound connection.
+    let mut outbound = BTreeMap::new();
+    let mut inbound = BTreeMap::new();
+    while outbound.len() + inbound.len() < 2 * (opts.parties - 1) {
+        let wait = deadline.saturating_duration_since(Instant::now());
+        let (pid, is_inbound, link) = match rx.recv_timeout(wait) {
+            Ok(result) => result?,
+            Err(_) => {
+                let missing: Vec<String> = (0..opts.parties)
+                    .filter(|&p| {
+                        p != opts.pid && !(outbound.contains_key(&p) && inbound.contains_key(&p))
+                    })
+                    .map(|p| format!("CP{}", p))
+                    .collect();
+                return Err(anyhow!("no TLS link to {}", missing.join(", ")));
+            }
+        };
+        if is_inbound {
+            inbound.insert(pid, link);
+        } else {
+            outbound.insert(pid, link);
+        }
+    }
+
+    let local = Hello::new(opts.pid, opts.program_hash.as_deref().unwrap_or_default());
+    let mut links = BTreeMap::new();
+    let mut statuses = Vec::new();
+    for (pid, mut out) in outbound {
+        let mut inb = inbound.remove(&pid).expect("counted above");
+        let body = serde_json::to_vec(&local)?;
+        let mut frame = (body.len() as i64).to_le_bytes().to_vec();
+        frame.extend_from_slice(&body);
+        write_frame(&mut out, &frame)?;
+        let wait = deadline.saturating_duration_since(Instant::now());
+        inb.set_read_timeout(Some(wait.max(Duration::from_millis(1))))?;
+        let state = match recv_hello(&mut inb) {
+            Ok(remote) if opts.program_hash.is_some() => compare(&local, &remote, pid),
+            Ok(remote) if remote.pid != pid => compare(&local, &remote, pid),
+            Ok(_) => LinkState::Up,
+            Err(e) => LinkState::Down(format!("{:#}", e)),
+        };
+        // Relayed traffic may pause for as long as Sequre computes.
+        out.set_read_timeout(None)?;
+        inb.set_read_timeout(None)?;
+        statuses.push(LinkStatus {
+            peer: pid,
+            address: peers[&pid].address.clone(),
+            state,
+        });
+        links.insert(pid, (out, inb));
+    }
+    Ok(TlsLinks {
+        links,
+        report: PreflightReport {
+            local,
+            links: statuses,
+        },
+    })
+}
+
+/// Relay every channel between Sequre's local sockets and the peers' TLS links.
+/// Returns the traffic per outbound channel once Sequre has closed them all.
+pub fn relay(opts: &TlsRelayOptions, links: TlsLinks) -> Result<Vec<ChannelStats>> {
+    links.report.ensure_ok()?;
+    let mut handles = Vec::new();
+    for (peer, (out, inb)) in links.links {
+        let opts = opts.clone();
+        handles.push(thread::spawn(move || relay_channel(&opts, peer, out, inb)));
+    }
+    let mut stats = Vec::new();
+    for handle in handles {
+        stats.push(
+            handle
+                .join()
+                .unwrap_or_else(|_| Err(anyhow!("relay thread panicked")))?,
+        );
+    }
+    Ok(stats)
+}
+
+/// [`establish`] then [`relay`].
+pub fn run_tls_relay(opts: &TlsRelayOptions) -> Result<Vec<ChannelStats>> {
+    let links = establish(opts)?;
+    if !links.report.ok() {
+        links.report.print_table();
+    }
+    relay(opts, links)
+}
+
+fn relay_channel(
+    opts: &TlsRelayOptions,
+    peer: usize,
+    mut out: Box<dyn Link>,
+    mut inb: Box<dyn Link>,
+) -> Result<ChannelStats> {
+    let port = channel_port(opts.base_port, opts.parties, opts.pid, peer)
+        .ok_or_else(|| anyhow!("port for channel {}<->{} overflows", opts.pid, peer))?;
+    // Same roles as the file proxy: opposite to Sequre's on this side.
+    let local = if opts.pid < peer {
+        connect_with_retry(port, opts.connect_timeout)?
+    } else {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+            .with_context(|| format!("failed to bind to port {}", port))?;
+        listener.accept()?.0
+    };
+    local.set_nodelay(true)?;
+    eprintln!(
+        "relay CP{}: channel {}<->{} on port {} (tls)",
+        opts.pid, opts.pid, peer, port
+    );
+
+    let stats = Arc::new(Mutex::new(ChannelStats::new(opts.pid, peer)));
+    let inbound = {
+        let mut writer = local.try_clone()?;
+        let stats = stats.clone();
+        thread::spawn(move || -> Result<()> {
+            while let Some(frame) = read_frame_max(&mut inb, MAX_MESSAGE_LEN)? {
+                if let Ok(mut stats) = stats.lock() {
+                    stats.record_reply();
+                }
+                write_frame(&mut writer, &frame)?;
+            }
+            let _ = writer.shutdown(Shutdown::Write);
+            Ok(())
+        })
+    };
+
+    let mut reader = local;
+    while let Some(frame) = read_frame(&mut reader)? {
+        write_frame(&mut out, &frame)?;
+        if let Ok(mut stats) = stats.lock() {
+            stats.record_sent(frame.len());
+        }
+    }
+    out.close();
+    inbound
+        .join()
+        .unwrap_or_else(|_| Err(anyhow!("inbound relay thread panicked")))?;
+    let stats = stats.lock().map_err(|_| anyhow!("stats lock poisoned"))?;
+    Ok(stats.clone())
+}
+
+fn accept_peers(
+    listener: TcpListener,
+    config: Arc<ServerConfig>,
+    allowed: Arc<BTreeMap<String, usize>>,
+    tx: mpsc::Sender<Result<PeerLink>>,
+    pid: usize,
+    deadline: Instant,
+) {
+    // Each higher peer dials once per direction. Every handshake runs on its own
+    // thread and the loop stops at the deadline, so connections that never
+    // finish their handshake cannot hold up the real peers.
+    let remaining = Arc::new(AtomicUsize::new(2 * allowed.len()));
+    let pending = Arc::new(AtomicUsize::new(0));
+    if let Err(e) = listener.set_nonblocking(true) {
+        let _ = tx.send(Err(e).context("failed to poll relay listener"));
+        return;
+    }
+    while remaining.load(Ordering::SeqCst) > 0 && Instant::now() < deadline {
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(_) => {
+                thread::sleep(ACCEPT_POLL);
+                continue;
+            }
+        };
+        if pending.load(Ordering::SeqCst) >= MAX_PENDING_HANDSHAKES {
+            eprintln!(
+                "relay CP{}: too many pending handshakes, dropping connection",
+                pid
+            );
+            continue;
+        }
+        pending.fetch_add(1, Ordering::SeqCst);
+        let (config, allowed, tx) = (config.clone(), allowed.clone(), tx.clone());
+        let (remaining, pending) = (remaining.clone(), pending.clone());
+        thread::spawn(move || {
+            let result = accept_peer(stream, config, &allowed, deadline);
+            pending.fetch_sub(1, Ordering::SeqCst);
+            match result {
+                Ok(accepted) => {
+                    remaining.fetch_sub(1, Ordering::SeqCst);
+                    let _ = tx.send(Ok(accepted));
+                }
+                // A stranger or a mismatched certificate; keep waiting for the real peer.
+                Err(e) => eprintln!("relay CP{}: rejected connection: {:#}", pid, e),
+            }
+        });
+    }
+}
+
+fn accept_peer(
+    stream: TcpStream,
+    config: Arc<ServerConfig>,
+    allowed: &BTreeMap<String, usize>,
+    deadline: Instant,
+) -> Result<PeerLink> {
+    // Accepted sockets may inherit the listener's non-blocking mode.
+    stream.set_nonblocking(false)?;
+    let wait = deadline.saturating_duration_since(Instant::now());
+    stream.set_read_timeout(Some(wait.max(Duration::from_millis(1))))?;
+    let mut tls = StreamOwned::new(ServerConnection::new(config)?, stream);
+    while tls.conn.is_handshaking() {
+        tls.conn.complete_io(&mut tls.sock)?;
+    }
+    let cert = tls
+        .conn
+        .peer_certificates()
+        .and_then(|certs| certs.first())
+        .ok_or_else(|| anyhow!("peer sent no certificate"))?;
+    // The verifier only lets pinned certificates through.
+    let pid = *allowed
+        .get(&fingerprint(cert))
+        .ok_or_else(|| anyhow!("unknown peer certificate"))?;
+    let mut direction = [0u8; 1];
+    tls.read_exact(&mut direction)?;
+    // The timeout stays until the hellos are swapped; see `establish`.
+    // TO_LISTENER means the peer sends and this side reads.
+    Ok((pid, direction[0] == TO_LISTENER, Box::new(tls)))
+}
+
+fn dial_peer(
+    address: &str,
+    config: Arc<ClientConfig>,
+    direction: u8,
+    deadline: Instant,
+) -> Result<Box<dyn Link>> {
+    let addr = address
+        .to_socket_addrs()
+        .with_context(|| format!("cannot resolve {}", address))?
+        .next()
+        .ok_or_else(|| anyhow!("cannot resolve {}", address))?;
+    let stream = loop {
+        match TcpStream::connect_timeout(&addr, Duration::from_secs(1)) {
+            Ok(stream) => break stream,
+            Err(e) if Instant::now() >= deadline => {
+                return Err(e).with_context(|| format!("relay at {} not reachable", address))
+            }
+            Err(_) => thread::sleep(Duration::from_millis(250)),
+        }
+    };
+    stream.set_nodelay(true)?;
+    // Like `accept_peer`: a peer that stops answering cannot hold the handshake
+    // or the hello past the deadline.
+    let wait = deadline.saturating_duration_since(Instant::now());
+    stream.set_read_timeout(Some(wait.max(Duration::from_millis(1))))?;
+    let name = ServerName::try_from(SERVER_NAME)?;
+    let mut tls = StreamOwned::new(ClientConnection::new(config, name)?, stream);
+    tls.write_all(&[direction])?;
+    tls.flush()?;
+    Ok(Box::new(tls))
+}
+
+fn recv_hello(link: &mut Box<dyn Link>) -> Result<Hello> {
+    let mut header = [0u8; FRAME_HEADER_LEN];
+    link.read_exact(&mut header).context("no hello from peer")?;
+    let len = super::frame_len(&header)?;
+    if len > MAX_HELLO_LEN {
+        return Err(anyhow!("peer sent a {} byte hello", len));
+    }
+    let mut body = vec![0u8; len];
+    link.read_exact(&mut body).context("truncated hello")?;
+    Ok(serde_json::from_slice(&body)?)
+}
+
+/// Accepts a server only if its certificate hash matches the pinned one.
+#[derive(Debug)]
+struct PinnedServer {
+    fingerprint: String,
+    pid: usize,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedServer {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if fingerprint(end_entity) == self.fingerprint {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General(format!(
+                "certificate does not match the one pinned for CP{}",
+                self.pid
+            )))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// Accepts a client only if its certificate hash is pinned for some peer.
+#[derive(Debug)]
+struct PinnedClients {
+    /// Fingerprint -> pid.
+    allowed: Arc<BTreeMap<String, usize>>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ClientCertVerifier for PinnedClients {
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _now: UnixTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        if self.allowed.contains_key(&fingerprint(end_entity)) {
+            Ok(ClientCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General(
+                "certificate is not pinned for any peer".to_string(),
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
@@ -0,0 +1,308 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::net::{Ipv4Addr, Shutdown, TcpListener};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use super::file::connect_with_retry;
+use super::{read_frame, write_frame};
+use crate::config::channel_port;
+use crate::keys::{create_private_dir, create_private_file};
+
+/// Name of the per-party metadata file in a transcript directory.
+const META_FILE: &str = "transcript.json";
+
+/// Directory holding party `pid`'s side of a recording.
+pub fn party_dir(transcript_dir: &Path, pid: usize) -> PathBuf {
+    transcript_dir.join(format!("CP{}", pid))
+}
+
+/// Frames of one directed channel, concatenated as they went over the wire.
+fn frames_path(dir: &Path, from: usize, to: usize) -> PathBuf {
+    dir.join(format!("{}_to_{}.bin", from, to))
+}
+
+/// One JSON line per frame: sequence number, offset into the `.bin` file and
+/// time since the recording started.
+fn index_path(dir: &Path, from: usize, to: usize) -> PathBuf {
+    dir.join(format!("{}_to_{}.jsonl", from, to))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TranscriptMeta {
+    pub pid: usize,
+    pub parties: usize,
+}
+
+/// Record `meta` for one party, creating its directory. Transcripts hold
+/// decrypted frames, i.e. secret shares, so only the owner may read them.
+pub fn create(transcript_dir: &Path, meta: &TranscriptMeta) -> Result<PathBuf> {
+    let dir = party_dir(transcript_dir, meta.pid);
+    create_private_dir(transcript_dir)?;
+    create_private_dir(&dir)?;
+    let path = dir.join(META_FILE);
+    create_private_file(&path)?
+        .write_all(&serde_json::to_vec_pretty(meta)?)
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    Ok(dir)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FrameRecord {
+    pub seq: u64,
+    pub offset: u64,
+    /// Frame length, header included.
+    pub len: u64,
+    pub elapsed_ms: u64,
+}
+
+/// Appends the frames of one directed channel to a party's transcript.
+pub struct ChannelRecorder {
+    frames: BufWriter<File>,
+    index: BufWriter<File>,
+    started: Instant,
+    seq: u64,
+    offset: u64,
+}
+
+impl ChannelRecorder {
+    pub fn create(dir: &Path, from: usize, to: usize, started: Instant) -> Result<Self> {
+        let open = |path: PathBuf| create_private_file(&path);
+        Ok(Self {
+            frames: BufWriter::new(open(frames_path(dir, from, to))?),
+            index: BufWriter::new(open(index_path(dir, from, to))?),
+            started,
+            seq: 0,
+            offset: 0,
+        })
+    }
+
+    pub fn record(&mut self, frame: &[u8]) -> Result<()> {
+        let record = FrameRecord {
+            seq: self.seq,
+            offset: self.offset,
+            len: frame.len() as u64,
+            elapsed_ms: self.started.elapsed().as_millis() as u64,
+        };
+        self.frames.write_all(frame)?;
+        serde_json::to_writer(&mut self.index, &record)?;
+        self.index.write_all(b"\n")?;
+        // Flush per frame so a crashed run still leaves a usable transcript.
+        self.frames.flush()?;
+        self.index.flush()?;
+        self.seq += 1;
+        self.offset += frame.len() as u64;
+        Ok(())
+    }
+}
+
+/// Every frame recorded on one directed channel, in order.
+pub fn read_channel(dir: &Path, from: usize, to: usize) -> Result<Vec<Vec<u8>>> {
+    let path = frames_path(dir, from, to);
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("failed to open {}", path.display())),
+    };
+    let mut reader = BufReader::new(file);
+    let mut frames = Vec::new();
+    while let Some(frame) =
+        read_frame(&mut reader).with_context(|| format!("corrupt transcript {}", path.display()))?
+    {
+        frames.push(frame);
+    }
+    Ok(frames)
+}
+
+/// Timing records of one directed channel.
+pub fn read_index(dir: &Path, from: usize, to: usize) -> Result<Vec<FrameRecord>> {
+    let path = index_path(dir, from, to);
+    let file = File::open(&path).with_context(|| format!("failed to open {}", path.display()))?;
+    BufReader::new(file)
+        .lines()
+        .map(|line| Ok(serde_json::from_str(&line?)?))
+        .collect()
+}
+
+/// Settings for replaying a recording into a single party.
+#[derive(Debug, Clone)]
+pub struct ReplayOptions {
+    /// Directory passed to `--record-transcript`.
+    pub transcript_dir: PathBuf,
+    pub pid: usize,
+    /// Port base of the replayed party's Sequre process.
+    pub base_port: u16,
+    /// How long to wait for Sequre to open its channels.
+    pub connect_timeout: Duration,
+}
+
+/// How one peer channel of the replayed party compared with the recording.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplayChannel {
+    pub peer: usize,
+    /// Recorded inbound frames handed to the party.
+    pub delivered: usize,
+    /// Outbound frames in the recording.
+    pub recorded: usize,
+    /// Outbound frames the party sent during the replay.
+    pub replayed: usize,
+    /// First outbound frame that differs from the recording.
+    pub first_divergence: Option<usize>,
+}
+
+impl ReplayChannel {
+    pub fn matches(&self) -> bool {
+        self.first_divergence.is_none() && self.recorded == self.replayed
+    }
+}
+
+/// Load a party's metadata from its transcript.
+pub fn read_meta(transcript_dir: &Path, pid: usize) -> Result<TranscriptMeta> {
+    let path = party_dir(transcript_dir, pid).join(META_FILE);
+    let bytes = std::fs::read(&path)
+        .with_context(|| format!("no transcript for CP{} at {}", pid, path.display()))?;
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+/// Stand in for every peer of `opts.pid`: feed Sequre the recorded inbound frames
+/// and capture what it sends back into `CP{pid}/replay/` for diffing. Returns
+/// once Sequre has closed every channel.
+pub fn replay_peers(opts: &ReplayOptions) -> Result<Vec<ReplayChannel>> {
+    let meta = read_meta(&opts.transcript_dir, opts.pid)?;
+    let dir = party_dir(&opts.transcript_dir, opts.pid);
+    let out_dir = dir.join("replay");
+    create_private_dir(&out_dir)?;
+
+    let mut handles = Vec::new();
+    for peer in (0..meta.parties).filter(|&p| p != opts.pid) {
+        let inbound = read_channel(&dir, peer, opts.pid)?;
+        let expected = read_channel(&dir, opts.pid, peer)?;
+        let port = channel_port(opts.base_port, meta.parties, opts.pid, peer)
+            .ok_or_else(|| anyhow!("port for channel {}<->{} overflows", opts.pid, peer))?;
+        let (pid, timeout, out_dir) = (opts.pid, opts.connect_timeout, out_dir.clone());
+        handles.push(thread::spawn(move || {
+            replay_channel(pid, peer, port, timeout, inbound, expected, &out_dir)
+        }));
+    }
+
+    handles
+        .into_iter()
+        .map(|h| {
+            h.join()
+                .unwrap_or_else(|_| Err(anyhow!("replay thread panicked")))
+        })
+        .collect()
+}
+
+fn replay_channel(
+    pid: usize,
+    peer: usize,
+    port: u16,
+    timeout: Duration,
+    inbound: Vec<Vec<u8>>,
+    expected: Vec<Vec<u8>>,
+    out_dir: &Path,
+) -> Result<ReplayChannel> {
+    // Same roles as the proxy: the peer side of Sequre's lower-pid listener connects.
+    let stream = if pid < peer {
+        connect_with_retry(port, timeout)?
+    } else {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+            .with_context(|| format!("failed to bind to port {}", port))?;
+        listener.accept()?.0
+    };
+    stream.set_nodelay(true)?;
+
+    let delivered = inbound.len();
+    let writer = {
+        let mut stream = stream.try_clone()?;
+        thread::spawn(move || -> Result<()> {
+            for frame in &inbound {
+                write_frame(&mut stream, frame)?;
+            }
+            // Nothing more was recorded; let Sequre see EOF if it asks for more.
+            let _ = stream.shutdown(Shutdown::Write);
+            Ok(())
+        })
+    };
+
+    let mut recorder = ChannelRecorder::create(out_dir, pid, peer, Instant::now())?;
+    let mut reader = stream;
+    let mut replayed = 0;
+    let mut first_divergence = None;
+    loop {
+        let frame = match read_frame(&mut reader) {
+            Ok(Some(frame)) => frame,
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("replay CP{}<->CP{}: {}", pid, peer, e);
+                break;
+            }
+        };
+        recorder.record(&frame)?;
+        if first_divergence.is_none() && expected.get(replayed) != Some(&frame) {
+            first_divergence = Some(replayed);
+        }
+        replayed += 1;
+    }
+    // Sequre may exit without draining everything it was offered.
+    let _ = writer.join();
+
+    Ok(ReplayChannel {
+        peer,
+        delivered,
+        recorded: expected.len(),
+        replayed,
+        first_divergence,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    fn mode(path: &Path) -> u32 {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::metadata(path).unwrap().permissions().mode() & 0o777
+    }
+
+    #[test]
+    fn recordings_are_private_and_read_back() {
+        let root =
+            std::env::temp_dir().join(format!("syqure-transcript-test-{}", std::process::id()));
+        let meta = TranscriptMeta { pid: 1, parties: 3 };
+        let dir = create(&root, &meta).unwrap();
+
+        let mut recorder = ChannelRecorder::create(&dir, 0, 1, Instant::now()).unwrap();
+        let mut frames = Vec::new();
+        for payload in [&b"first"[..], b"second"] {
+            let mut frame = (payload.len() as i64).to_le_bytes().to_vec();
+            frame.extend_from_slice(payload);
+            recorder.record(&frame).unwrap();
+            frames.push(frame);
+        }
+        drop(recorder);
+
+        assert_eq!(read_meta(&root, 1).unwrap().parties, 3);
+        assert_eq!(read_channel(&dir, 0, 1).unwrap(), frames);
+        assert_eq!(
+            read_index(&dir, 0, 1).unwrap()[1].offset,
+            frames[0].len() as u64
+        );
+        assert!(read_channel(&dir, 1, 0).unwrap().is_empty());
+        #[cfg(unix)]
+        {
+            assert_eq!(mode(&root), 0o700);
+            assert_eq!(mode(&dir), 0o700);
+            assert_eq!(mode(&frames_path(&dir, 0, 1)), 0o600);
+            assert_eq!(mode(&index_path(&dir, 0, 1)), 0o600);
+            assert_eq!(mode(&dir.join(META_FILE)), 0o600);
+        }
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,110 @@
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+/// Longest an inotify wait blocks, so callers still notice shutdown flags.
+const MAX_NOTIFY_WAIT: Duration = Duration::from_millis(100);
+
+/// Waits for new files in a channel directory: inotify on Linux, polling
+/// everywhere else or when inotify is unavailable.
+pub enum DirWatcher {
+    #[cfg(target_os = "linux")]
+    Inotify(inotify::Watch),
+    Poll(Duration),
+}
+
+impl DirWatcher {
+    /// Watch `dir`, falling back to polling every `poll_interval` when
+    /// `use_inotify` is off or inotify cannot be set up.
+    pub fn new(dir: &Path, use_inotify: bool, poll_interval: Duration) -> Self {
+        #[cfg(target_os = "linux")]
+        if use_inotify {
+            match inotify::Watch::new(dir) {
+                Ok(watch) => return DirWatcher::Inotify(watch),
+                Err(e) => eprintln!(
+                    "inotify unavailable for {} ({}), polling every {:?}",
+                    dir.display(),
+                    e,
+                    poll_interval
+                ),
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = (dir, use_inotify);
+        DirWatcher::Poll(poll_interval)
+    }
+
+    /// Whether this watcher is event driven.
+    pub fn is_notify(&self) -> bool {
+        !matches!(self, DirWatcher::Poll(_))
+    }
+
+    /// Block until a file may have appeared. Spurious wakeups are allowed, so
+    /// callers re-check the directory after every wait.
+    pub fn wait(&self) {
+        match self {
+            #[cfg(target_os = "linux")]
+            DirWatcher::Inotify(watch) => watch.wait(MAX_NOTIFY_WAIT),
+            DirWatcher::Poll(interval) => thread::sleep(*interval),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod inotify {
+    use std::ffi::CString;
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+    use std::time::Duration;
+
+    /// An inotify instance watching one directory for files that were
+    /// written or renamed into it.
+    pub struct Watch {
+        fd: libc::c_int,
+    }
+
+    impl Watch {
+        pub fn new(dir: &Path) -> io::Result<Self> {
+            let path = CString::new(dir.as_os_str().as_bytes())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            // SAFETY: plain syscalls on a descriptor this struct owns.
+            let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let watch = Watch { fd };
+            let mask = libc::IN_MOVED_TO | libc::IN_CLOSE_WRITE | libc::IN_CREATE;
+            if unsafe { libc::inotify_add_watch(fd, path.as_ptr(), mask) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(watch)
+        }
+
+        /// Wait up to `timeout` for events, then drain them.
+        pub fn wait(&self, timeout: Duration) {
+            let mut pollfd = libc::pollfd {
+                fd: self.fd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let ms = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+            // SAFETY: `pollfd` and `buf` outlive the calls; the fd is non-blocking.
+            unsafe {
+                if libc::poll(&mut pollfd, 1, ms) > 0 {
+                    let mut buf = [0u8; 4096];
+                    while libc::read(self.fd, buf.as_mut_ptr().cast(), buf.len()) > 0 {}
+                }
+            }
+        }
+    }
+
+    impl Drop for Watch {
+        fn drop(&mut self) {
+            // SAFETY: the fd is owned by this struct and closed exactly once.
+            unsafe {
+                libc::close(self.fd);
+            }
+        }
+    }
+}